@@ -0,0 +1,288 @@
+//! Measuring an endpoint by timing an upload and download of synthetic data, and the latency of
+//! each request the client made along the way
+//!
+use crate::stats::{format_elapsed, format_rate, format_size};
+use crate::walk::unique_key;
+use ring::digest::{Context, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// Where the benchmark object goes, under the prefix
+pub const BENCH_PREFIX: &str = "s3upload-bench/";
+
+/// How much synthetic data is made at a time
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+/// A [unique_key] under [BENCH_PREFIX], so runs at the same time don't collide
+///
+/// ```
+/// use rust_test_s3_upload::bench::bench_key;
+///
+/// assert!(bench_key("hosts/web1").starts_with("hosts/web1/s3upload-bench/"));
+/// assert!(bench_key("").starts_with("s3upload-bench/"));
+/// ```
+pub fn bench_key(prefix: &str) -> String {
+    unique_key(prefix, BENCH_PREFIX, "bin")
+}
+
+/// Bytes that look random enough not to compress, made quickly from a seed
+///
+/// The same seed always makes the same bytes.
+///
+/// ```
+/// use rust_test_s3_upload::bench::Synthetic;
+///
+/// let (mut first, mut second) = ([0u8; 100], [0u8; 100]);
+/// Synthetic::new(42).fill(&mut first);
+/// Synthetic::new(42).fill(&mut second);
+/// assert_eq!(first, second);
+/// assert!(first.iter().any(|byte| *byte != first[0]));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Synthetic {
+    state: u64,
+}
+
+impl Synthetic {
+    pub fn new(seed: u64) -> Self {
+        // xorshift never leaves zero
+        Self { state: seed.max(1) }
+    }
+
+    /// Fills `buffer` with the next bytes
+    pub fn fill(&mut self, buffer: &mut [u8]) {
+        for chunk in buffer.chunks_mut(8) {
+            // xorshift64*
+            self.state ^= self.state >> 12;
+            self.state ^= self.state << 25;
+            self.state ^= self.state >> 27;
+            let value = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d).to_le_bytes();
+            chunk.copy_from_slice(&value[..chunk.len()]);
+        }
+    }
+}
+
+/// Writes `size` bytes of [Synthetic] data with a random seed to `path`, returning their SHA256 in
+/// hex like [crate::dedupe::content_hash]
+pub async fn write_synthetic(path: &Path, size: u64) -> std::io::Result<String> {
+    let mut seed = [0u8; 8];
+    let _ = SystemRandom::new().fill(&mut seed);
+    let mut synthetic = Synthetic::new(u64::from_le_bytes(seed));
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut context = Context::new(&SHA256);
+    let mut buffer = vec![0u8; BLOCK_SIZE];
+    let mut remaining = size;
+    while remaining > 0 {
+        let block = &mut buffer[..BLOCK_SIZE.min(usize::try_from(remaining).unwrap_or(usize::MAX))];
+        synthetic.fill(block);
+        context.update(block);
+        file.write_all(block).await?;
+        remaining -= block.len() as u64;
+    }
+    file.flush().await?;
+    Ok(context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// How long each request took, by operation, from a client with [crate::S3Client::with_latencies]
+///
+/// Clones share the same samples. A request's time includes any retries, and for a GET it's until
+/// the response starts rather than the whole body.
+///
+/// ```
+/// use rust_test_s3_upload::bench::Latencies;
+/// use std::time::Duration;
+///
+/// let latencies = Latencies::default();
+/// for millis in 1..=100 {
+///     latencies.record("upload_part", Duration::from_millis(millis));
+/// }
+/// latencies.record("head_object", Duration::from_millis(7));
+///
+/// let summary = latencies.summary();
+/// assert_eq!(summary[0].operation, "head_object");
+/// let parts = &summary[1];
+/// assert_eq!((parts.count, parts.p50_ms, parts.p90_ms, parts.p99_ms, parts.max_ms), (100, 50.0, 90.0, 99.0, 100.0));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Latencies {
+    samples: Arc<Mutex<BTreeMap<&'static str, Vec<Duration>>>>,
+}
+
+impl Latencies {
+    pub fn record(&self, operation: &'static str, elapsed: Duration) {
+        let mut samples = match self.samples.lock() {
+            Ok(value) => value,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        samples.entry(operation).or_default().push(elapsed);
+    }
+
+    /// Percentiles for each operation, in order of name
+    pub fn summary(&self) -> Vec<LatencySummary> {
+        let samples = match self.samples.lock() {
+            Ok(value) => value,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        samples
+            .iter()
+            .map(|(operation, durations)| {
+                let mut sorted = durations.clone();
+                sorted.sort();
+                LatencySummary {
+                    operation,
+                    count: sorted.len(),
+                    p50_ms: as_millis(percentile(&sorted, 50.0)),
+                    p90_ms: as_millis(percentile(&sorted, 90.0)),
+                    p99_ms: as_millis(percentile(&sorted, 99.0)),
+                    max_ms: as_millis(sorted.last().copied().unwrap_or_default()),
+                }
+            })
+            .collect()
+    }
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000.0
+}
+
+/// The nearest-rank percentile of `sorted`, zero if it's empty
+///
+/// ```
+/// use rust_test_s3_upload::bench::percentile;
+/// use std::time::Duration;
+///
+/// let sorted: Vec<Duration> = [15, 20, 35, 40, 50].into_iter().map(Duration::from_millis).collect();
+/// assert_eq!(percentile(&sorted, 30.0), Duration::from_millis(20));
+/// assert_eq!(percentile(&sorted, 100.0), Duration::from_millis(50));
+/// assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+/// ```
+pub fn percentile(sorted: &[Duration], percentile: f64) -> Duration {
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    match rank.checked_sub(1) {
+        Some(index) => sorted[index.min(sorted.len() - 1)],
+        None => sorted.first().copied().unwrap_or_default(),
+    }
+}
+
+/// The latencies of one operation
+#[derive(Serialize, Debug, Clone)]
+pub struct LatencySummary {
+    pub operation: &'static str,
+    pub count: usize,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// How long one direction of the transfer took
+#[derive(Serialize, Debug, Clone)]
+pub struct Phase {
+    #[serde(rename = "duration_ms", serialize_with = "duration_millis")]
+    pub elapsed: Duration,
+    pub bytes_per_second: u64,
+}
+
+impl Phase {
+    pub fn new(size: u64, elapsed: Duration) -> Self {
+        Self {
+            elapsed,
+            bytes_per_second: (size as f64 / elapsed.as_secs_f64().max(0.001)) as u64,
+        }
+    }
+}
+
+fn duration_millis<S: serde::Serializer>(
+    elapsed: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(elapsed.as_millis() as u64)
+}
+
+/// The results of a benchmark, the detail of its JSON record
+#[derive(Serialize, Debug, Clone)]
+pub struct BenchReport {
+    pub key: String,
+    pub size: u64,
+    pub parts: u64,
+    pub part_size: u64,
+    pub concurrency: usize,
+    pub upload: Phase,
+    pub download: Phase,
+    pub latencies: Vec<LatencySummary>,
+}
+
+impl BenchReport {
+    /// Some lines for a person
+    ///
+    /// ```
+    /// use rust_test_s3_upload::bench::{BenchReport, Phase};
+    /// use std::time::Duration;
+    ///
+    /// let size = 64 << 20;
+    /// let report = BenchReport {
+    ///     key: String::from("s3upload-bench/0011223344556677.bin"),
+    ///     size,
+    ///     parts: 4,
+    ///     part_size: 16 << 20,
+    ///     concurrency: 4,
+    ///     upload: Phase::new(size, Duration::from_secs(2)),
+    ///     download: Phase::new(size, Duration::from_secs(1)),
+    ///     latencies: Vec::new(),
+    /// };
+    /// assert_eq!(
+    ///     report.describe().lines().take(2).collect::<Vec<_>>(),
+    ///     [
+    ///         "upload   64.0 MiB in 2.0s (32.0 MiB/s), 4 parts of 16.0 MiB, 4 at a time",
+    ///         "download 64.0 MiB in 1.0s (64.0 MiB/s), SHA256 matched",
+    ///     ]
+    /// );
+    /// ```
+    pub fn describe(&self) -> String {
+        let mut lines = vec![
+            format!(
+                "upload   {} in {} ({}), {} parts of {}, {} at a time",
+                format_size(self.size),
+                format_elapsed(self.upload.elapsed),
+                format_rate(self.size, self.upload.elapsed),
+                self.parts,
+                format_size(self.part_size),
+                self.concurrency
+            ),
+            format!(
+                "download {} in {} ({}), SHA256 matched",
+                format_size(self.size),
+                format_elapsed(self.download.elapsed),
+                format_rate(self.size, self.download.elapsed)
+            ),
+        ];
+        if !self.latencies.is_empty() {
+            lines.push(format!(
+                "{:<24} {:>6} {:>10} {:>10} {:>10} {:>10}",
+                "operation", "count", "p50 ms", "p90 ms", "p99 ms", "max ms"
+            ));
+        }
+        for latency in &self.latencies {
+            lines.push(format!(
+                "{:<24} {:>6} {:>10.1} {:>10.1} {:>10.1} {:>10.1}",
+                latency.operation,
+                latency.count,
+                latency.p50_ms,
+                latency.p90_ms,
+                latency.p99_ms,
+                latency.max_ms
+            ));
+        }
+        lines.join("\n")
+    }
+}
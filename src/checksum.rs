@@ -0,0 +1,374 @@
+//! Checking that what S3 stored is what we sent
+//!
+use aws_sdk_s3::types::ChecksumAlgorithm;
+use aws_smithy_types::base64;
+use crc_fast::CrcAlgorithm;
+use md5::{Digest, Md5};
+use ring::digest::{Context, SHA256};
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+/// How much of a file is hashed at a time
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// The MD5 of an upload's body, sent as `Content-MD5` so S3 rejects a body that was corrupted on
+/// the way, and compared with the etag of a single PUT afterwards
+///
+/// ```
+/// use rust_test_s3_upload::checksum::ContentMd5;
+///
+/// let md5 = ContentMd5::of(b"hello world");
+/// assert_eq!(md5.hex(), "5eb63bbbe01eeed093cb22bb8f5acdc3");
+/// assert_eq!(md5.header(), "XrY7u+Ae7tCTyyK7j1rNww==");
+/// assert!(md5.matches_etag("5EB63BBBE01EEED093CB22BB8F5ACDC3"));
+/// assert!(!md5.matches_etag("5eb63bbbe01eeed093cb22bb8f5acdc3-2"));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentMd5 {
+    digest: Vec<u8>,
+}
+
+impl ContentMd5 {
+    pub fn of(bytes: &[u8]) -> Self {
+        Self {
+            digest: Md5::digest(bytes).to_vec(),
+        }
+    }
+
+    /// Hashes a file a piece at a time, so it's never all held in memory
+    pub async fn of_file(path: &Path) -> std::io::Result<Self> {
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut hasher = Md5::new();
+        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(Self {
+            digest: hasher.finalize().to_vec(),
+        })
+    }
+
+    /// Lowercase hex, which is how S3 formats the etag of an object uploaded in one PUT
+    pub fn hex(&self) -> String {
+        self.digest
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Base64, for the `Content-MD5` header
+    pub fn header(&self) -> String {
+        base64::encode(&self.digest)
+    }
+
+    /// Compares with an etag that's had its quotes trimmed
+    pub fn matches_etag(&self, etag: &str) -> bool {
+        etag.eq_ignore_ascii_case(&self.hex())
+    }
+}
+
+/// Checks if S3 will have used the MD5 of the body as an object's etag
+///
+/// That's only the case for single PUTs that aren't encrypted with KMS or an SSE-C key, multipart
+/// etags are the MD5 of the parts' MD5s with the part count on the end.
+///
+/// ```
+/// use rust_test_s3_upload::checksum::etag_is_md5;
+///
+/// assert!(etag_is_md5("5eb63bbbe01eeed093cb22bb8f5acdc3", Some("AES256"), false));
+/// assert!(!etag_is_md5("5eb63bbbe01eeed093cb22bb8f5acdc3", Some("aws:kms"), false));
+/// assert!(!etag_is_md5("5eb63bbbe01eeed093cb22bb8f5acdc3", None, true));
+/// assert!(!etag_is_md5("5eb63bbbe01eeed093cb22bb8f5acdc3-12", None, false));
+/// ```
+pub fn etag_is_md5(etag: &str, server_side_encryption: Option<&str>, customer_key: bool) -> bool {
+    let kms = server_side_encryption.is_some_and(|value| value.starts_with("aws:kms"));
+    !etag.is_empty() && !etag.contains('-') && !kms && !customer_key
+}
+
+/// Parses a `--checksum-algorithm` value, ignoring case
+///
+/// ```
+/// use aws_sdk_s3::types::ChecksumAlgorithm;
+/// use rust_test_s3_upload::checksum::parse_checksum_algorithm;
+///
+/// assert_eq!(parse_checksum_algorithm("crc32c"), Ok(ChecksumAlgorithm::Crc32C));
+/// assert!(parse_checksum_algorithm("md5").is_err());
+/// ```
+pub fn parse_checksum_algorithm(value: &str) -> Result<ChecksumAlgorithm, String> {
+    match value.to_ascii_uppercase().as_str() {
+        "CRC32" => Ok(ChecksumAlgorithm::Crc32),
+        "CRC32C" => Ok(ChecksumAlgorithm::Crc32C),
+        "SHA256" => Ok(ChecksumAlgorithm::Sha256),
+        _ => Err(format!(
+            "{:?} isn't supported, use CRC32, CRC32C or SHA256",
+            value
+        )),
+    }
+}
+
+/// Works out one of S3's additional checksums, or an MD5 to compare with an etag, a piece at a
+/// time
+pub enum Checksummer {
+    Crc32(crc_fast::Digest),
+    Crc32C(crc_fast::Digest),
+    Sha256(Context),
+    Md5(Md5),
+}
+
+impl Checksummer {
+    /// `None` for the algorithms we don't support
+    pub fn new(algorithm: &ChecksumAlgorithm) -> Option<Self> {
+        match algorithm {
+            ChecksumAlgorithm::Crc32 => Some(Self::Crc32(crc_fast::Digest::new(
+                CrcAlgorithm::Crc32IsoHdlc,
+            ))),
+            ChecksumAlgorithm::Crc32C => Some(Self::Crc32C(crc_fast::Digest::new(
+                CrcAlgorithm::Crc32Iscsi,
+            ))),
+            ChecksumAlgorithm::Sha256 => Some(Self::Sha256(Context::new(&SHA256))),
+            _ => None,
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Crc32(digest) | Self::Crc32C(digest) => digest.update(bytes),
+            Self::Sha256(context) => context.update(bytes),
+            Self::Md5(hasher) => hasher.update(bytes),
+        }
+    }
+
+    /// Feeds a whole file through, a piece at a time
+    pub async fn update_file(&mut self, path: &Path) -> std::io::Result<()> {
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+        loop {
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                return Ok(());
+            }
+            self.update(&buffer[..read]);
+        }
+    }
+
+    /// The raw checksum, CRCs are big-endian
+    fn finish_bytes(self) -> Vec<u8> {
+        match self {
+            Self::Crc32(digest) | Self::Crc32C(digest) => {
+                (digest.finalize() as u32).to_be_bytes().to_vec()
+            }
+            Self::Sha256(context) => context.finish().as_ref().to_vec(),
+            Self::Md5(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+
+    /// The checksum base64 encoded, as S3 sends it in the `x-amz-checksum-*` headers
+    pub fn finish(self) -> String {
+        base64::encode(self.finish_bytes())
+    }
+}
+
+/// An additional checksum worked out locally, sent with a request so S3 checks the body against it
+///
+/// ```
+/// use aws_sdk_s3::types::ChecksumAlgorithm;
+/// use rust_test_s3_upload::checksum::Checksum;
+///
+/// let checksum = Checksum::of(&ChecksumAlgorithm::Crc32, b"hello world").unwrap();
+/// assert_eq!(checksum.value, "DUoRhQ==");
+/// assert_eq!(checksum.value_for(&ChecksumAlgorithm::Crc32).as_deref(), Some("DUoRhQ=="));
+/// assert_eq!(checksum.value_for(&ChecksumAlgorithm::Sha256), None);
+///
+/// let sha256 = Checksum::of(&ChecksumAlgorithm::Sha256, b"hello world").unwrap();
+/// assert_eq!(sha256.value, "uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek=");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub value: String,
+}
+
+impl Checksum {
+    pub fn of(algorithm: &ChecksumAlgorithm, bytes: &[u8]) -> Option<Self> {
+        let mut checksummer = Checksummer::new(algorithm)?;
+        checksummer.update(bytes);
+        Some(Self {
+            algorithm: algorithm.clone(),
+            value: checksummer.finish(),
+        })
+    }
+
+    /// Checksums a file a piece at a time, `None` for the algorithms we don't support
+    pub async fn of_file(
+        algorithm: &ChecksumAlgorithm,
+        path: &Path,
+    ) -> std::io::Result<Option<Self>> {
+        let Some(mut checksummer) = Checksummer::new(algorithm) else {
+            return Ok(None);
+        };
+        checksummer.update_file(path).await?;
+        Ok(Some(Self {
+            algorithm: algorithm.clone(),
+            value: checksummer.finish(),
+        }))
+    }
+
+    /// The value if this is an `algorithm` checksum, for filling in the request field for each one
+    pub fn value_for(&self, algorithm: &ChecksumAlgorithm) -> Option<String> {
+        (&self.algorithm == algorithm).then(|| self.value.clone())
+    }
+
+    /// What S3 reports for an object uploaded in parts, the checksum of the parts' raw checksums
+    /// with the part count on the end
+    ///
+    /// ```
+    /// use aws_sdk_s3::types::ChecksumAlgorithm;
+    /// use rust_test_s3_upload::checksum::Checksum;
+    ///
+    /// let parts = [
+    ///     Checksum::of(&ChecksumAlgorithm::Crc32C, b"hello ").unwrap(),
+    ///     Checksum::of(&ChecksumAlgorithm::Crc32C, b"world").unwrap(),
+    /// ];
+    /// let composite = Checksum::composite(&ChecksumAlgorithm::Crc32C, &parts).unwrap();
+    /// assert!(composite.value.ends_with("-2"));
+    /// ```
+    pub fn composite(algorithm: &ChecksumAlgorithm, parts: &[Checksum]) -> Option<Self> {
+        let mut checksummer = Checksummer::new(algorithm)?;
+        for part in parts.iter() {
+            checksummer.update(&base64::decode(&part.value).ok()?);
+        }
+        Some(Self {
+            algorithm: algorithm.clone(),
+            value: format!("{}-{}", checksummer.finish(), parts.len()),
+        })
+    }
+
+    /// Checks the checksum S3 returned, `returned` being the one for this algorithm if there was one
+    pub fn matches(&self, returned: Option<&str>) -> bool {
+        returned == Some(self.value.as_str())
+    }
+}
+
+/// Picks out the checksum a response carries, given its CRC32, CRC32C and SHA256 fields
+///
+/// S3 only returns the one the object was stored with, so the first that's set wins.
+pub fn returned_checksum(
+    crc32: Option<&str>,
+    crc32c: Option<&str>,
+    sha256: Option<&str>,
+) -> Option<Checksum> {
+    [
+        (ChecksumAlgorithm::Crc32, crc32),
+        (ChecksumAlgorithm::Crc32C, crc32c),
+        (ChecksumAlgorithm::Sha256, sha256),
+    ]
+    .into_iter()
+    .find_map(|(algorithm, value)| {
+        value.map(|value| Checksum {
+            algorithm,
+            value: value.to_string(),
+        })
+    })
+}
+
+/// What a whole download is checked against as it streams in
+///
+/// ```
+/// use aws_sdk_s3::types::ChecksumAlgorithm;
+/// use rust_test_s3_upload::checksum::{Checksum, Expected};
+///
+/// let stored = Checksum::of(&ChecksumAlgorithm::Sha256, b"hello world");
+/// let expected = Expected::pick(stored, "5eb63bbbe01eeed093cb22bb8f5acdc3", None, false).unwrap();
+/// assert_eq!(expected.name(), "SHA256 checksum");
+///
+/// let etag = Expected::pick(None, "5eb63bbbe01eeed093cb22bb8f5acdc3", None, false).unwrap();
+/// assert_eq!(etag.name(), "MD5 etag");
+/// let mut checksummer = etag.checksummer();
+/// checksummer.update(b"hello world");
+/// assert_eq!(etag.digest(checksummer), etag.value());
+///
+/// // parts, so there's nothing to compare the whole body with
+/// let composite = Checksum {
+///     algorithm: ChecksumAlgorithm::Crc32C,
+///     value: String::from("yZRlqg==-2"),
+/// };
+/// assert_eq!(Expected::pick(Some(composite), "0123456789abcdef0123456789abcdef-2", None, false), None);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expected {
+    /// The whole object checksum S3 stored
+    Checksum(Checksum),
+    /// An etag that's the MD5 of the body, in lowercase hex
+    Md5(String),
+}
+
+impl Expected {
+    /// Prefers a whole object checksum to the etag, `None` if neither can be checked
+    ///
+    /// The checksum and etag of an object uploaded in parts are worked out from the parts, which
+    /// can't be done without knowing where the parts began.
+    pub fn pick(
+        stored: Option<Checksum>,
+        etag: &str,
+        server_side_encryption: Option<&str>,
+        customer_key: bool,
+    ) -> Option<Self> {
+        match stored {
+            Some(stored)
+                if !stored.value.contains('-') && Checksummer::new(&stored.algorithm).is_some() =>
+            {
+                Some(Self::Checksum(stored))
+            }
+            _ => etag_is_md5(etag, server_side_encryption, customer_key)
+                .then(|| Self::Md5(etag.to_ascii_lowercase())),
+        }
+    }
+
+    /// What's being compared, for messages
+    pub fn name(&self) -> String {
+        match self {
+            Self::Checksum(checksum) => format!("{} checksum", checksum.algorithm.as_str()),
+            Self::Md5(_) => String::from("MD5 etag"),
+        }
+    }
+
+    pub fn value(&self) -> &str {
+        match self {
+            Self::Checksum(checksum) => &checksum.value,
+            Self::Md5(etag) => etag,
+        }
+    }
+
+    /// Something to feed the downloaded bytes through
+    pub fn checksummer(&self) -> Checksummer {
+        match self {
+            // [Expected::pick] only picks algorithms we support, anything else won't match
+            Self::Checksum(checksum) => Checksummer::new(&checksum.algorithm)
+                .unwrap_or_else(|| Checksummer::Md5(Md5::new())),
+            Self::Md5(_) => Checksummer::Md5(Md5::new()),
+        }
+    }
+
+    /// What `checksummer` worked out, in the same form as [Expected::value]
+    pub fn digest(&self, checksummer: Checksummer) -> String {
+        match self {
+            Self::Checksum(_) => checksummer.finish(),
+            Self::Md5(_) => checksummer
+                .finish_bytes()
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect(),
+        }
+    }
+
+    /// [Expected::digest] of a file, read a piece at a time
+    pub async fn digest_file(&self, path: &Path) -> std::io::Result<String> {
+        let mut checksummer = self.checksummer();
+        checksummer.update_file(path).await?;
+        Ok(self.digest(checksummer))
+    }
+}
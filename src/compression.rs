@@ -0,0 +1,255 @@
+//! Compressing uploads as they stream out and decompressing downloads as they stream in, so
+//! neither needs more than a buffer's worth of memory
+//!
+use async_compression::tokio::bufread::{GzipEncoder, ZstdEncoder};
+use async_compression::tokio::write::{GzipDecoder, ZstdDecoder};
+use async_compression::Level;
+use std::fmt;
+use std::path::Path;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, BufReader, ReadBuf};
+
+/// User metadata holding the size of a compressed upload before it was compressed
+pub const ORIGINAL_SIZE_METADATA: &str = "s3upload-original-size";
+
+/// Extensions of files that are already compressed, which `--compress auto` leaves alone
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "avif", "br", "bz2", "gif", "gz", "heic", "jpeg", "jpg", "lz", "lz4", "lzma", "mkv",
+    "mov", "mp3", "mp4", "ogg", "png", "rar", "tbz2", "tgz", "txz", "webm", "webp", "xz", "zip",
+    "zst",
+];
+
+/// The formats we can compress to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    Zstd,
+}
+
+impl Format {
+    /// Added to the key of compressed uploads, and taken off the file name when decompressing
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Gzip => ".gz",
+            Self::Zstd => ".zst",
+        }
+    }
+
+    /// The `Content-Encoding` for objects in this format
+    pub fn content_encoding(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    /// The format a key's extension says it's compressed with
+    ///
+    /// ```
+    /// use rust_test_s3_upload::compression::Format;
+    ///
+    /// assert_eq!(Format::from_key("db/2024-05-01.sql.gz"), Some(Format::Gzip));
+    /// assert_eq!(Format::from_key("db/2024-05-01.sql.zst"), Some(Format::Zstd));
+    /// assert_eq!(Format::from_key("db/2024-05-01.sql"), None);
+    /// ```
+    pub fn from_key(key: &str) -> Option<Self> {
+        [Self::Gzip, Self::Zstd]
+            .into_iter()
+            .find(|format| key.ends_with(format.extension()))
+    }
+
+    /// Wraps `writer` so what's written to it is decompressed on the way through, it has to be
+    /// shut down at the end to check the stream was complete
+    pub fn decompressor<W: AsyncWrite + Unpin>(self, writer: W) -> Decompressor<W> {
+        match self {
+            Self::Gzip => Decompressor::Gzip(GzipDecoder::new(writer)),
+            Self::Zstd => Decompressor::Zstd(ZstdDecoder::new(writer)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        })
+    }
+}
+
+/// How to compress uploads, from `--compress`
+///
+/// `auto` is gzip at the default level, except for files that look like they're already compressed.
+///
+/// ```
+/// use rust_test_s3_upload::compression::{Compression, Format};
+///
+/// let compression: Compression = "zstd:19".parse().unwrap();
+/// assert_eq!((compression.format, compression.level), (Format::Zstd, Some(19)));
+/// assert_eq!(compression.to_string(), "zstd:19");
+///
+/// let auto: Compression = "auto".parse().unwrap();
+/// assert!(auto.applies_to("db.sql"));
+/// assert!(!auto.applies_to("photos/IMG_0001.JPG"));
+/// assert!("gzip".parse::<Compression>().unwrap().applies_to("photos/IMG_0001.JPG"));
+///
+/// assert!("gzip:12".parse::<Compression>().is_err());
+/// assert!("brotli".parse::<Compression>().is_err());
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compression {
+    pub format: Format,
+    /// The format's default if not set
+    pub level: Option<i32>,
+    /// Leave files that already look compressed alone
+    pub auto: bool,
+}
+
+impl Compression {
+    /// Whether a file with this name gets compressed
+    pub fn applies_to(&self, name: &str) -> bool {
+        !self.auto || !looks_compressed(name)
+    }
+
+    /// Wraps `reader` so what's read from it comes out compressed
+    pub fn compressor<R: AsyncRead + Unpin>(&self, reader: R) -> Compressor<R> {
+        let level = self.level.map_or(Level::Default, Level::Precise);
+        let reader = BufReader::new(reader);
+        match self.format {
+            Format::Gzip => Compressor::Gzip(GzipEncoder::with_quality(reader, level)),
+            Format::Zstd => Compressor::Zstd(ZstdEncoder::with_quality(reader, level)),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let lowercase = value.to_ascii_lowercase();
+        if lowercase == "auto" {
+            return Ok(Self {
+                format: Format::Gzip,
+                level: None,
+                auto: true,
+            });
+        }
+        let (name, level) = match lowercase.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (lowercase.as_str(), None),
+        };
+        let (format, levels) = match name {
+            "gzip" => (Format::Gzip, 1..=9),
+            "zstd" => (Format::Zstd, 1..=22),
+            _ => {
+                return Err(format!(
+                    "{:?} isn't a compression, use gzip, zstd or auto, with a level like zstd:19",
+                    value
+                ))
+            }
+        };
+        let level = match level {
+            Some(level) => match level.parse::<i32>() {
+                Ok(level) if levels.contains(&level) => Some(level),
+                _ => {
+                    return Err(format!(
+                        "{:?} isn't a {} level, use {} to {}",
+                        level,
+                        format,
+                        levels.start(),
+                        levels.end()
+                    ))
+                }
+            },
+            None => None,
+        };
+        Ok(Self {
+            format,
+            level,
+            auto: false,
+        })
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.auto, self.level) {
+            (true, _) => f.write_str("auto"),
+            (false, Some(level)) => write!(f, "{}:{}", self.format, level),
+            (false, None) => write!(f, "{}", self.format),
+        }
+    }
+}
+
+/// Checks a file name's extension against the formats that are already compressed
+///
+/// ```
+/// use rust_test_s3_upload::compression::looks_compressed;
+///
+/// assert!(looks_compressed("logs/2024-05-01.tar.gz"));
+/// assert!(looks_compressed("IMG_0001.JPG"));
+/// assert!(!looks_compressed("db.sql"));
+/// assert!(!looks_compressed("README"));
+/// ```
+pub fn looks_compressed(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            COMPRESSED_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        })
+}
+
+/// A reader that compresses another as it's read, from [Compression::compressor]
+pub enum Compressor<R> {
+    Gzip(GzipEncoder<BufReader<R>>),
+    Zstd(ZstdEncoder<BufReader<R>>),
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Compressor<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Gzip(reader) => Pin::new(reader).poll_read(cx, buf),
+            Self::Zstd(reader) => Pin::new(reader).poll_read(cx, buf),
+        }
+    }
+}
+
+/// A writer that decompresses what's written before passing it on, from [Format::decompressor]
+pub enum Decompressor<W> {
+    Gzip(GzipDecoder<W>),
+    Zstd(ZstdDecoder<W>),
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Decompressor<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Self::Gzip(writer) => Pin::new(writer).poll_write(cx, buf),
+            Self::Zstd(writer) => Pin::new(writer).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Gzip(writer) => Pin::new(writer).poll_flush(cx),
+            Self::Zstd(writer) => Pin::new(writer).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Gzip(writer) => Pin::new(writer).poll_shutdown(cx),
+            Self::Zstd(writer) => Pin::new(writer).poll_shutdown(cx),
+        }
+    }
+}
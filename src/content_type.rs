@@ -0,0 +1,57 @@
+//! Guessing an upload's `Content-Type` from its file extension
+//!
+use std::path::Path;
+
+/// What S3 is told when the extension isn't one we know
+pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Looks up the MIME type for a file or key by its extension, ignoring case
+///
+/// ```
+/// use rust_test_s3_upload::content_type::{guess, DEFAULT_CONTENT_TYPE};
+///
+/// assert_eq!(guess("reports/index.HTML".as_ref()), "text/html");
+/// assert_eq!(guess("backups/db.sql.gz".as_ref()), "application/gzip");
+/// assert_eq!(guess("backups/db.dump".as_ref()), DEFAULT_CONTENT_TYPE);
+/// assert_eq!(guess("Makefile".as_ref()), DEFAULT_CONTENT_TYPE);
+/// ```
+pub fn guess(path: &Path) -> &'static str {
+    let extension = match path.extension() {
+        Some(value) => value.to_string_lossy().to_ascii_lowercase(),
+        None => return DEFAULT_CONTENT_TYPE,
+    };
+    match extension.as_str() {
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "txt" | "log" => "text/plain",
+        "csv" => "text/csv",
+        "md" => "text/markdown",
+        "xml" => "application/xml",
+        "json" => "application/json",
+        "yaml" | "yml" => "application/yaml",
+        "toml" => "application/toml",
+        "sql" => "application/sql",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "ico" => "image/vnd.microsoft.icon",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "gz" | "tgz" => "application/gzip",
+        "bz2" => "application/x-bzip2",
+        "xz" => "application/x-xz",
+        "zst" => "application/zstd",
+        "zip" => "application/zip",
+        "tar" => "application/x-tar",
+        "7z" => "application/x-7z-compressed",
+        _ => DEFAULT_CONTENT_TYPE,
+    }
+}
@@ -0,0 +1,61 @@
+//! Content addressed uploads, where a file's key is its SHA-256 so identical files from different
+//! places are only stored once, and the manifest records which file is which content key
+//!
+use ring::digest::{Context, SHA256};
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+/// Where content addressed objects go, from the root of the bucket so every upload shares them
+pub const CONTENT_PREFIX: &str = "cas/";
+
+/// How much of a file is hashed at a time
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// The key for content with this hash
+///
+/// ```
+/// use rust_test_s3_upload::dedupe::content_key;
+///
+/// assert_eq!(
+///     content_key("b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"),
+///     "cas/b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+/// );
+/// ```
+pub fn content_key(hash: &str) -> String {
+    format!("{}{}", CONTENT_PREFIX, hash)
+}
+
+/// The SHA-256 of a file in lowercase hex, read a piece at a time so it's never all in memory
+///
+/// ```
+/// use rust_test_s3_upload::dedupe::content_hash;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let path = std::env::temp_dir().join("dedupe-hash-doctest.txt");
+/// std::fs::write(&path, "hello world").unwrap();
+/// assert_eq!(
+///     content_hash(&path).await.unwrap(),
+///     "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+/// );
+/// std::fs::remove_file(&path).unwrap();
+/// # }
+/// ```
+pub async fn content_hash(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut context = Context::new(&SHA256);
+    let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        context.update(&buffer[..read]);
+    }
+    Ok(context
+        .finish()
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
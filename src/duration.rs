@@ -0,0 +1,111 @@
+//! Parsing human-friendly durations like `7d` or `12h`
+//!
+use aws_smithy_types::date_time::Format;
+use aws_smithy_types::DateTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Parses a number followed by `s`, `m`, `h`, `d` or `w`, a bare number is in seconds
+///
+/// ```
+/// use rust_test_s3_upload::duration::parse_duration;
+/// use std::time::Duration;
+///
+/// assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 86400)));
+/// assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+/// assert!(parse_duration("soon").is_err());
+/// ```
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => value.split_at(index),
+        None => (value, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("{:?} should be a number followed by s, m, h, d or w", value))?;
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "{:?} has an unknown unit, use s, m, h, d or w",
+                value
+            ))
+        }
+    };
+    number
+        .checked_mul(multiplier)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("{:?} is too long", value))
+}
+
+/// Parses an RFC 3339 timestamp, or a duration from now like `7d`
+///
+/// ```
+/// use rust_test_s3_upload::duration::parse_time;
+/// use std::time::{Duration, SystemTime};
+///
+/// assert_eq!(
+///     parse_time("2030-01-01T00:00:00Z"),
+///     Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(1_893_456_000))
+/// );
+/// assert!(parse_time("7d").unwrap() > SystemTime::now());
+/// assert!(parse_time("next tuesday").is_err());
+/// ```
+pub fn parse_time(value: &str) -> Result<SystemTime, String> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::from_str(value, Format::DateTime) {
+        return SystemTime::try_from(time).map_err(|_| format!("{:?} is out of range", value));
+    }
+    let duration = parse_duration(value).map_err(|_| {
+        format!(
+            "{:?} should be an RFC 3339 timestamp or a duration like 7d",
+            value
+        )
+    })?;
+    SystemTime::now()
+        .checked_add(duration)
+        .ok_or_else(|| format!("{:?} is too far in the future", value))
+}
+
+/// How far the local timezone is ahead of UTC at `time` in seconds, `None` if it can't be worked
+/// out, eg before 1970 or on a platform without `localtime_r`
+///
+/// ```
+/// use rust_test_s3_upload::duration::local_offset;
+/// use std::time::SystemTime;
+///
+/// if let Some(offset) = local_offset(SystemTime::now()) {
+///     assert!(offset.abs() <= 14 * 60 * 60);
+/// }
+/// ```
+pub fn local_offset(time: SystemTime) -> Option<i64> {
+    let seconds = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    local_offset_at(libc_time(seconds)?)
+}
+
+#[cfg(unix)]
+fn libc_time(seconds: u64) -> Option<libc::time_t> {
+    libc::time_t::try_from(seconds).ok()
+}
+
+#[cfg(not(unix))]
+fn libc_time(_seconds: u64) -> Option<i64> {
+    None
+}
+
+#[cfg(unix)]
+fn local_offset_at(seconds: libc::time_t) -> Option<i64> {
+    // SAFETY: tm is plain data that localtime_r fills in, it returns null if it couldn't
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::localtime_r(&seconds, &mut tm) };
+    (!result.is_null()).then_some(tm.tm_gmtoff as i64)
+}
+
+#[cfg(not(unix))]
+fn local_offset_at(_seconds: i64) -> Option<i64> {
+    None
+}
@@ -0,0 +1,327 @@
+//! Client-side encryption, so backups are encrypted before they leave the machine
+//!
+//! The format is a header of a magic string and a random salt, followed by the input in 64 KiB
+//! chunks sealed with ChaCha20-Poly1305. Each upload gets its own key, derived from the key file
+//! and the salt with HKDF-SHA256, and each chunk's nonce is its position plus a flag on the last
+//! one, so chunks can't be reordered, dropped or cut off without decryption failing.
+//!
+use crate::config::ConfigError;
+use aws_smithy_types::base64;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::hkdf;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Added to the key of encrypted uploads, and taken off the file name when decrypting
+pub const ENCRYPTED_SUFFIX: &str = ".enc";
+
+const MAGIC: &[u8; 16] = b"s3upload-enc-v1\n";
+const SALT_LENGTH: usize = 16;
+const HEADER_LENGTH: usize = MAGIC.len() + SALT_LENGTH;
+const KEY_LENGTH: usize = 32;
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LENGTH: usize = 16;
+const KEY_INFO: &[u8] = b"s3upload payload";
+
+/// A 256-bit key for client-side encryption, losing it means losing the backups
+///
+/// ```
+/// use rust_test_s3_upload::encryption::EncryptionKey;
+///
+/// let key = EncryptionKey::from_base64("MDEyMzQ1Njc4OTAxMjM0NTY3ODkwMTIzNDU2Nzg5MDE=").unwrap();
+/// // the key itself is never printed
+/// assert_eq!(format!("{:?}", key), "EncryptionKey { .. }");
+/// assert!(EncryptionKey::from_base64("dG9vIHNob3J0").is_err());
+/// ```
+#[derive(Clone)]
+pub struct EncryptionKey {
+    key: [u8; KEY_LENGTH],
+}
+
+impl EncryptionKey {
+    /// Parses a base64 encoded key
+    pub fn from_base64(value: &str) -> Result<Self, ConfigError> {
+        let bytes = base64::decode(value.trim()).map_err(|error| {
+            ConfigError::InvalidEncryptionKey(format!("it isn't valid base64: {}", error))
+        })?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Reads a key file, either the 32 raw bytes or them base64 encoded
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read(path).map_err(|error| {
+            ConfigError::InvalidEncryptionKey(format!(
+                "failed to read {}: {}",
+                path.display(),
+                error
+            ))
+        })?;
+        match contents.len() == KEY_LENGTH {
+            true => Self::from_bytes(&contents),
+            false => Self::from_base64(&String::from_utf8_lossy(&contents)),
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ConfigError> {
+        let key = <[u8; KEY_LENGTH]>::try_from(bytes).map_err(|_| {
+            ConfigError::InvalidEncryptionKey(format!(
+                "it's {} bytes but encryption keys are {}",
+                bytes.len(),
+                KEY_LENGTH
+            ))
+        })?;
+        Ok(Self { key })
+    }
+
+    /// The key for one object, from the salt in its header
+    fn object_key(&self, salt: &[u8]) -> LessSafeKey {
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(&self.key);
+        let okm = prk
+            .expand(&[KEY_INFO], &CHACHA20_POLY1305)
+            .expect("the output length is valid for HKDF-SHA256");
+        LessSafeKey::new(UnboundKey::from(okm))
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey").finish_non_exhaustive()
+    }
+}
+
+/// The nonce for chunk `counter`, the last byte marks the final chunk
+fn chunk_nonce(counter: u64, last: bool) -> Nonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[3..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = u8::from(last);
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// Encrypts a stream a piece at a time, call [Encryptor::update] with the input as it's read then
+/// [Encryptor::finish] once at the end
+///
+/// ```
+/// use rust_test_s3_upload::encryption::{Decryptor, EncryptionKey, Encryptor};
+///
+/// let key = EncryptionKey::from_base64("MDEyMzQ1Njc4OTAxMjM0NTY3ODkwMTIzNDU2Nzg5MDE=").unwrap();
+/// let input = vec![7u8; 200_000];
+/// let mut encryptor = Encryptor::new(&key);
+/// let mut encrypted = encryptor.update(&input[..1000]);
+/// encrypted.extend(encryptor.update(&input[1000..]));
+/// encrypted.extend(encryptor.finish());
+///
+/// let mut decryptor = Decryptor::new(&key);
+/// let mut output = Vec::new();
+/// for piece in encrypted.chunks(4096) {
+///     output.extend(decryptor.update(piece).unwrap());
+/// }
+/// output.extend(decryptor.finish().unwrap());
+/// assert_eq!(output, input);
+///
+/// // another key, or a truncated object, fails rather than producing garbage
+/// let other = EncryptionKey::from_base64("OTk5OTk5OTk5OTk5OTk5OTk5OTk5OTk5OTk5OTk5OTk=").unwrap();
+/// assert!(Decryptor::new(&other).update(&encrypted).is_err());
+/// let mut decryptor = Decryptor::new(&key);
+/// let cut = encrypted.len() - 100;
+/// assert!(decryptor.update(&encrypted[..cut]).is_ok());
+/// assert!(decryptor.finish().is_err());
+/// ```
+pub struct Encryptor {
+    key: LessSafeKey,
+    /// The header, until it's been returned with the first output
+    header: Option<Vec<u8>>,
+    buffer: Vec<u8>,
+    counter: u64,
+}
+
+impl Encryptor {
+    pub fn new(key: &EncryptionKey) -> Self {
+        let mut salt = [0u8; SALT_LENGTH];
+        SystemRandom::new()
+            .fill(&mut salt)
+            .expect("the system random number generator failed");
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&salt);
+        Self {
+            key: key.object_key(&salt),
+            header: Some(header),
+            buffer: Vec::new(),
+            counter: 0,
+        }
+    }
+
+    /// Takes more input, returning whatever can be encrypted so far
+    pub fn update(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut output = self.header.take().unwrap_or_default();
+        self.buffer.extend_from_slice(input);
+        // hold back a full chunk, it might turn out to be the last one
+        while self.buffer.len() > CHUNK_SIZE {
+            let rest = self.buffer.split_off(CHUNK_SIZE);
+            let chunk = std::mem::replace(&mut self.buffer, rest);
+            output.extend(self.seal(chunk, false));
+        }
+        output
+    }
+
+    /// Encrypts the rest of the input as the final chunk
+    pub fn finish(&mut self) -> Vec<u8> {
+        let mut output = self.header.take().unwrap_or_default();
+        let chunk = std::mem::take(&mut self.buffer);
+        output.extend(self.seal(chunk, true));
+        output
+    }
+
+    fn seal(&mut self, mut chunk: Vec<u8>, last: bool) -> Vec<u8> {
+        self.key
+            .seal_in_place_append_tag(chunk_nonce(self.counter, last), Aad::empty(), &mut chunk)
+            .expect("chunks are well under ChaCha20-Poly1305's length limit");
+        self.counter += 1;
+        chunk
+    }
+}
+
+/// Reverses [Encryptor], failing if the key is wrong or the data was changed or cut short
+///
+/// Nothing is returned from a chunk until it's been authenticated, but an error partway through
+/// means the output so far has to be thrown away.
+pub struct Decryptor {
+    key: EncryptionKey,
+    /// Set once the header has been read
+    object_key: Option<LessSafeKey>,
+    buffer: Vec<u8>,
+    counter: u64,
+}
+
+impl Decryptor {
+    pub fn new(key: &EncryptionKey) -> Self {
+        Self {
+            key: key.clone(),
+            object_key: None,
+            buffer: Vec::new(),
+            counter: 0,
+        }
+    }
+
+    /// Takes more of the encrypted data, returning whatever can be decrypted so far
+    pub fn update(&mut self, input: &[u8]) -> Result<Vec<u8>, String> {
+        self.buffer.extend_from_slice(input);
+        if self.object_key.is_none() {
+            if self.buffer.len() < HEADER_LENGTH {
+                return Ok(Vec::new());
+            }
+            self.read_header()?;
+        }
+        let mut output = Vec::new();
+        while self.buffer.len() > CHUNK_SIZE + TAG_LENGTH {
+            let rest = self.buffer.split_off(CHUNK_SIZE + TAG_LENGTH);
+            let chunk = std::mem::replace(&mut self.buffer, rest);
+            output.extend(self.open(chunk, false)?);
+        }
+        Ok(output)
+    }
+
+    /// Decrypts the final chunk, checking nothing is missing from the end
+    pub fn finish(&mut self) -> Result<Vec<u8>, String> {
+        if self.object_key.is_none() {
+            if self.buffer.len() < HEADER_LENGTH {
+                return Err(String::from("it's too short to have been encrypted"));
+            }
+            self.read_header()?;
+        }
+        let chunk = std::mem::take(&mut self.buffer);
+        if chunk.len() < TAG_LENGTH {
+            return Err(String::from("the encrypted data has been cut short"));
+        }
+        self.open(chunk, true)
+    }
+
+    fn read_header(&mut self) -> Result<(), String> {
+        if &self.buffer[..MAGIC.len()] != MAGIC {
+            return Err(String::from("it wasn't encrypted by s3upload"));
+        }
+        let rest = self.buffer.split_off(HEADER_LENGTH);
+        let header = std::mem::replace(&mut self.buffer, rest);
+        self.object_key = Some(self.key.object_key(&header[MAGIC.len()..]));
+        Ok(())
+    }
+
+    fn open(&mut self, mut chunk: Vec<u8>, last: bool) -> Result<Vec<u8>, String> {
+        let key = self.object_key.as_ref().expect("the header has been read");
+        let length = key
+            .open_in_place(chunk_nonce(self.counter, last), Aad::empty(), &mut chunk)
+            .map_err(|_| {
+                String::from(
+                    "decryption failed, the key is wrong or the data was changed or cut short",
+                )
+            })?
+            .len();
+        chunk.truncate(length);
+        self.counter += 1;
+        Ok(chunk)
+    }
+}
+
+/// Wraps a reader so everything read from it comes out encrypted
+pub struct EncryptingReader<R> {
+    inner: R,
+    encryptor: Encryptor,
+    /// Encrypted bytes waiting to be read, from `position` on
+    pending: Vec<u8>,
+    position: usize,
+    input: Vec<u8>,
+    done: bool,
+}
+
+impl<R> EncryptingReader<R> {
+    pub fn new(inner: R, key: &EncryptionKey) -> Self {
+        Self {
+            inner,
+            encryptor: Encryptor::new(key),
+            pending: Vec::new(),
+            position: 0,
+            input: vec![0u8; CHUNK_SIZE],
+            done: false,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for EncryptingReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.position < this.pending.len() {
+                let length = buf.remaining().min(this.pending.len() - this.position);
+                buf.put_slice(&this.pending[this.position..this.position + length]);
+                this.position += length;
+                return Poll::Ready(Ok(()));
+            }
+            if this.done {
+                return Poll::Ready(Ok(()));
+            }
+            let mut input_buf = ReadBuf::new(&mut this.input);
+            match Pin::new(&mut this.inner).poll_read(cx, &mut input_buf) {
+                Poll::Ready(Ok(())) => {
+                    let filled = input_buf.filled();
+                    this.pending = match filled.is_empty() {
+                        true => {
+                            this.done = true;
+                            this.encryptor.finish()
+                        }
+                        false => this.encryptor.update(filled),
+                    };
+                    this.position = 0;
+                }
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -0,0 +1,551 @@
+//! Errors from the S3 operations
+//!
+use crate::retry::is_transient;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::config::timeout::TimeoutConfig;
+use aws_sdk_s3::error::{BoxError, ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::primitives::ByteStreamError;
+use serde_derive::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Errors from the S3 operations, the underlying SDK error is kept as the `source()`, in a
+/// [FailedRequest] if S3 responded
+#[derive(Debug, Error)]
+pub enum S3Error {
+    #[error("The bucket has ACLs disabled, so {key} can't be uploaded with one")]
+    AclNotSupported {
+        key: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error("Access to bucket {bucket} was denied")]
+    BucketAccessDenied {
+        bucket: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error("Bucket {bucket} isn't empty")]
+    BucketNotEmpty {
+        bucket: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error("Bucket {bucket} doesn't exist")]
+    BucketNotFound {
+        bucket: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error(
+        "{key} failed its {algorithm} checksum, expected {expected} but S3 has {}",
+        .actual.as_deref().unwrap_or("none")
+    )]
+    ChecksumMismatch {
+        key: String,
+        algorithm: String,
+        expected: String,
+        actual: Option<String>,
+    },
+    #[error("Failed to connect to the endpoint or proxy during {operation}")]
+    Connection {
+        operation: &'static str,
+        #[source]
+        source: BoxError,
+    },
+    #[error(
+        "{key} should be the content of {} but it's {actual} bytes and the file is {expected}",
+        .path.display()
+    )]
+    ContentMismatch {
+        key: String,
+        path: PathBuf,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("Failed to delete {key}: {}", .message.as_deref().unwrap_or("unknown error"))]
+    DeleteFailed {
+        key: String,
+        code: Option<String>,
+        message: Option<String>,
+    },
+    #[error("Failed to download {key}")]
+    DownloadFailure {
+        key: String,
+        #[source]
+        source: ByteStreamError,
+    },
+    #[error("The SSE-C key doesn't match the one {key} was encrypted with")]
+    EncryptionKeyMismatch {
+        key: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error("{} already exists", .path.display())]
+    FileExists { path: PathBuf },
+    #[error("Failed to open {path}")]
+    FileOpen {
+        path: String,
+        #[source]
+        source: ByteStreamError,
+    },
+    #[error("Failed to write {}", .path.display())]
+    FileWrite {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Download of {key} is incomplete, expected {expected} bytes but got {actual}")]
+    IncompleteDownload {
+        key: String,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("{key} failed its integrity check: {message}")]
+    IntegrityFailure { key: String, message: String },
+    #[error("Can't presign {key}: {message}")]
+    InvalidExpiry { key: String, message: String },
+    #[error("Can't upload {key} in parts: {message}")]
+    InvalidPartSize { key: String, message: String },
+    #[error("Invalid key {key:?}: {message}")]
+    InvalidKey { key: String, message: String },
+    #[error("Invalid metadata for {key}: {message}")]
+    InvalidMetadata { key: String, message: String },
+    #[error("Invalid range for {key}: {message}")]
+    InvalidRange { key: String, message: String },
+    #[error("Invalid tags for {key}: {message}")]
+    InvalidTags { key: String, message: String },
+    #[error("{key} has no versions, it doesn't exist or the bucket has never had versioning on")]
+    NoVersions { key: String },
+    #[error("{key} isn't deleted, its latest version isn't a delete marker")]
+    NotDeleted { key: String },
+    #[error("{key} is protected by Object Lock")]
+    ObjectLocked {
+        key: String,
+        version_id: Option<String>,
+    },
+    #[error("{key} not found in the bucket")]
+    NotFound {
+        operation: &'static str,
+        key: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error("{operation} was refused for {key}, the bucket is requester pays")]
+    RequesterPays {
+        operation: &'static str,
+        key: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error("Session credentials expired during {operation}")]
+    SessionExpired {
+        operation: &'static str,
+        #[source]
+        source: BoxError,
+    },
+    #[error("Gave up on {operation} for {key} after {attempts} attempts")]
+    RetriesExhausted {
+        operation: &'static str,
+        key: String,
+        attempts: u32,
+        #[source]
+        source: Box<S3Error>,
+    },
+    #[error("Failed to read the input for {key}")]
+    Read {
+        key: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("{operation} failed for {key}: {}", .message.as_deref().unwrap_or("unknown error"))]
+    Service {
+        operation: &'static str,
+        key: String,
+        code: Option<String>,
+        message: Option<String>,
+        #[source]
+        source: BoxError,
+    },
+    #[error("{} is a symlink", .path.display())]
+    Symlink { path: PathBuf },
+    #[error("A transfer task for {key} failed")]
+    TaskFailed {
+        key: String,
+        #[source]
+        source: tokio::task::JoinError,
+    },
+    #[error("{operation} timed out: {message}")]
+    Timeout {
+        operation: &'static str,
+        message: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error(
+        "{operation} failed for {key}, S3 is busy or unavailable: {}",
+        .message.as_deref().unwrap_or("no response")
+    )]
+    Unavailable {
+        operation: &'static str,
+        key: String,
+        code: Option<String>,
+        message: Option<String>,
+        #[source]
+        source: BoxError,
+    },
+    #[error("{key} failed verification, its {attribute} is {actual} but {expected} was uploaded")]
+    VerificationFailed {
+        key: String,
+        attribute: &'static str,
+        expected: String,
+        actual: String,
+    },
+    #[error("Copied {source_key} to {key} but failed to delete {source_key}, the data is now at both keys")]
+    MoveIncomplete {
+        source_key: String,
+        key: String,
+        #[source]
+        source: Box<S3Error>,
+    },
+    #[error("Can't move {key} onto itself")]
+    MoveOntoItself { key: String },
+    #[error("{key} has no version {version_id}")]
+    VersionNotFound { key: String, version_id: String },
+    #[error(
+        "Bucket {bucket} is in {}, not {configured}",
+        .region.as_deref().unwrap_or("another region")
+    )]
+    WrongRegion {
+        bucket: String,
+        /// Where S3 said the bucket is, if it did
+        region: Option<String>,
+        configured: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error("Failed to write {key} to the output")]
+    Write {
+        key: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+impl S3Error {
+    /// Sorts an SDK error into the timeout, connection, expired session, unavailable, requester
+    /// pays, ACLs disabled, not found or service error variants
+    pub(crate) fn from_sdk<E>(
+        operation: &'static str,
+        key: &str,
+        error: SdkError<E, HttpResponse>,
+        timeout_config: Option<&TimeoutConfig>,
+    ) -> Self
+    where
+        E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+    {
+        if let Some(message) = timeout_message(&error, timeout_config) {
+            return S3Error::Timeout {
+                operation,
+                message,
+                source: boxed_sdk_error(error),
+            };
+        }
+        if is_connection_failure(&error) {
+            return S3Error::Connection {
+                operation,
+                source: boxed_sdk_error(error),
+            };
+        }
+        if is_session_expired(&error) {
+            return S3Error::SessionExpired {
+                operation,
+                source: boxed_sdk_error(error),
+            };
+        }
+        if is_transient(&error) {
+            return S3Error::Unavailable {
+                operation,
+                key: key.to_string(),
+                code: error.code().map(str::to_string),
+                message: error.message().map(str::to_string),
+                source: boxed_sdk_error(error),
+            };
+        }
+        if is_requester_pays(&error) {
+            return S3Error::RequesterPays {
+                operation,
+                key: key.to_string(),
+                source: boxed_sdk_error(error),
+            };
+        }
+        if is_acl_not_supported(&error) {
+            return S3Error::AclNotSupported {
+                key: key.to_string(),
+                source: boxed_sdk_error(error),
+            };
+        }
+        if is_not_found(&error) {
+            return S3Error::NotFound {
+                operation,
+                key: key.to_string(),
+                source: boxed_sdk_error(error),
+            };
+        }
+        S3Error::Service {
+            operation,
+            key: key.to_string(),
+            code: error.code().map(str::to_string),
+            message: error
+                .message()
+                .map(str::to_string)
+                .or_else(|| error.code().map(str::to_string)),
+            source: boxed_sdk_error(error),
+        }
+    }
+
+    /// What S3 said about the request that failed, if it got as far as a response
+    ///
+    /// Errors that gave up retrying or moving look at the error that caused them.
+    pub fn response(&self) -> Option<&ResponseDetails> {
+        match self {
+            S3Error::RetriesExhausted { source, .. } | S3Error::MoveIncomplete { source, .. } => {
+                source.response()
+            }
+            _ => std::error::Error::source(self)?
+                .downcast_ref::<FailedRequest>()
+                .map(|failed| &failed.response),
+        }
+    }
+
+    /// Whether trying again later might work, see [is_transient]
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            S3Error::Connection { .. } | S3Error::Timeout { .. } | S3Error::Unavailable { .. }
+        )
+    }
+    /// The variant's name in snake case, eg `not_found`, for output that's read by scripts
+    ///
+    /// ```
+    /// use rust_test_s3_upload::S3Error;
+    ///
+    /// let error = S3Error::NoVersions { key: String::from("db.sql") };
+    /// assert_eq!(error.kind(), "no_versions");
+    /// ```
+    pub fn kind(&self) -> &'static str {
+        match self {
+            S3Error::AclNotSupported { .. } => "acl_not_supported",
+            S3Error::BucketAccessDenied { .. } => "bucket_access_denied",
+            S3Error::BucketNotEmpty { .. } => "bucket_not_empty",
+            S3Error::BucketNotFound { .. } => "bucket_not_found",
+            S3Error::ChecksumMismatch { .. } => "checksum_mismatch",
+            S3Error::Connection { .. } => "connection",
+            S3Error::ContentMismatch { .. } => "content_mismatch",
+            S3Error::DeleteFailed { .. } => "delete_failed",
+            S3Error::DownloadFailure { .. } => "download_failure",
+            S3Error::EncryptionKeyMismatch { .. } => "encryption_key_mismatch",
+            S3Error::FileExists { .. } => "file_exists",
+            S3Error::FileOpen { .. } => "file_open",
+            S3Error::FileWrite { .. } => "file_write",
+            S3Error::IncompleteDownload { .. } => "incomplete_download",
+            S3Error::IntegrityFailure { .. } => "integrity_failure",
+            S3Error::InvalidExpiry { .. } => "invalid_expiry",
+            S3Error::InvalidPartSize { .. } => "invalid_part_size",
+            S3Error::InvalidKey { .. } => "invalid_key",
+            S3Error::InvalidMetadata { .. } => "invalid_metadata",
+            S3Error::InvalidRange { .. } => "invalid_range",
+            S3Error::InvalidTags { .. } => "invalid_tags",
+            S3Error::NoVersions { .. } => "no_versions",
+            S3Error::NotDeleted { .. } => "not_deleted",
+            S3Error::ObjectLocked { .. } => "object_locked",
+            S3Error::NotFound { .. } => "not_found",
+            S3Error::RequesterPays { .. } => "requester_pays",
+            S3Error::SessionExpired { .. } => "session_expired",
+            S3Error::RetriesExhausted { .. } => "retries_exhausted",
+            S3Error::Read { .. } => "read",
+            S3Error::Service { .. } => "service",
+            S3Error::Symlink { .. } => "symlink",
+            S3Error::TaskFailed { .. } => "task_failed",
+            S3Error::Timeout { .. } => "timeout",
+            S3Error::Unavailable { .. } => "unavailable",
+            S3Error::VerificationFailed { .. } => "verification_failed",
+            S3Error::MoveIncomplete { .. } => "move_incomplete",
+            S3Error::MoveOntoItself { .. } => "move_onto_itself",
+            S3Error::VersionNotFound { .. } => "version_not_found",
+            S3Error::WrongRegion { .. } => "wrong_region",
+            S3Error::Write { .. } => "write",
+        }
+    }
+}
+
+/// What S3 said about a failed request, the ids are what a support ticket asks for
+///
+/// ```
+/// use aws_sdk_s3::config::http::HttpResponse;
+/// use aws_sdk_s3::error::{ErrorMetadata, SdkError};
+/// use aws_sdk_s3::operation::put_object::PutObjectError;
+/// use aws_smithy_types::body::SdkBody;
+/// use rust_test_s3_upload::error::ResponseDetails;
+///
+/// let mut response = HttpResponse::new(503.try_into().unwrap(), SdkBody::empty());
+/// response.headers_mut().insert("x-amz-request-id", "4442587FB7D0A2F9");
+/// response.headers_mut().insert("x-amz-id-2", "vlR7PnpV2Ce81l0PRw6jlUpck7Jo5ZsQjryTjKlc5aLWGVHPZLj5NeC6qMa0emYBDXOo6QBU0Wo=");
+/// let error = SdkError::service_error(
+///     PutObjectError::generic(ErrorMetadata::builder().code("SlowDown").build()),
+///     response,
+/// );
+///
+/// let details = ResponseDetails::from_sdk(&error).unwrap();
+/// assert_eq!(details.request_id.as_deref(), Some("4442587FB7D0A2F9"));
+/// assert_eq!(
+///     details.to_string(),
+///     "HTTP 503, code SlowDown, request id 4442587FB7D0A2F9, extended request id vlR7PnpV2Ce81l0PRw6jlUpck7Jo5ZsQjryTjKlc5aLWGVHPZLj5NeC6qMa0emYBDXOo6QBU0Wo="
+/// );
+///
+/// // nothing came back, so there's nothing to say
+/// let timeout: SdkError<PutObjectError, HttpResponse> = SdkError::timeout_error("too slow");
+/// assert!(ResponseDetails::from_sdk(&timeout).is_none());
+/// ```
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponseDetails {
+    pub status: Option<u16>,
+    /// The S3 error code, eg `SlowDown` or `NoSuchKey`
+    pub code: Option<String>,
+    /// `x-amz-request-id`
+    pub request_id: Option<String>,
+    /// `x-amz-id-2`
+    pub extended_request_id: Option<String>,
+}
+
+impl ResponseDetails {
+    /// The details of the response to a failed request, `None` if there wasn't one
+    pub fn from_sdk<E: ProvideErrorMetadata>(error: &SdkError<E, HttpResponse>) -> Option<Self> {
+        let response = error.raw_response()?;
+        let header = |name: &str| response.headers().get(name).map(str::to_string);
+        Some(Self {
+            status: Some(response.status().as_u16()),
+            code: error.code().map(str::to_string),
+            request_id: header("x-amz-request-id"),
+            extended_request_id: header("x-amz-id-2"),
+        })
+    }
+}
+
+impl fmt::Display for ResponseDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = [
+            self.status.map(|status| format!("HTTP {}", status)),
+            self.code.as_ref().map(|code| format!("code {}", code)),
+            self.request_id
+                .as_ref()
+                .map(|id| format!("request id {}", id)),
+            self.extended_request_id
+                .as_ref()
+                .map(|id| format!("extended request id {}", id)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        f.write_str(&parts.join(", "))
+    }
+}
+
+/// An SDK error with the details of its response, as the source of an [S3Error] so
+/// [S3Error::response] can find them
+///
+/// It displays as the SDK error does and has the same sources, so error chains read as before.
+#[derive(Debug)]
+pub struct FailedRequest {
+    pub response: ResponseDetails,
+    error: BoxError,
+}
+
+impl fmt::Display for FailedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for FailedRequest {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
+}
+
+/// Boxes an SDK error as a source, with the details of its response if it got one
+pub(crate) fn boxed_sdk_error<E>(error: SdkError<E, HttpResponse>) -> BoxError
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    match ResponseDetails::from_sdk(&error) {
+        Some(response) => Box::new(FailedRequest {
+            response,
+            error: Box::new(error),
+        }),
+        None => Box::new(error),
+    }
+}
+
+/// Checks if a request failed because the (assumed role) session credentials have expired
+fn is_session_expired<E: ProvideErrorMetadata, R>(error: &SdkError<E, R>) -> bool {
+    matches!(
+        error.code(),
+        Some("ExpiredToken") | Some("ExpiredTokenException") | Some("TokenRefreshRequired")
+    )
+}
+
+/// Checks if a request was refused because the bucket is requester pays and we didn't agree to pay
+fn is_requester_pays<E: ProvideErrorMetadata, R>(error: &SdkError<E, R>) -> bool {
+    error.code() == Some("AccessDenied")
+        && error.message().is_some_and(|message| {
+            let message = message.to_ascii_lowercase();
+            message.contains("requester pays") || message.contains("requesterpays")
+        })
+}
+
+/// Checks if an ACL was sent to a bucket with Object Ownership set to BucketOwnerEnforced
+fn is_acl_not_supported<E: ProvideErrorMetadata, R>(error: &SdkError<E, R>) -> bool {
+    matches!(error.code(), Some("AccessControlListNotSupported"))
+}
+
+/// Checks if the key doesn't exist, HEAD responses have no body so they only get the generic NotFound code
+fn is_not_found<E: ProvideErrorMetadata, R>(error: &SdkError<E, R>) -> bool {
+    matches!(error.code(), Some("NoSuchKey") | Some("NotFound"))
+}
+
+fn describe_limit(limit: Option<Duration>) -> String {
+    match limit {
+        Some(value) => format!("{:?}", value),
+        None => String::from("unlimited"),
+    }
+}
+
+/// Describes which kind of timeout a request hit and what the limit was, if it timed out
+fn timeout_message<E, R>(
+    error: &SdkError<E, R>,
+    timeout_config: Option<&TimeoutConfig>,
+) -> Option<String> {
+    let operation_timeout = timeout_config.and_then(|value| value.operation_timeout());
+    let connect_timeout = timeout_config.and_then(|value| value.connect_timeout());
+    let read_timeout = timeout_config.and_then(|value| value.read_timeout());
+    match error {
+        SdkError::TimeoutError(_) => Some(format!(
+            "Operation timed out, the limit is {}",
+            describe_limit(operation_timeout)
+        )),
+        SdkError::DispatchFailure(failure) if failure.is_timeout() => Some(format!(
+            "Request timed out, the connect limit is {} and the read limit is {}",
+            describe_limit(connect_timeout),
+            describe_limit(read_timeout)
+        )),
+        _ => None,
+    }
+}
+
+/// Checks if a request never got a response, eg the endpoint or proxy refused the connection
+fn is_connection_failure<E, R>(error: &SdkError<E, R>) -> bool {
+    matches!(error, SdkError::DispatchFailure(_))
+}
@@ -0,0 +1,155 @@
+//! Exit codes, so a script or cron job can tell what kind of failure ended a run
+//!
+//! | Code | Meaning |
+//! | ---- | ------- |
+//! | 0 | Everything worked |
+//! | 1 | Something else failed, or failures of more than one kind |
+//! | 2 | Bad arguments or config |
+//! | 3 | The credentials were refused or have expired |
+//! | 4 | The bucket, object or version wasn't found |
+//! | 5 | A transfer failed, eg a timeout, dropped connection or a file that couldn't be read or written |
+//! | 6 | Something didn't match what was expected, eg a checksum or `verify` finding differences |
+//!
+use crate::error::S3Error;
+
+pub const SUCCESS: i32 = 0;
+pub const FAILURE: i32 = 1;
+pub const CONFIG: i32 = 2;
+pub const AUTH: i32 = 3;
+pub const NOT_FOUND: i32 = 4;
+pub const TRANSFER: i32 = 5;
+pub const VERIFICATION: i32 = 6;
+
+/// Service error codes that mean the credentials aren't allowed to do what was asked
+const AUTH_CODES: &[&str] = &[
+    "AccessDenied",
+    "AllAccessDisabled",
+    "InvalidAccessKeyId",
+    "InvalidToken",
+    "SignatureDoesNotMatch",
+];
+
+/// The exit code for a run that ended with `error`
+///
+/// ```
+/// use rust_test_s3_upload::exit;
+/// use rust_test_s3_upload::S3Error;
+///
+/// let not_found = S3Error::NotFound {
+///     operation: "head_object",
+///     key: String::from("db.sql"),
+///     source: "NoSuchKey".into(),
+/// };
+/// assert_eq!(exit::code(&not_found), exit::NOT_FOUND);
+///
+/// let denied = S3Error::Service {
+///     operation: "put_object",
+///     key: String::from("db.sql"),
+///     code: Some(String::from("AccessDenied")),
+///     message: None,
+///     source: "AccessDenied".into(),
+/// };
+/// assert_eq!(exit::code(&denied), exit::AUTH);
+///
+/// let timeout = S3Error::Timeout {
+///     operation: "put_object",
+///     message: String::from("operation timeout (all attempts including retries) occurred after 30s"),
+///     source: "timeout".into(),
+/// };
+/// assert_eq!(exit::code(&timeout), exit::TRANSFER);
+///
+/// // giving up on retries is still the kind of failure that was being retried
+/// let exhausted = S3Error::RetriesExhausted {
+///     operation: "put_object",
+///     key: String::from("db.sql"),
+///     attempts: 3,
+///     source: Box::new(timeout),
+/// };
+/// assert_eq!(exit::code(&exhausted), exit::TRANSFER);
+///
+/// let mismatch = S3Error::VerificationFailed {
+///     key: String::from("db.sql"),
+///     attribute: "size",
+///     expected: String::from("1024"),
+///     actual: String::from("512"),
+/// };
+/// assert_eq!(exit::code(&mismatch), exit::VERIFICATION);
+///
+/// let range = S3Error::InvalidRange {
+///     key: String::from("db.sql"),
+///     message: String::from("starts after the end of the object"),
+/// };
+/// assert_eq!(exit::code(&range), exit::CONFIG);
+///
+/// let locked = S3Error::ObjectLocked { key: String::from("db.sql"), version_id: None };
+/// assert_eq!(exit::code(&locked), exit::FAILURE);
+/// ```
+pub fn code(error: &S3Error) -> i32 {
+    match error {
+        S3Error::BucketAccessDenied { .. }
+        | S3Error::RequesterPays { .. }
+        | S3Error::SessionExpired { .. } => AUTH,
+        S3Error::Service {
+            code: Some(code), ..
+        } if AUTH_CODES.contains(&code.as_str()) => AUTH,
+        S3Error::BucketNotFound { .. }
+        | S3Error::NotFound { .. }
+        | S3Error::NoVersions { .. }
+        | S3Error::VersionNotFound { .. } => NOT_FOUND,
+        S3Error::Connection { .. }
+        | S3Error::DownloadFailure { .. }
+        | S3Error::FileOpen { .. }
+        | S3Error::FileWrite { .. }
+        | S3Error::IncompleteDownload { .. }
+        | S3Error::Read { .. }
+        | S3Error::TaskFailed { .. }
+        | S3Error::Timeout { .. }
+        | S3Error::Unavailable { .. }
+        | S3Error::Write { .. } => TRANSFER,
+        S3Error::ChecksumMismatch { .. }
+        | S3Error::ContentMismatch { .. }
+        | S3Error::EncryptionKeyMismatch { .. }
+        | S3Error::IntegrityFailure { .. }
+        | S3Error::VerificationFailed { .. } => VERIFICATION,
+        S3Error::AclNotSupported { .. }
+        | S3Error::FileExists { .. }
+        | S3Error::InvalidExpiry { .. }
+        | S3Error::InvalidKey { .. }
+        | S3Error::InvalidMetadata { .. }
+        | S3Error::InvalidPartSize { .. }
+        | S3Error::InvalidRange { .. }
+        | S3Error::InvalidTags { .. }
+        | S3Error::MoveOntoItself { .. }
+        | S3Error::Symlink { .. }
+        | S3Error::WrongRegion { .. } => CONFIG,
+        S3Error::RetriesExhausted { source, .. } | S3Error::MoveIncomplete { source, .. } => {
+            code(source)
+        }
+        S3Error::BucketNotEmpty { .. }
+        | S3Error::DeleteFailed { .. }
+        | S3Error::NotDeleted { .. }
+        | S3Error::ObjectLocked { .. }
+        | S3Error::Service { .. } => FAILURE,
+    }
+}
+
+/// The exit code for a run where several things failed, theirs if they all agree, otherwise
+/// [FAILURE]
+///
+/// ```
+/// use rust_test_s3_upload::exit;
+///
+/// assert_eq!(exit::combine([exit::TRANSFER, exit::TRANSFER]), exit::TRANSFER);
+/// assert_eq!(exit::combine([exit::TRANSFER, exit::NOT_FOUND]), exit::FAILURE);
+/// assert_eq!(exit::combine([]), exit::SUCCESS);
+/// ```
+pub fn combine(codes: impl IntoIterator<Item = i32>) -> i32 {
+    let mut codes = codes.into_iter();
+    let Some(first) = codes.next() else {
+        return SUCCESS;
+    };
+    match codes.all(|code| code == first) {
+        true => first,
+        false => FAILURE,
+    }
+}
@@ -0,0 +1,258 @@
+//! Include and exclude glob patterns for choosing which files to transfer, and filters on the
+//! age, size and key of listed objects
+//!
+use crate::config::ConfigError;
+use aws_sdk_s3::types::Object;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex_lite::Regex;
+use std::time::{Duration, SystemTime};
+
+/// Globs matched against a relative path, patterns without a `/` match the file name at any depth
+#[derive(Clone, Debug)]
+struct PatternSet {
+    paths: GlobSet,
+    names: GlobSet,
+    count: usize,
+}
+
+impl PatternSet {
+    fn new(patterns: &[String], case_insensitive: bool) -> Result<Self, ConfigError> {
+        let mut paths = GlobSetBuilder::new();
+        let mut names = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .case_insensitive(case_insensitive)
+                .build()
+                .map_err(|error| ConfigError::InvalidPattern(pattern.clone(), error.to_string()))?;
+            match pattern.contains('/') {
+                true => paths.add(glob),
+                false => names.add(glob),
+            };
+        }
+        let build = |builder: GlobSetBuilder| {
+            builder
+                .build()
+                .map_err(|error| ConfigError::InvalidPattern(patterns.join(" "), error.to_string()))
+        };
+        Ok(Self {
+            paths: build(paths)?,
+            names: build(names)?,
+            count: patterns.len(),
+        })
+    }
+
+    fn is_match(&self, path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.paths.is_match(path) || self.names.is_match(name)
+    }
+}
+
+/// Decides which paths to transfer, excludes win over includes and no includes means everything
+///
+/// Paths are relative, with `/` separators. `*` doesn't cross directories, `**` does.
+///
+/// ```
+/// use rust_test_s3_upload::filter::PathFilter;
+///
+/// let filter = PathFilter::new(
+///     &[String::from("*.sql.gz"), String::from("logs/**/*.log")],
+///     &[String::from("*.tmp"), String::from("old/**")],
+///     false,
+/// )
+/// .unwrap();
+///
+/// // name-only patterns match in nested directories
+/// assert!(filter.matches("db/2024/dump.sql.gz"));
+/// assert!(!filter.matches("db/2024/dump.sql"));
+/// // ** spans any number of directories, * only one name
+/// assert!(filter.matches("logs/app/2024/05/app.log"));
+/// assert!(!filter.matches("other/app.log"));
+/// // excludes take precedence
+/// assert!(!filter.matches("old/dump.sql.gz"));
+/// assert!(!filter.matches("logs/app/upload.tmp"));
+/// // matching is case sensitive unless asked otherwise
+/// assert!(!filter.matches("DUMP.SQL.GZ"));
+///
+/// let filter = PathFilter::new(&[String::from("*.sql.gz")], &[], true).unwrap();
+/// assert!(filter.matches("DUMP.SQL.GZ"));
+/// assert!(PathFilter::default().matches("anything/at/all"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct PathFilter {
+    includes: PatternSet,
+    excludes: PatternSet,
+}
+
+impl PathFilter {
+    pub fn new(
+        includes: &[String],
+        excludes: &[String],
+        case_insensitive: bool,
+    ) -> Result<Self, ConfigError> {
+        Ok(Self {
+            includes: PatternSet::new(includes, case_insensitive)?,
+            excludes: PatternSet::new(excludes, case_insensitive)?,
+        })
+    }
+
+    /// Checks if a relative path should be transferred
+    pub fn matches(&self, path: &str) -> bool {
+        if self.excludes.is_match(path) {
+            return false;
+        }
+        self.includes.count == 0 || self.includes.is_match(path)
+    }
+}
+
+impl Default for PathFilter {
+    fn default() -> Self {
+        // no patterns can't fail to build
+        #[allow(clippy::unwrap_used)]
+        Self::new(&[], &[], false).unwrap()
+    }
+}
+
+/// Parses a size in bytes, with an optional K, M, G or T suffix in powers of 1024
+///
+/// ```
+/// use rust_test_s3_upload::filter::parse_size;
+///
+/// assert_eq!(parse_size("512"), Ok(512));
+/// assert_eq!(parse_size("100M"), Ok(100 * 1024 * 1024));
+/// assert_eq!(parse_size("2g"), Ok(2 * 1024 * 1024 * 1024));
+/// assert_eq!(parse_size("1T"), Ok(1024 * 1024 * 1024 * 1024));
+/// assert!(parse_size("big").is_err());
+/// assert!(parse_size("M").is_err());
+/// ```
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, multiplier) = match value.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&value[..value.len() - 1], 1u64 << 10),
+        Some('M') => (&value[..value.len() - 1], 1 << 20),
+        Some('G') => (&value[..value.len() - 1], 1 << 30),
+        Some('T') => (&value[..value.len() - 1], 1 << 40),
+        _ => (value, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| {
+            format!(
+                "{:?} should be a size in bytes, with an optional K, M, G or T suffix like 100M",
+                value
+            )
+        })
+}
+
+/// Chooses listed objects by age, size and a regex on the key, every filter that's set has to
+/// match and none set matches everything
+///
+/// Objects without a last-modified time never match an age filter, so they're never picked as
+/// old enough to delete.
+///
+/// ```
+/// use rust_test_s3_upload::duration::parse_duration;
+/// use rust_test_s3_upload::filter::{parse_size, ObjectFilter};
+/// use std::time::{Duration, SystemTime};
+///
+/// let filter = ObjectFilter {
+///     older_than: Some(parse_duration("90d").unwrap()),
+///     min_size: Some(parse_size("100M").unwrap()),
+///     ..Default::default()
+/// }
+/// .with_name_regex(r"db-.*\.sql\.gz$")
+/// .unwrap();
+///
+/// let now = SystemTime::now();
+/// let old = Some(now - parse_duration("100d").unwrap());
+/// let recent = Some(now - parse_duration("12h").unwrap());
+/// let big = 200 * 1024 * 1024;
+/// assert!(filter.matches("hosts/web1/db-2024.sql.gz", big, old, now));
+/// assert!(!filter.matches("hosts/web1/db-2024.sql.gz", big, recent, now));
+/// assert!(!filter.matches("hosts/web1/db-2024.sql.gz", 1024, old, now));
+/// assert!(!filter.matches("hosts/web1/db-2024.sql", big, old, now));
+/// assert!(!filter.matches("hosts/web1/db-2024.sql.gz", big, None, now));
+///
+/// let newer = ObjectFilter {
+///     newer_than: Some(Duration::from_secs(24 * 60 * 60)),
+///     ..Default::default()
+/// };
+/// assert!(newer.matches("db.sql", 0, recent, now));
+/// assert!(!newer.matches("db.sql", 0, old, now));
+/// assert!(ObjectFilter::default().is_empty());
+///
+/// let error = ObjectFilter::default().with_name_regex("db-(").unwrap_err();
+/// assert!(error.to_string().starts_with("Pattern \"db-(\" is invalid"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ObjectFilter {
+    /// Last modified longer ago than this
+    pub older_than: Option<Duration>,
+    /// Last modified more recently than this
+    pub newer_than: Option<Duration>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Found anywhere in the key unless it's anchored
+    pub name_regex: Option<Regex>,
+}
+
+impl ObjectFilter {
+    /// Sets the regex the key has to match
+    pub fn with_name_regex(mut self, pattern: &str) -> Result<Self, ConfigError> {
+        let regex = Regex::new(pattern)
+            .map_err(|error| ConfigError::InvalidPattern(pattern.to_string(), error.to_string()))?;
+        self.name_regex = Some(regex);
+        Ok(self)
+    }
+
+    /// Checks if nothing is being filtered
+    pub fn is_empty(&self) -> bool {
+        self.older_than.is_none()
+            && self.newer_than.is_none()
+            && self.min_size.is_none()
+            && self.max_size.is_none()
+            && self.name_regex.is_none()
+    }
+
+    /// Checks an object's key, size and last-modified time, with ages worked out from `now`
+    pub fn matches(
+        &self,
+        key: &str,
+        size: u64,
+        last_modified: Option<SystemTime>,
+        now: SystemTime,
+    ) -> bool {
+        let age = last_modified.map(|time| now.duration_since(time).unwrap_or_default());
+        let older = self
+            .older_than
+            .is_none_or(|limit| age.is_some_and(|age| age > limit));
+        let newer = self
+            .newer_than
+            .is_none_or(|limit| age.is_some_and(|age| age < limit));
+        older
+            && newer
+            && self.min_size.is_none_or(|min| size >= min)
+            && self.max_size.is_none_or(|max| size <= max)
+            && self
+                .name_regex
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(key))
+    }
+
+    /// [ObjectFilter::matches] for an object from a listing
+    pub fn matches_object(&self, object: &Object, now: SystemTime) -> bool {
+        self.matches(
+            object.key().unwrap_or_default(),
+            object
+                .size()
+                .and_then(|value| u64::try_from(value).ok())
+                .unwrap_or(0),
+            object
+                .last_modified()
+                .and_then(|value| SystemTime::try_from(*value).ok()),
+            now,
+        )
+    }
+}
@@ -0,0 +1,60 @@
+//! Test for s3 playing
+//!
+//! Load an [S3Configuration] from a config file, build an [S3Client] from it, then upload,
+//! download, head, delete and list objects in the configured bucket.
+//!
+//! ```no_run
+//! use rust_test_s3_upload::client::UploadOptions;
+//! use rust_test_s3_upload::{S3Client, S3Configuration, DEFAULT_PROFILE};
+//!
+//! async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//!     let configuration = S3Configuration::load("config.toml".as_ref(), DEFAULT_PROFILE)?;
+//!     let client = S3Client::new(&configuration).await?;
+//!     let upload = client
+//!         .upload("test_file.txt", &UploadOptions::default())
+//!         .await?;
+//!     println!("etag: {}", upload.etag);
+//!     Ok(())
+//! }
+//! ```
+pub mod bench;
+pub mod checksum;
+pub mod client;
+pub mod compression;
+pub mod config;
+pub mod content_type;
+pub mod dedupe;
+pub mod duration;
+pub mod encryption;
+pub mod error;
+pub mod exit;
+pub mod filter;
+pub mod lifecycle;
+pub mod location;
+mod logging;
+pub mod manifest;
+pub mod metadata;
+pub mod mock;
+pub mod output;
+pub mod progress;
+pub mod prune;
+pub mod range;
+mod resume;
+pub mod retry;
+pub mod selftest;
+pub mod sse;
+pub mod stats;
+pub mod store;
+pub mod sync;
+pub mod tags;
+pub mod template;
+pub mod throttle;
+pub mod verify;
+pub mod walk;
+
+pub use client::{
+    CopyResult, DeleteResult, DownloadResult, MoveResult, S3Client, S3FileInfo, UndeleteResult,
+    UploadResult,
+};
+pub use config::{ConfigError, S3Configuration, DEFAULT_PROFILE};
+pub use error::S3Error;
@@ -0,0 +1,159 @@
+//! Bucket lifecycle rules, which have S3 expire old objects, versions and uploads on its own
+//!
+use aws_sdk_s3::error::BuildError;
+use aws_sdk_s3::types::{
+    AbortIncompleteMultipartUpload, ExpirationStatus, LifecycleExpiration, LifecycleRule,
+    LifecycleRuleFilter, NoncurrentVersionExpiration, Transition, TransitionStorageClass,
+};
+use serde_derive::{Deserialize, Serialize};
+
+/// A lifecycle rule for the keys under a prefix, in the parts this tool sets
+///
+/// Rules read back from S3 can do more than this, eg match on tags or expire on a date, those
+/// parts aren't shown here but are kept when the rules are merged.
+///
+/// ```
+/// use rust_test_s3_upload::lifecycle::Rule;
+///
+/// let rule = Rule {
+///     expire_days: Some(90),
+///     noncurrent_expire_days: Some(30),
+///     ..Rule::new("backups/")
+/// };
+/// assert_eq!(rule.id, "s3upload:backups/");
+/// let sdk = rule.to_sdk().unwrap();
+/// assert_eq!(sdk.expiration().and_then(|expiration| expiration.days()), Some(90));
+/// assert_eq!(Rule::from(&sdk), rule);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rule {
+    pub id: String,
+    pub enabled: bool,
+    /// Only keys starting with this, empty for the whole bucket
+    pub prefix: String,
+    /// Expire the current version this many days after it was written
+    pub expire_days: Option<i32>,
+    /// Remove old versions this many days after they stopped being current
+    pub noncurrent_expire_days: Option<i32>,
+    /// Abort multipart uploads that haven't been completed this many days after they started
+    pub abort_multipart_days: Option<i32>,
+    /// Move objects to `transition_storage_class` this many days after they were written
+    pub transition_days: Option<i32>,
+    pub transition_storage_class: Option<String>,
+}
+
+impl Rule {
+    /// An enabled rule for `prefix` that doesn't do anything yet, with an id made from the prefix
+    /// so setting a rule for the same prefix again replaces it
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            id: format!("s3upload:{}", prefix),
+            enabled: true,
+            prefix: prefix.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Checks the rule does something, S3 rejects rules without an action
+    pub fn has_action(&self) -> bool {
+        self.expire_days.is_some()
+            || self.noncurrent_expire_days.is_some()
+            || self.abort_multipart_days.is_some()
+            || self.transition_days.is_some()
+    }
+
+    /// The rule as S3 takes it
+    pub fn to_sdk(&self) -> Result<LifecycleRule, BuildError> {
+        let transition = self.transition_days.map(|days| {
+            Transition::builder()
+                .days(days)
+                .set_storage_class(
+                    self.transition_storage_class
+                        .as_deref()
+                        .map(TransitionStorageClass::from),
+                )
+                .build()
+        });
+        LifecycleRule::builder()
+            .id(&self.id)
+            .status(match self.enabled {
+                true => ExpirationStatus::Enabled,
+                false => ExpirationStatus::Disabled,
+            })
+            .filter(LifecycleRuleFilter::builder().prefix(&self.prefix).build())
+            .set_expiration(
+                self.expire_days
+                    .map(|days| LifecycleExpiration::builder().days(days).build()),
+            )
+            .set_noncurrent_version_expiration(self.noncurrent_expire_days.map(|days| {
+                NoncurrentVersionExpiration::builder()
+                    .noncurrent_days(days)
+                    .build()
+            }))
+            .set_abort_incomplete_multipart_upload(self.abort_multipart_days.map(|days| {
+                AbortIncompleteMultipartUpload::builder()
+                    .days_after_initiation(days)
+                    .build()
+            }))
+            .set_transitions(transition.map(|transition| vec![transition]))
+            .build()
+    }
+}
+
+impl From<&LifecycleRule> for Rule {
+    fn from(rule: &LifecycleRule) -> Self {
+        let filter = rule.filter();
+        let transition = rule.transitions().first();
+        Self {
+            id: rule.id().unwrap_or_default().to_string(),
+            enabled: rule.status() == &ExpirationStatus::Enabled,
+            prefix: filter
+                .and_then(|filter| {
+                    filter
+                        .prefix()
+                        .or_else(|| filter.and().and_then(|and| and.prefix()))
+                })
+                .unwrap_or_default()
+                .to_string(),
+            expire_days: rule.expiration().and_then(|expiration| expiration.days()),
+            noncurrent_expire_days: rule
+                .noncurrent_version_expiration()
+                .and_then(|expiration| expiration.noncurrent_days()),
+            abort_multipart_days: rule
+                .abort_incomplete_multipart_upload()
+                .and_then(|abort| abort.days_after_initiation()),
+            transition_days: transition.and_then(|transition| transition.days()),
+            transition_storage_class: transition
+                .and_then(|transition| transition.storage_class())
+                .map(|class| class.as_str().to_string()),
+        }
+    }
+}
+
+/// Adds `rule` to the bucket's existing rules, replacing the one with the same id if there is one
+///
+/// ```
+/// use rust_test_s3_upload::lifecycle::{merge_rule, Rule};
+///
+/// let rule = |prefix: &str, days: i32| {
+///     Rule {
+///         expire_days: Some(days),
+///         ..Rule::new(prefix)
+///     }
+///     .to_sdk()
+///     .unwrap()
+/// };
+/// let rules = merge_rule(vec![rule("logs/", 7), rule("backups/", 30)], rule("backups/", 90));
+/// let rules: Vec<Rule> = rules.iter().map(Rule::from).collect();
+/// assert_eq!(rules.len(), 2);
+/// assert_eq!((rules[0].prefix.as_str(), rules[0].expire_days), ("logs/", Some(7)));
+/// assert_eq!((rules[1].prefix.as_str(), rules[1].expire_days), ("backups/", Some(90)));
+/// ```
+pub fn merge_rule(rules: Vec<LifecycleRule>, rule: LifecycleRule) -> Vec<LifecycleRule> {
+    let mut rules: Vec<LifecycleRule> = rules
+        .into_iter()
+        .filter(|existing| existing.id() != rule.id())
+        .collect();
+    rules.push(rule);
+    rules
+}
@@ -0,0 +1,65 @@
+//! `s3://bucket/prefix` locations, for commands that take either a local path or somewhere in S3
+//!
+use crate::config::validate_bucket_name;
+use std::fmt;
+use std::str::FromStr;
+
+const SCHEME: &str = "s3://";
+
+/// A bucket and a key or prefix in it
+///
+/// ```
+/// use rust_test_s3_upload::location::S3Location;
+///
+/// let location: S3Location = "s3://backups/hosts/web1".parse().unwrap();
+/// assert_eq!(location.bucket, "backups");
+/// assert_eq!(location.key, "hosts/web1");
+/// assert_eq!(location.to_string(), "s3://backups/hosts/web1");
+///
+/// let location: S3Location = "s3://backups".parse().unwrap();
+/// assert_eq!(location.key, "");
+/// assert!("backups/hosts".parse::<S3Location>().is_err());
+/// assert!("s3://Not_A_Bucket/hosts".parse::<S3Location>().is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct S3Location {
+    pub bucket: String,
+    /// Everything after the bucket, empty for the whole bucket
+    pub key: String,
+}
+
+impl S3Location {
+    /// Checks if an argument is meant to be an S3 location rather than a local path
+    ///
+    /// ```
+    /// use rust_test_s3_upload::location::S3Location;
+    ///
+    /// assert!(S3Location::is_s3("s3://backups/hosts"));
+    /// assert!(!S3Location::is_s3("./hosts"));
+    /// ```
+    pub fn is_s3(value: &str) -> bool {
+        value.starts_with(SCHEME)
+    }
+}
+
+impl FromStr for S3Location {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let rest = value
+            .strip_prefix(SCHEME)
+            .ok_or_else(|| format!("{:?} should look like s3://bucket/prefix", value))?;
+        let (bucket, key) = rest.split_once('/').unwrap_or((rest, ""));
+        validate_bucket_name(bucket).map_err(|error| error.to_string())?;
+        Ok(Self {
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for S3Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}/{}", SCHEME, self.bucket, self.key)
+    }
+}
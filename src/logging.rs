@@ -0,0 +1,70 @@
+//! Logging each HTTP request the SDK sends, at DEBUG with its status, request id and how long it took
+//!
+use aws_sdk_s3::config::interceptors::{
+    BeforeDeserializationInterceptorContextRef, BeforeTransmitInterceptorContextRef,
+};
+use aws_sdk_s3::config::{ConfigBag, Intercept, RuntimeComponents};
+use aws_sdk_s3::error::BoxError;
+use aws_smithy_types::config_bag::{Storable, StoreReplace};
+use std::time::Instant;
+use tracing::debug;
+
+/// What's kept from the request until its response comes back
+#[derive(Debug, Clone)]
+struct SentRequest {
+    method: String,
+    uri: String,
+    started: Instant,
+}
+
+impl Storable for SentRequest {
+    type Storer = StoreReplace<Self>;
+}
+
+/// Interceptor that logs every request, including each of the SDK's own retries
+#[derive(Debug)]
+pub(crate) struct RequestLogger;
+
+impl Intercept for RequestLogger {
+    fn name(&self) -> &'static str {
+        "RequestLogger"
+    }
+
+    fn read_before_transmit(
+        &self,
+        context: &BeforeTransmitInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        if tracing::enabled!(tracing::Level::DEBUG) {
+            let request = context.request();
+            cfg.interceptor_state().store_put(SentRequest {
+                method: request.method().to_string(),
+                uri: request.uri().to_string(),
+                started: Instant::now(),
+            });
+        }
+        Ok(())
+    }
+
+    fn read_after_transmit(
+        &self,
+        context: &BeforeDeserializationInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let Some(sent) = cfg.load::<SentRequest>() else {
+            return Ok(());
+        };
+        let response = context.response();
+        debug!(
+            method = %sent.method,
+            uri = %sent.uri,
+            status = response.status().as_u16(),
+            request_id = response.headers().get("x-amz-request-id").unwrap_or("-"),
+            elapsed_ms = sent.started.elapsed().as_millis() as u64,
+            "request finished"
+        );
+        Ok(())
+    }
+}
@@ -1,225 +1,4874 @@
-use aws_sdk_s3::types::ByteStream;
-///! Test for s3 playing
+//! Test for s3 playing
+//!
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
+use aws_sdk_s3::types::{
+    ChecksumAlgorithm, MetadataDirective, Object, ObjectCannedAcl, ObjectLockMode, StorageClass,
+    Tier,
+};
+use clap::{
+    ArgAction, ArgGroup, ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand,
+};
+use rust_test_s3_upload::bench::{bench_key, write_synthetic, BenchReport, Latencies, Phase};
+use rust_test_s3_upload::checksum::parse_checksum_algorithm;
+use rust_test_s3_upload::client::{
+    csv_field, needs_restore, CopyOptions, DownloadOptions, ListedObject, ObjectVersion,
+    RestoreStatus, UploadOptions, DEFAULT_COPY_PART_SIZE, MAX_PARTS, MAX_PRESIGN_EXPIRY,
+    MAX_PUT_SIZE, MIN_PART_SIZE,
+};
+use rust_test_s3_upload::compression::{Compression, Format};
+use rust_test_s3_upload::config::{parse_acl, parse_storage_class, validate_bucket_name};
+use rust_test_s3_upload::dedupe::content_hash;
+use rust_test_s3_upload::duration::{local_offset, parse_duration, parse_time};
+use rust_test_s3_upload::encryption::ENCRYPTED_SUFFIX;
+use rust_test_s3_upload::exit;
+use rust_test_s3_upload::filter::{parse_size, ObjectFilter, PathFilter};
+use rust_test_s3_upload::lifecycle::Rule;
+use rust_test_s3_upload::location::S3Location;
+use rust_test_s3_upload::manifest::{
+    compare_previous, manifest_key, parse_manifest, read_manifest, to_json_lines, verify_file,
+    write_manifest, ManifestEntry,
+};
+use rust_test_s3_upload::metadata::{parse_metadata_directive, parse_pair, validate_metadata};
+use rust_test_s3_upload::output::{Outcome, Output, Record};
+use rust_test_s3_upload::progress::Progress;
+use rust_test_s3_upload::prune::{self, Backup};
+use rust_test_s3_upload::range::ByteRange;
+use rust_test_s3_upload::selftest::{self, SELFTEST_PREFIX};
+use rust_test_s3_upload::stats::{format_elapsed, format_rate, format_size};
+use rust_test_s3_upload::sync::{self, Compare};
+use rust_test_s3_upload::tags::validate_tags;
+use rust_test_s3_upload::template::KeyTemplate;
+use rust_test_s3_upload::throttle::parse_rate;
+use rust_test_s3_upload::verify::{self, Status};
+use rust_test_s3_upload::walk::{
+    default_key, find_files, join_key, local_path, normalize_key, unique_key, FoundFile, Links,
+};
+use rust_test_s3_upload::{
+    ConfigError, CopyResult, DeleteResult, MoveResult, S3Client, S3Configuration, S3Error,
+    S3FileInfo, UploadResult, DEFAULT_PROFILE,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+/// Shown at the end of --help, see the exit module
+const EXIT_CODES: &str = "Exit codes:
+  0  success
+  1  any other failure, or failures of more than one kind
+  2  bad arguments or config
+  3  the credentials were refused or have expired
+  4  the bucket, object or version wasn't found
+  5  a transfer failed, eg a timeout or a file that couldn't be read or written
+  6  something didn't match, eg a checksum, or verify found differences";
+
+#[derive(Parser)]
+#[command(version, about, arg_required_else_help = true, after_help = EXIT_CODES)]
+struct Cli {
+    /// Path to the config file
+    #[arg(
+        long,
+        global = true,
+        env = "S3UPLOAD_CONFIG",
+        default_value = "config.toml"
+    )]
+    config: PathBuf,
+    /// Which profile from the config file to use
+    #[arg(
+        long,
+        global = true,
+        env = "S3UPLOAD_PROFILE",
+        default_value = DEFAULT_PROFILE
+    )]
+    profile: String,
+    /// Put uploaded keys under this prefix, and only list what's under it, instead of backup_s3_prefix
+    #[arg(long, global = true)]
+    prefix: Option<String>,
+    /// Base64 SSE-C key to encrypt uploads with and read them back, instead of backup_s3_sse_c_key
+    #[arg(
+        long,
+        global = true,
+        env = "S3UPLOAD_SSE_C_KEY",
+        hide_env_values = true
+    )]
+    sse_c_key: Option<String>,
+    /// File holding the SSE-C key, as 32 raw bytes or base64
+    #[arg(long, global = true, conflicts_with = "sse_c_key")]
+    sse_c_key_file: Option<PathBuf>,
+    /// Key file for --encrypt and --decrypt, as 32 raw bytes or base64, instead of backup_s3_encryption_key_file
+    #[arg(long, global = true, env = "S3UPLOAD_ENCRYPTION_KEY_FILE")]
+    encryption_key_file: Option<PathBuf>,
+    /// Use this bucket instead of the one in the config file
+    #[arg(long, global = true)]
+    bucket: Option<String>,
+    /// Connect timeout in seconds
+    #[arg(long, global = true)]
+    connect_timeout: Option<u64>,
+    /// Timeout in seconds for each API call, including retries
+    #[arg(long, global = true)]
+    operation_timeout: Option<u64>,
+    /// How many times to try each request
+    #[arg(long, global = true)]
+    max_attempts: Option<u32>,
+    /// How many times to try each operation when S3 is throttling or unavailable, after the SDK's own attempts
+    #[arg(long, global = true)]
+    retry_attempts: Option<u32>,
+    /// Stop retrying an operation after this long, eg 10m
+    #[arg(long, global = true, value_parser = parse_duration)]
+    retry_max_elapsed: Option<Duration>,
+    /// Cap the combined speed of uploads and downloads, in bytes per second with an optional K, M or G suffix like 10M
+    #[arg(long, global = true, value_parser = parse_rate)]
+    limit_rate: Option<u64>,
+    /// Check the bucket exists and can be accessed before running the command
+    #[arg(long, global = true)]
+    preflight: bool,
+    /// Agree to pay for requests to a requester pays bucket, instead of backup_s3_request_payer
+    #[arg(long, global = true)]
+    request_payer: bool,
+    /// Show what upload, sync, copy, move and delete would do, making only read-only requests
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Log more to stderr, -v for each request and how long each operation took, -vv for
+    /// everything down to the SDK's wire-level detail, RUST_LOG overrides it
+    #[arg(long, short, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+    /// Print nothing but errors, for cron, exit codes and --json records are unaffected
+    #[arg(long, short, global = true)]
+    quiet: bool,
+    /// Print a line of JSON to stdout for each result instead of text, with the operation, key,
+    /// outcome, size, etag, error and how long it took, failures included
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Check the bucket exists, the credentials can access it and it's in the configured region
+    Check,
+    /// Upload an object of its own under selftest/, check it reads back the same, then delete it
+    Selftest,
+    /// Upload local files, using their file names as the keys
+    #[command(alias = "put")]
+    Upload(Box<UploadArgs>),
+    /// Download an object, to a file named after the key unless a destination is given
+    Download {
+        key: String,
+        dest: Option<PathBuf>,
+        /// Overwrite the destination if it already exists
+        #[arg(long)]
+        force: bool,
+        /// Only download these bytes, eg 0-1023 or 1048576- for everything from 1MiB on
+        #[arg(long)]
+        range: Option<ByteRange>,
+        /// How many chunks to download at once
+        #[arg(long, default_value_t = 1)]
+        concurrency: usize,
+        /// Chunk size in MiB for parallel downloads
+        #[arg(long, default_value_t = 8)]
+        chunk_size: u64,
+        /// Decrypt an object uploaded with --encrypt, the default destination drops the .enc
+        #[arg(long, conflicts_with = "range")]
+        decrypt: bool,
+        /// Decompress an object uploaded with --compress, the format comes from its .gz or .zst
+        /// extension and the default destination drops it
+        #[arg(long, conflicts_with = "range")]
+        decompress: bool,
+        /// Don't check the download against the object's checksum or etag, for objects uploaded
+        /// in parts without a checksum, which have nothing to check it against
+        #[arg(long)]
+        no_verify: bool,
+        /// Start again instead of carrying on from the partial file an interrupted download left
+        #[arg(long)]
+        no_resume: bool,
+    },
+    /// Write an object to stdout
+    Cat { key: String },
+    /// Show the metadata for an object
+    Head { key: String },
+    /// Copy an object on the server without downloading it, to another bucket on the same endpoint with --dest-bucket
+    #[command(alias = "cp")]
+    Copy(Box<CopyArgs>),
+    /// Move an object by copying it on the server then deleting the source once the copy is checked
+    #[command(alias = "mv")]
+    Move(Box<CopyArgs>),
+    /// Print a URL that downloads or uploads an object without credentials until it expires
+    Presign {
+        key: String,
+        /// get for a download URL, put for an upload one
+        #[arg(long, value_parser = ["get", "put"], default_value = "get")]
+        method: String,
+        /// How long the URL works for, like 30m or 2d, up to 7d
+        #[arg(long, value_parser = parse_presign_expiry, default_value = "1h")]
+        expires_in: Duration,
+        /// Content-Disposition for the download instead of the object's, eg 'attachment; filename="db.sql"'
+        #[arg(long)]
+        content_disposition: Option<String>,
+        /// Content-Type the upload has to be sent with, for put
+        #[arg(long)]
+        content_type: Option<String>,
+        /// Print a curl command line that uses the URL instead of just the URL
+        #[arg(long)]
+        curl: bool,
+    },
+    /// Delete objects, in batches of up to 1000
+    #[command(alias = "rm")]
+    Delete {
+        /// The keys to delete, - reads them from stdin one per line, or with --recursive a prefix or s3://bucket/prefix
+        #[arg(required = true)]
+        keys: Vec<String>,
+        /// Delete everything under the prefix, after showing how many objects that is and asking
+        #[arg(long, short)]
+        recursive: bool,
+        /// Delete every version and delete marker under the prefix for good, rather than adding delete markers
+        #[arg(long, requires = "recursive")]
+        all_versions: bool,
+        /// Don't ask before deleting a prefix
+        #[arg(long, short)]
+        yes: bool,
+        /// Permanently delete this version of the key instead of adding a delete marker
+        #[arg(long, conflicts_with = "recursive")]
+        version_id: Option<String>,
+        /// Delete versions under GOVERNANCE retention, needs s3:BypassGovernanceRetention
+        #[arg(long)]
+        bypass_governance: bool,
+    },
+    /// Bring back a deleted object in a versioned bucket by removing its latest delete marker
+    Undelete { key: String },
+    /// List every version and delete marker of a key, or of the keys under a prefix, newest first
+    Versions { key_or_prefix: String },
+    /// List the objects in the bucket one level at a time like a directory, printing each page as it arrives
+    #[command(alias = "ls")]
+    List {
+        /// An s3://bucket/prefix to list, or a prefix in the configured bucket under backup_s3_prefix
+        location: Option<String>,
+        /// Only list keys starting with this, under backup_s3_prefix
+        #[arg(long, conflicts_with = "location")]
+        prefix: Option<String>,
+        /// List every key under the prefix instead of grouping them by the next /
+        #[arg(long, short)]
+        recursive: bool,
+        /// Stop after this many objects and PRE lines
+        #[arg(long)]
+        max_items: Option<usize>,
+        #[command(flatten)]
+        filter: ObjectFilterArgs,
+        #[command(flatten)]
+        order: ListOrder,
+        #[command(flatten)]
+        format: ListFormat,
+    },
+    /// Total the size and number of objects under each prefix, and by storage class
+    Du {
+        /// An s3://bucket/prefix to total, or a prefix in the configured bucket under backup_s3_prefix
+        location: Option<String>,
+        /// How many levels of prefixes to group by, 0 for a single total
+        #[arg(long, default_value_t = 1)]
+        depth: usize,
+    },
+    /// Delete old backups under the prefix, by age or keeping the newest few of each, after showing
+    /// what would go and asking
+    Prune {
+        /// An s3://bucket/prefix to prune, or a prefix in the configured bucket under backup_s3_prefix
+        location: Option<String>,
+        /// Keep anything last modified within this many days
+        #[arg(long, required_unless_present = "keep_count", value_parser = clap::value_parser!(u32).range(1..))]
+        keep_days: Option<u32>,
+        /// Keep the newest this many copies of each backup, keys that only differ in the digits of
+        /// their file name are copies of the same backup
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        keep_count: Option<u32>,
+        /// Only consider objects matching these for pruning
+        #[command(flatten)]
+        filter: ObjectFilterArgs,
+        /// Don't ask before deleting
+        #[arg(long, short)]
+        yes: bool,
+    },
+    /// Manage multipart uploads that were never completed
+    Multipart {
+        #[command(subcommand)]
+        command: MultipartCommands,
+    },
+    /// Create or delete buckets, or manage versioning on the configured one
+    Bucket {
+        #[command(subcommand)]
+        command: BucketCommands,
+    },
+    /// Show or add to the bucket's lifecycle rules, which expire objects, versions and uploads
+    Lifecycle {
+        #[command(subcommand)]
+        command: LifecycleCommands,
+    },
+    /// Restore archived GLACIER or DEEP_ARCHIVE objects so they can be downloaded, or check on a restore
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Restore {
+        #[command(subcommand)]
+        command: Option<RestoreCommands>,
+        #[command(flatten)]
+        args: RestoreArgs,
+    },
+    /// Show the Object Lock retention on an object
+    Retention {
+        #[command(subcommand)]
+        command: RetentionCommands,
+    },
+    /// Show, put on or take off the legal hold on an object
+    LegalHold {
+        #[command(subcommand)]
+        command: LegalHoldCommands,
+    },
+    /// Show or replace the tags on an object
+    Tag {
+        #[command(subcommand)]
+        command: TagCommands,
+    },
+    /// Check local files against their objects, reporting any missing from S3, different, or only in S3
+    Verify {
+        /// The local directory to check
+        #[arg(required_unless_present = "manifest")]
+        local: Option<PathBuf>,
+        /// The s3://bucket/prefix it was uploaded to, or a prefix in the configured bucket under backup_s3_prefix
+        location: Option<String>,
+        /// Check the files an upload manifest records instead of walking a directory, from a local
+        /// file, s3://bucket/key or a key under the prefix
+        #[arg(long, conflicts_with_all = ["local", "location"])]
+        manifest: Option<String>,
+        /// Read every file to compare its MD5 or additional checksum too, rather than just its size
+        #[arg(long)]
+        checksum: bool,
+        /// Only check files matching this glob, can be given more than once
+        #[arg(long)]
+        include: Vec<String>,
+        /// Skip files matching this glob, takes precedence over --include
+        #[arg(long)]
+        exclude: Vec<String>,
+        /// How many HEAD requests to make at once
+        #[arg(long, default_value_t = 8)]
+        jobs: usize,
+    },
+    /// Copy what's new or changed from a directory to s3://bucket/prefix, or from there back to a directory
+    Sync(SyncArgs),
+    /// Measure the endpoint by uploading synthetic data, downloading it back and checking it, then
+    /// deleting it, reporting the throughput and how long each kind of request took
+    Bench {
+        /// How much data to send, eg 1G
+        #[arg(long, value_parser = parse_size, default_value = "256M")]
+        size: u64,
+        /// How many parts to upload it in, 1 for a single PUT, it's downloaded in chunks the same size
+        #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u64).range(1..=MAX_PARTS))]
+        parts: u64,
+        /// How many parts or chunks to send or fetch at once
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum BucketCommands {
+    /// Create a bucket in the configured region
+    Create { name: String },
+    /// Delete a bucket, which has to be empty unless --force is given
+    Delete {
+        name: String,
+        /// Delete every object, version and delete marker in it and abort its multipart uploads first
+        #[arg(long)]
+        force: bool,
+    },
+    /// Show or change the tags on the bucket
+    Tags {
+        #[command(subcommand)]
+        command: BucketTagCommands,
+    },
+    /// Show or change whether the bucket keeps old versions of objects
+    Versioning {
+        #[command(subcommand)]
+        command: VersioningCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum BucketTagCommands {
+    /// Show the tags on the bucket
+    Get {
+        /// Print a table, one JSON object, or CSV with a header row
+        #[arg(long, value_parser = ["table", "json", "csv"], default_value = "table")]
+        output: String,
+    },
+    /// Add tags to the bucket, replacing any with the same key
+    Set {
+        #[arg(required = true, value_parser = parse_pair)]
+        tags: Vec<(String, String)>,
+        /// Remove the tags that aren't given instead of keeping them
+        #[arg(long)]
+        replace: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum VersioningCommands {
+    /// Show the versioning and MFA delete status, exiting with 6 if versioning isn't enabled
+    Get,
+    /// Keep every version of every object from now on
+    Enable,
+    /// Stop keeping new versions, the existing ones are kept
+    Suspend,
+}
+
+#[derive(Subcommand)]
+enum LifecycleCommands {
+    /// Show the bucket's lifecycle rules
+    Get,
+    /// Add a rule for a prefix, replacing any rule with the same id and keeping the others
+    Set(LifecycleSetArgs),
+}
+
+#[derive(Args)]
+struct LifecycleSetArgs {
+    /// Keys the rule applies to, as they are in the bucket without backup_s3_prefix, eg backups/
+    #[arg(long, default_value = "")]
+    prefix: String,
+    /// The rule's id, defaults to one made from the prefix so setting it again replaces it
+    #[arg(long)]
+    id: Option<String>,
+    /// Expire objects this many days after they were written
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    expire_days: Option<i32>,
+    /// Remove old versions this many days after they were replaced or deleted
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    noncurrent_expire_days: Option<i32>,
+    /// Abort multipart uploads that still aren't complete this many days after they started
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    abort_multipart_days: Option<i32>,
+    /// Move objects to --transition-storage-class this many days after they were written
+    #[arg(long, requires = "transition_storage_class", value_parser = clap::value_parser!(i32).range(0..))]
+    transition_days: Option<i32>,
+    /// The storage class to move objects to, eg GLACIER or STANDARD_IA
+    #[arg(long, requires = "transition_days")]
+    transition_storage_class: Option<String>,
+    /// Add the rule turned off
+    #[arg(long)]
+    disabled: bool,
+}
+
+#[derive(Subcommand)]
+enum RestoreCommands {
+    /// Show whether an object needs restoring, is being restored, or until when it's restored
+    Status { key: String },
+}
+
+#[derive(Args)]
+struct RestoreArgs {
+    /// The key to restore, or with --recursive a prefix
+    #[arg(required_unless_present = "manifest", conflicts_with = "manifest")]
+    key: Option<String>,
+    /// How many days to keep the restored copy for
+    #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(i32).range(1..))]
+    days: i32,
+    /// Expedited takes minutes, Standard hours and Bulk up to a day but costs the least, DEEP_ARCHIVE has no Expedited
+    #[arg(
+        long,
+        value_parser = ["Standard", "Bulk", "Expedited"],
+        ignore_case = true,
+        default_value = "Standard"
+    )]
+    tier: String,
+    /// Restore every archived object under the prefix
+    #[arg(long, short)]
+    recursive: bool,
+    /// Download the files an upload manifest records instead, checking each against it, from a
+    /// local file, s3://bucket/key or a key under the prefix like manifests/2024-05-01T020000Z.json
+    #[arg(long, requires = "dest", conflicts_with = "recursive")]
+    manifest: Option<String>,
+    /// The directory to download the manifest's files into, at the paths of their keys
+    #[arg(long, requires = "manifest")]
+    dest: Option<PathBuf>,
+    /// Only restore keys from the manifest matching this glob, can be given more than once
+    #[arg(long, requires = "manifest")]
+    include: Vec<String>,
+    /// Skip keys matching this glob, takes precedence over --include
+    #[arg(long, requires = "manifest")]
+    exclude: Vec<String>,
+    /// How many files to download at once from the manifest
+    #[arg(long, default_value_t = 4)]
+    jobs: usize,
+}
+
+#[derive(Subcommand)]
+enum RetentionCommands {
+    /// Show the retention mode and date, if the object has any
+    Get { key: String },
+}
+
+#[derive(Subcommand)]
+enum LegalHoldCommands {
+    /// Show whether there's a legal hold on the object
+    Get { key: String },
+    /// Put a legal hold on the object, or take it off
+    Set {
+        key: String,
+        #[arg(value_parser = ["on", "off"])]
+        status: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum TagCommands {
+    /// Show the tags on an object, one key=value per line
+    Get { key: String },
+    /// Replace the tags on an object, any that aren't given are removed
+    Set {
+        key: String,
+        #[arg(required = true, value_parser = parse_pair)]
+        tags: Vec<(String, String)>,
+    },
+}
+
+#[derive(Args)]
+struct UploadArgs {
+    /// Files to upload, `-` reads from stdin and needs --key
+    #[arg(required = true)]
+    filenames: Vec<String>,
+    /// Key to upload a single file or stdin to, instead of the file name
+    #[arg(long)]
+    key: Option<String>,
+    /// Build keys from a template instead of backup_s3_key_template, eg {hostname}/{yyyy}-{mm}-{dd}/{filename}
+    ///
+    /// Tokens are {filename}, {basename}, {ext}, {hostname}, {yyyy}, {mm}, {dd} and {epoch}, the
+    /// prefix is joined in front of the result. Ignored for --key and stdin.
+    #[arg(long)]
+    key_template: Option<String>,
+    /// Upload everything under the given directories
+    #[arg(long, short)]
+    recursive: bool,
+    /// Only upload files matching this glob when uploading recursively, can be given more than once
+    #[arg(long)]
+    include: Vec<String>,
+    /// Skip files matching this glob when uploading recursively, takes precedence over --include
+    #[arg(long)]
+    exclude: Vec<String>,
+    /// Match --include and --exclude patterns case insensitively
+    #[arg(long)]
+    ignore_case: bool,
+    /// What to do with symlinks when uploading recursively: follow to upload what they point to,
+    /// skip to leave them out, or error to stop at the first one
+    #[arg(long, default_value_t = Links::Follow)]
+    links: Links,
+    /// How many files to upload at once
+    #[arg(long, default_value_t = 4)]
+    jobs: usize,
+    /// Files this size in MiB or bigger are uploaded in parts, instead of
+    /// backup_s3_multipart_threshold or 64, at most 5120 as that's the most S3 takes in one PUT
+    #[arg(long)]
+    multipart_threshold: Option<u64>,
+    /// Part size in MiB for multipart uploads, instead of backup_s3_part_size or 16, at least 5,
+    /// it's raised for files that would need more than 10,000 parts
+    #[arg(long)]
+    part_size: Option<u64>,
+    /// How many parts to upload at once
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+    /// Don't keep a state file to resume an interrupted multipart upload, abort it instead
+    #[arg(long)]
+    no_resume: bool,
+    /// Content-Type for the uploads, guessed from the file extension otherwise
+    #[arg(long)]
+    content_type: Option<String>,
+    /// Cache-Control S3 sends back for the uploads, eg "max-age=3600"
+    #[arg(long)]
+    cache_control: Option<String>,
+    /// Content-Disposition S3 sends back for the uploads, eg "attachment"
+    #[arg(long)]
+    content_disposition: Option<String>,
+    /// Content-Encoding S3 sends back for the uploads, eg "gzip" if the files are already compressed
+    #[arg(long)]
+    content_encoding: Option<String>,
+    /// Expires header for the uploads, an RFC 3339 timestamp or a duration from now like 7d
+    #[arg(long, value_parser = parse_time)]
+    expires: Option<SystemTime>,
+    /// Store key=value as user metadata on the uploads, can be given more than once
+    #[arg(long, value_parser = parse_pair)]
+    metadata: Vec<(String, String)>,
+    /// Tag the uploads with key=value, can be given up to 10 times
+    #[arg(long, value_parser = parse_pair)]
+    tag: Vec<(String, String)>,
+    /// Storage class for the uploads instead of backup_s3_storage_class, eg STANDARD_IA or GLACIER
+    #[arg(long, value_parser = parse_storage_class)]
+    storage_class: Option<StorageClass>,
+    /// Canned ACL for the uploads instead of backup_s3_acl, eg private, public-read or bucket-owner-full-control
+    #[arg(long, value_parser = parse_acl)]
+    acl: Option<ObjectCannedAcl>,
+    /// Server-side encryption for the uploads instead of backup_s3_sse, AES256 or aws:kms
+    #[arg(long)]
+    sse: Option<String>,
+    /// KMS key id or ARN for aws:kms encryption instead of backup_s3_sse_kms_key_id
+    #[arg(long)]
+    sse_kms_key_id: Option<String>,
+    /// Use an S3 Bucket Key with aws:kms encryption
+    #[arg(long)]
+    sse_bucket_key: bool,
+    /// Encrypt the uploads with the --encryption-key-file key first, and add .enc to their keys
+    #[arg(long)]
+    encrypt: bool,
+    /// Compress the uploads as they're sent with gzip or zstd, optionally at a level like
+    /// zstd:19, and add .gz or .zst to their keys
+    ///
+    /// auto is gzip for everything but files that already look compressed, like .jpg or .zip.
+    /// Stdin is always compressed. Objects get a Content-Encoding unless they're encrypted too.
+    #[arg(long)]
+    compress: Option<Compression>,
+    /// Send a CRC32, CRC32C or SHA256 checksum with the uploads and check the one S3 stores
+    #[arg(long, value_parser = parse_checksum_algorithm)]
+    checksum_algorithm: Option<ChecksumAlgorithm>,
+    /// Object Lock retention for the uploads, GOVERNANCE or COMPLIANCE, needs --object-lock-retain-until
+    #[arg(
+        long,
+        value_parser = ["GOVERNANCE", "COMPLIANCE"],
+        ignore_case = true,
+        requires = "object_lock_retain_until"
+    )]
+    object_lock_mode: Option<String>,
+    /// Keep the uploads locked until this RFC 3339 time, or for a duration like 90d
+    #[arg(long, value_parser = parse_time, requires = "object_lock_mode")]
+    object_lock_retain_until: Option<SystemTime>,
+    /// Put a legal hold on the uploads, so they can't be deleted until it's taken off
+    #[arg(long)]
+    legal_hold: bool,
+    /// HEAD each object after it's uploaded to check its size, and its etag for single PUTs
+    #[arg(long)]
+    verify: bool,
+    /// Skip files whose object already has the same size and MD5, or for multipart uploads is newer
+    #[arg(long)]
+    if_changed: bool,
+    /// Upload every file even with --if-changed
+    #[arg(long)]
+    force: bool,
+    /// Write a manifest of the run to this file, a line of JSON for each file with where it went
+    /// and how
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+    /// Upload the manifest to manifests/<timestamp>.json under the prefix as well
+    #[arg(long)]
+    upload_manifest: bool,
+    /// Only upload files that have changed since the run that wrote this manifest, going by their
+    /// size and modification time, then write the new manifest back to it unless --manifest is given
+    #[arg(long)]
+    incremental: Option<PathBuf>,
+    /// With --incremental, delete the objects of files in the manifest that are gone locally, apart
+    /// from content stored with --dedupe, which other files may share
+    #[arg(long, requires = "incremental")]
+    delete: bool,
+    /// Store each file once under cas/<sha256> at the root of the bucket, skipping any that are
+    /// already there, and record which file is which in the manifest
+    ///
+    /// Needs --manifest, --upload-manifest or --incremental, as the manifest is the only record of
+    /// a file's name. An existing object of the wrong size is reported as corrupt.
+    #[arg(long, conflicts_with_all = ["encrypt", "compress", "if_changed", "delete"])]
+    dedupe: bool,
+}
+
+#[derive(Args)]
+struct ListFormat {
+    /// table lines up the columns for reading, json prints an object per line and csv has a header row
+    #[arg(long, value_parser = ["table", "json", "csv"], default_value = "table")]
+    output: String,
+    /// Add the storage class to the table
+    #[arg(long, short)]
+    long: bool,
+    /// Add the etag to the table
+    #[arg(long)]
+    etag: bool,
+    /// Show sizes in KiB, MiB and GiB instead of bytes
+    #[arg(long, short = 'H')]
+    human_readable: bool,
+    /// Show times in UTC instead of local time
+    #[arg(long)]
+    utc: bool,
+}
+
+impl ListFormat {
+    fn size(&self, bytes: u64) -> String {
+        match self.human_readable {
+            true => format_size(bytes),
+            false => bytes.to_string(),
+        }
+    }
+
+    /// A last-modified time to the second, in UTC if asked for or if the local offset is unknown
+    fn time(&self, time: Option<&DateTime>) -> String {
+        let Some(time) = time else {
+            return String::from("-");
+        };
+        let offset = match self.utc {
+            true => None,
+            false => SystemTime::try_from(*time).ok().and_then(local_offset),
+        };
+        DateTime::from_secs(time.secs() + offset.unwrap_or(0))
+            .fmt(DateTimeFormat::DateTime)
+            .unwrap_or_default()
+            .replacen('T', " ", 1)
+            .trim_end_matches('Z')
+            .to_string()
+    }
+}
+
+#[derive(Args)]
+struct CopyArgs {
+    /// The key to copy or move
+    source: String,
+    /// The key to copy or move it to
+    dest: String,
+    /// Copy into this bucket instead, it has to be on the same endpoint
+    #[arg(long)]
+    dest_bucket: Option<String>,
+    /// COPY keeps the source's metadata and headers, REPLACE sets them from the flags below
+    #[arg(long, value_parser = parse_metadata_directive, default_value = "COPY")]
+    metadata_directive: MetadataDirective,
+    /// Content-Type for the copy, the source's if not given, needs --metadata-directive REPLACE
+    #[arg(long)]
+    content_type: Option<String>,
+    /// Cache-Control for the copy, needs --metadata-directive REPLACE
+    #[arg(long)]
+    cache_control: Option<String>,
+    /// Content-Disposition for the copy, needs --metadata-directive REPLACE
+    #[arg(long)]
+    content_disposition: Option<String>,
+    /// Content-Encoding for the copy, needs --metadata-directive REPLACE
+    #[arg(long)]
+    content_encoding: Option<String>,
+    /// Expires header for the copy, an RFC 3339 timestamp or a duration from now like 7d, needs --metadata-directive REPLACE
+    #[arg(long, value_parser = parse_time)]
+    expires: Option<SystemTime>,
+    /// Store key=value as user metadata on the copy, can be given more than once, needs --metadata-directive REPLACE
+    #[arg(long, value_parser = parse_pair)]
+    metadata: Vec<(String, String)>,
+    /// Tag the copy with key=value instead of keeping the source's tags, can be given up to 10 times
+    #[arg(long, value_parser = parse_pair)]
+    tag: Vec<(String, String)>,
+    /// Storage class for the copy instead of backup_s3_storage_class
+    #[arg(long, value_parser = parse_storage_class)]
+    storage_class: Option<StorageClass>,
+    /// Canned ACL for the copy instead of backup_s3_acl
+    #[arg(long, value_parser = parse_acl)]
+    acl: Option<ObjectCannedAcl>,
+    /// Server-side encryption for the copy instead of backup_s3_sse, AES256 or aws:kms
+    #[arg(long)]
+    sse: Option<String>,
+    /// KMS key id or ARN for aws:kms encryption instead of backup_s3_sse_kms_key_id
+    #[arg(long)]
+    sse_kms_key_id: Option<String>,
+    /// Use an S3 Bucket Key with aws:kms encryption
+    #[arg(long)]
+    sse_bucket_key: bool,
+    /// Part size in MiB for objects over 5 GiB, which are copied in parts
+    #[arg(long, default_value_t = DEFAULT_COPY_PART_SIZE / 1024 / 1024)]
+    part_size: u64,
+    /// How many parts to copy at once
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+}
+
+#[derive(Args)]
+struct SyncArgs {
+    /// A local directory, or s3://bucket/prefix to restore from
+    source: String,
+    /// s3://bucket/prefix, or the local directory to restore to
+    dest: String,
+    /// How to tell a file has changed: size, mtime for size and modification time, or checksum for size and MD5
+    #[arg(long, default_value_t = Compare::Mtime)]
+    compare: Compare,
+    /// Only sync files matching this glob, can be given more than once
+    #[arg(long)]
+    include: Vec<String>,
+    /// Skip files matching this glob, takes precedence over --include
+    #[arg(long)]
+    exclude: Vec<String>,
+    /// Match --include and --exclude patterns case insensitively
+    #[arg(long)]
+    ignore_case: bool,
+    /// What to do with symlinks in the local directory: follow to sync what they point to, skip to
+    /// leave them out, or error to stop at the first one
+    #[arg(long, default_value_t = Links::Follow)]
+    links: Links,
+    /// Delete what --include and --exclude accept at the destination but isn't in the source
+    #[arg(long)]
+    delete: bool,
+    /// Let --delete run when there's nothing in the source, emptying the destination
+    #[arg(long)]
+    allow_empty_source: bool,
+    /// How many files to transfer at once
+    #[arg(long, default_value_t = 4)]
+    jobs: usize,
+    /// When syncing to S3, files this size in MiB or bigger are uploaded in parts, instead of
+    /// backup_s3_multipart_threshold or 64
+    #[arg(long)]
+    multipart_threshold: Option<u64>,
+    /// When syncing to S3, the part size in MiB for multipart uploads, instead of
+    /// backup_s3_part_size or 16
+    #[arg(long)]
+    part_size: Option<u64>,
+    /// When syncing to S3, treat files as unchanged if they match this manifest from the last
+    /// sync without comparing them, then write the new manifest back to it
+    #[arg(long)]
+    incremental: Option<PathBuf>,
+    /// When restoring, don't check downloads against their object's checksum or etag
+    #[arg(long)]
+    no_verify: bool,
+}
+
+/// The parts of [SyncArgs] both directions use
+struct SyncSettings {
+    filter: PathFilter,
+    compare: Compare,
+    links: Links,
+    delete: bool,
+    allow_empty_source: bool,
+    jobs: usize,
+    dry_run: bool,
+    incremental: Option<PathBuf>,
+    verify: bool,
+    out: Output,
+}
+
+impl SyncSettings {
+    /// Exits if --delete would remove everything because the source is empty, eg a mount that's missing
+    fn check_source(&self, examined: usize, source: &str, dest: &str) {
+        if self.delete && examined == 0 && !self.allow_empty_source {
+            error!(
+                "{} has nothing to sync, so --delete would remove everything under {}, use --allow-empty-source if that's intended",
+                source, dest
+            );
+            std::process::exit(exit::CONFIG);
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum MultipartCommands {
+    /// List the multipart uploads in progress, with their part counts and sizes
+    List,
+    /// Abort one multipart upload, or all of those older than a threshold
+    #[command(group(ArgGroup::new("target").required(true).args(["upload_id", "older_than"])))]
+    Abort {
+        /// The id of the upload to abort
+        upload_id: Option<String>,
+        /// Abort every upload started longer ago than this, eg 7d or 12h
+        #[arg(long, value_parser = parse_duration)]
+        older_than: Option<Duration>,
+    },
+}
+
+fn format_time(time: SystemTime) -> String {
+    DateTime::from(time)
+        .fmt(DateTimeFormat::DateTime)
+        .unwrap_or_default()
+}
+
+/// Progress bars go on stderr, so they're only drawn when that's a terminal, the output on stdout
+/// isn't meant for a script and we weren't asked to be quiet
+fn show_progress(out: &Output) -> bool {
+    out.is_text() && std::io::stderr().is_terminal()
+}
+
+/// Renders the object metadata as one field per line
+fn format_file_info(info: &S3FileInfo) -> String {
+    let mut lines = vec![
+        format!("etag: {}", info.etag),
+        format!("size: {}", info.size),
+        format!(
+            "server_side_encryption: {}",
+            info.server_side_encryption.as_deref().unwrap_or("none")
+        ),
+        format!("storage_class: {}", info.storage_class),
+    ];
+    if let (Some(algorithm), Some(checksum)) = (&info.checksum_algorithm, &info.checksum) {
+        lines.push(format!(
+            "checksum_{}: {}",
+            algorithm.to_ascii_lowercase(),
+            checksum
+        ));
+    }
+    if let Some(key_id) = &info.sse_kms_key_id {
+        lines.push(format!("sse_kms_key_id: {}", key_id));
+    }
+    if let Some(version_id) = &info.version_id {
+        lines.push(format!("version_id: {}", version_id));
+    }
+    if let Some(last_modified) = info.last_modified {
+        lines.push(format!("last_modified: {}", format_time(last_modified)));
+    }
+    if let Some(content_type) = &info.content_type {
+        lines.push(format!("content_type: {}", content_type));
+    }
+    if let Some(cache_control) = &info.cache_control {
+        lines.push(format!("cache_control: {}", cache_control));
+    }
+    if let Some(content_disposition) = &info.content_disposition {
+        lines.push(format!("content_disposition: {}", content_disposition));
+    }
+    if let Some(content_encoding) = &info.content_encoding {
+        lines.push(format!("content_encoding: {}", content_encoding));
+    }
+    if let Some(expires) = &info.expires {
+        lines.push(format!("expires: {}", expires));
+    }
+    if let Some(mode) = &info.object_lock_mode {
+        lines.push(format!("object_lock_mode: {}", mode));
+    }
+    if let Some(retain_until) = info.object_lock_retain_until {
+        lines.push(format!(
+            "object_lock_retain_until: {}",
+            format_time(retain_until)
+        ));
+    }
+    if let Some(legal_hold) = info.legal_hold {
+        lines.push(format!(
+            "legal_hold: {}",
+            if legal_hold { "on" } else { "off" }
+        ));
+    }
+    if let Some(restore) = &info.restore {
+        lines.push(format!(
+            "restore: {}",
+            match (restore.in_progress, &restore.expiry) {
+                (true, _) => String::from("in progress"),
+                (false, Some(expiry)) => format!("available until {}", expiry),
+                (false, None) => String::from("done"),
+            }
+        ));
+    }
+    for (name, value) in info.metadata.iter() {
+        lines.push(format!("metadata.{}: {}", name, value));
+    }
+    lines.join("\n")
+}
+
+/// Strips leading slashes from a key with a warning, exiting if there's nothing left
+fn clean_key(key: &str) -> String {
+    match normalize_key(key) {
+        Some(value) if value == key => value.to_string(),
+        Some(value) => {
+            warn!("removed the leading / from the key {}", key);
+            value.to_string()
+        }
+        None => {
+            error!("{:?} isn't a usable key", key);
+            std::process::exit(exit::CONFIG);
+        }
+    }
+}
+
+/// What a dry run would have done with a file
+fn format_planned(path: &Path, upload: &UploadResult, bucket: &str) -> String {
+    match upload.skipped {
+        true => format!(
+            "Would skip {}, s3://{}/{} is unchanged",
+            path.display(),
+            bucket,
+            upload.key
+        ),
+        false => format!(
+            "Would upload {} ({}) -> s3://{}/{}",
+            path.display(),
+            format_size(upload.size),
+            bucket,
+            upload.key
+        ),
+    }
+}
+
+fn format_upload(upload: &UploadResult) -> String {
+    if upload.skipped {
+        return format!(
+            "Skipped {}, it's unchanged, etag {}",
+            upload.key, upload.etag
+        );
+    }
+    let mut line = format!(
+        "Uploaded {} ({} bytes, {}) etag {}",
+        upload.key, upload.size, upload.content_type, upload.etag
+    );
+    if let Some(version_id) = &upload.version_id {
+        line.push_str(&format!(" version {}", version_id));
+    }
+    if let Some(sse) = &upload.server_side_encryption {
+        line.push_str(&format!(" encrypted with {}", sse));
+    }
+    if let Some(key_id) = &upload.sse_kms_key_id {
+        line.push_str(&format!(" key {}", key_id));
+    }
+    if let (Some(algorithm), Some(checksum)) = (&upload.checksum_algorithm, &upload.checksum) {
+        line.push_str(&format!(" {} {}", algorithm, checksum));
+    }
+    // what's needed to work out the etag of a multipart upload
+    if let Some(layout) = &upload.multipart {
+        line.push_str(&format!(
+            " in {} parts of {} bytes, the last {} bytes",
+            layout.parts, layout.part_size, layout.last_part_size
+        ));
+    }
+    match &upload.verified {
+        Some(verified) if verified.etag => line.push_str(", verified size and etag"),
+        Some(_) => line.push_str(", verified size"),
+        None => {}
+    }
+    line
+}
+
+/// Parses `--expires-in`, which can't be longer than SigV4 allows
+fn parse_presign_expiry(value: &str) -> Result<Duration, String> {
+    let expires_in = parse_duration(value)?;
+    if expires_in.is_zero() || expires_in > MAX_PRESIGN_EXPIRY {
+        return Err(format!("{:?} should be between 1s and 7d", value));
+    }
+    Ok(expires_in)
+}
+
+/// Quotes a value for a POSIX shell
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// A curl command line for a presigned URL, uploading `file` for put or saving under the key's name for get
+fn curl_command(method: &str, key: &str, url: &str, content_type: Option<&str>) -> String {
+    let file = key.rsplit('/').next().unwrap_or(key);
+    match method {
+        "put" => {
+            let mut command = format!("curl -T {}", shell_quote(file));
+            if let Some(content_type) = content_type {
+                command.push_str(&format!(
+                    " -H {}",
+                    shell_quote(&format!("Content-Type: {}", content_type))
+                ));
+            }
+            format!("{} {}", command, shell_quote(url))
+        }
+        _ => format!("curl -o {} {}", shell_quote(file), shell_quote(url)),
+    }
+}
+
+fn format_copy(copy: &CopyResult) -> String {
+    let mut line = format!(
+        "Copied s3://{}/{} to s3://{}/{} ({}) etag {}",
+        copy.source_bucket,
+        copy.source_key,
+        copy.bucket,
+        copy.key,
+        format_size(copy.size),
+        copy.etag
+    );
+    if let Some(version_id) = &copy.version_id {
+        line.push_str(&format!(" version {}", version_id));
+    }
+    if copy.multipart {
+        line.push_str(", in parts");
+    }
+    line
+}
+
+fn format_move(moved: &MoveResult) -> String {
+    let mut line = format_copy(&moved.copy).replacen("Copied", "Moved", 1);
+    if moved.delete.delete_marker {
+        line.push_str(&format!(
+            ", created a delete marker for {}",
+            moved.delete.key
+        ));
+    }
+    line
+}
+
+fn format_delete(delete: &DeleteResult) -> String {
+    let mut line = format!("Deleted {}", delete.key);
+    match (delete.permanent, delete.delete_marker) {
+        (true, true) => line.push_str(", removed the delete marker"),
+        (true, false) => line.push_str(", destroyed"),
+        (false, true) => line.push_str(", created a delete marker"),
+        (false, false) => {}
+    }
+    if let Some(version_id) = &delete.version_id {
+        line.push_str(&format!(" version {}", version_id));
+    }
+    line
+}
+
+/// Deletes `keys` in batches, printing each one then a summary if there was more than one, and
+/// exits with an error if any couldn't be deleted
+async fn delete_keys(
+    client: &S3Client,
+    keys: &[String],
+    out: &Output,
+    dry_run: bool,
+) -> Result<String, S3Error> {
+    if dry_run {
+        let mut total_bytes = 0;
+        for key in keys {
+            let size = match client.head(key).await {
+                Ok(info) => Some(info.size),
+                // deleting a missing key isn't an error, so planning to isn't either
+                Err(S3Error::NotFound { .. }) => None,
+                Err(error) => return Err(error),
+            };
+            total_bytes += size.unwrap_or(0);
+            let mut record = Record::new("delete", Some(key)).outcome(Outcome::DryRun);
+            if let Some(size) = size {
+                record = record.size(size);
+            }
+            out.record(&record);
+            out.text(match size {
+                Some(size) => format!(
+                    "Would delete s3://{}/{} ({})",
+                    client.bucket(),
+                    key,
+                    format_size(size)
+                ),
+                None => format!(
+                    "Would delete s3://{}/{}, it doesn't exist",
+                    client.bucket(),
+                    key
+                ),
+            });
+        }
+        return Ok(match keys.len() {
+            1 => String::new(),
+            _ => format!(
+                "Would delete {} objects ({})",
+                keys.len(),
+                format_size(total_bytes)
+            ),
+        });
+    }
+
+    let results = client.delete_objects(keys).await?;
+    Ok(report_deletes(results, out))
+}
+
+/// Prints what was deleted, then a summary if there was more than one, and exits with an error if
+/// anything couldn't be deleted
+fn report_deletes(results: Vec<Result<DeleteResult, S3Error>>, out: &Output) -> String {
+    let total = results.len();
+    let (deleted, failed): (Vec<_>, Vec<_>) = results.into_iter().partition(Result::is_ok);
+    let deleted: Vec<DeleteResult> = deleted.into_iter().filter_map(Result::ok).collect();
+    for delete in deleted.iter() {
+        out.record(&Record::new("delete", Some(&delete.key)).detail(delete));
+        out.text(format_delete(delete));
+    }
+    for result in failed.iter() {
+        if let Err(error) = result {
+            report_error(error);
+            out.record(&Record::failed("delete", None, error));
+        }
+    }
+    let summary = format!("Deleted {} objects, {} failed", deleted.len(), failed.len());
+    if !failed.is_empty() {
+        out.text(&summary);
+        let codes = failed.iter().filter_map(|result| result.as_ref().err());
+        exit_with(out, exit::combine(codes.map(exit::code)), &summary);
+    }
+    match total == 1 {
+        true => String::new(),
+        false => summary,
+    }
+}
+
+/// Prints the versions and delete markers of the keys starting with `key_or_prefix` a page at a
+/// time, as a table or one record per version
 ///
-use aws_sdk_s3::{Client, Config, Endpoint, Error};
-use aws_types::region::Region;
-use aws_types::Credentials;
-use http::Uri;
-use serde_derive::{Deserialize, Serialize};
-use std::io::Read;
-use std::str::{self, FromStr};
-use std::time::SystemTime;
-
-#[derive(Debug)]
-pub enum S3Result {
-    DeleteFailure(String),
-    // DownloadFailure(String),
-    FileOpenFail(String),
-    HeadError(String),
-    Success,
-    UploadFailure(String),
-}
-
-#[derive(Clone, Deserialize)]
-struct S3Configuration {
-    backup_s3_access_key_id: String,
-    backup_s3_secret_access_key: String,
-    backup_s3_bucket: String,
-    backup_s3_region: String,
-    // Set a custom endpoint, for example if you're using minio or another alternate S3 provider
-    backup_s3_endpoint: Option<String>,
-    // backup_minio: Option<bool>,
-}
-
-impl S3Configuration {
-    fn new() -> Self {
-        let configpath = std::path::PathBuf::from(String::from("config.toml"));
-        let mut confighandle = std::fs::File::open(&configpath).unwrap();
-        let mut configcontents = String::new();
-
-        #[allow(clippy::unwrap_used)]
-        confighandle.read_to_string(&mut configcontents).unwrap();
-
-        toml::from_str(&configcontents)
-            .map_err(|error| eprintln!("Failed to load config file: {:?}", error))
-            .unwrap()
-    }
-}
-
-// snippet-start:[rust.example_code.s3.basics.list_objects]
-pub async fn list_objects(client: &Client, bucket_name: &str) -> Result<(), Error> {
-    let objects = client.list_objects_v2().bucket(bucket_name).send().await?;
-    println!("Objects in bucket:");
-    for obj in objects.contents().unwrap_or_default() {
-        println!("{:?}", obj.key().unwrap());
-    }
-
-    Ok(())
-}
-
-fn get_client(creds: Credentials, region: String, endpoint: Option<String>) -> Client {
-    let client_config = Config::builder()
-        .credentials_provider(creds)
-        .region(Region::new(region));
-    // set the endpoint if we need to
-    let client_config = match endpoint {
-        Some(_) => client_config.endpoint_resolver(Endpoint::immutable(
-            Uri::from_str(endpoint.unwrap().as_str()).unwrap(),
-        )),
-        None => client_config,
-    };
-    Client::from_conf(client_config.build())
-}
-
-async fn s3_head_file(
-    filename: &str,
-    aws_client: Client,
-    bucket: &str,
-) -> Result<String, S3Result> {
-    let head = aws_client
-        .head_object()
-        .key(filename)
-        .bucket(bucket)
-        .send()
-        .await;
+/// A bucket that's never had versioning turned on only has `null` versions, so that's reported
+/// instead of listing them.
+async fn list_versions(
+    client: &S3Client,
+    key_or_prefix: &str,
+    out: &Output,
+) -> Result<String, S3Error> {
+    match client
+        .versioning()
+        .await
+        .map(|versioning| versioning.status)
+    {
+        Ok(Some(status)) if status == "Enabled" => {}
+        Ok(Some(status)) => warn!(
+            "versioning is {} on {}, objects written since then only have a null version",
+            status.to_lowercase(),
+            client.bucket()
+        ),
+        Ok(None) => {
+            return Ok(format!(
+                "Versioning has never been enabled on {}, so there are no versions to list",
+                client.bucket()
+            ))
+        }
+        // eg no s3:GetBucketVersioning permission, the listing might still work
+        Err(error) => warn!(
+            "couldn't check versioning on {}: {}",
+            client.bucket(),
+            error
+        ),
+    }
+    let mut pages = client.version_pages(key_or_prefix);
+    let mut count = 0;
+    while let Some(versions) = pages.next_page().await? {
+        for version in versions {
+            let last_modified = version.last_modified.map(format_time);
+            out.result(
+                || {
+                    Record::new("versions", Some(&version.key))
+                        .size(version.size)
+                        .detail(&serde_json::json!({
+                            "version_id": version.version_id,
+                            "is_latest": version.is_latest,
+                            "delete_marker": version.delete_marker,
+                            "last_modified": last_modified,
+                        }))
+                },
+                || {
+                    format!(
+                        "{:<20}  {:>12}  {:<6}  {:<13}  {}  {}",
+                        last_modified.as_deref().unwrap_or("-"),
+                        match version.delete_marker {
+                            true => String::from("-"),
+                            false => version.size.to_string(),
+                        },
+                        if version.is_latest { "LATEST" } else { "" },
+                        if version.delete_marker {
+                            "DELETE MARKER"
+                        } else {
+                            ""
+                        },
+                        version.version_id,
+                        version.key
+                    )
+                },
+            );
+            count += 1;
+        }
+    }
+    Ok(match count {
+        0 => format!("No versions of {} in {}", key_or_prefix, client.bucket()),
+        _ => String::new(),
+    })
+}
+
+/// Deletes everything under a prefix, or every version of it with `all_versions`, after showing
+/// how much that is and asking unless `yes`
+///
+/// `location` is an `s3://bucket/prefix` or a prefix in the configured bucket, and is treated as
+/// a folder so `2022` doesn't match `2022-old/`.
+async fn delete_prefix(
+    client: &S3Client,
+    location: &str,
+    all_versions: bool,
+    yes: bool,
+    out: &Output,
+    dry_run: bool,
+) -> Result<String, S3Error> {
+    let (client, prefix) = match S3Location::is_s3(location) {
+        true => match location.parse::<S3Location>() {
+            Ok(location) => (client.clone().with_bucket(location.bucket), location.key),
+            Err(error) => {
+                error!("{}", error);
+                std::process::exit(exit::CONFIG);
+            }
+        },
+        false => (client.clone(), location.to_string()),
+    };
+    let prefix = join_key(&prefix, "");
+    let described = format!("s3://{}/{}", client.bucket(), prefix);
+
+    let versions: Vec<ObjectVersion> = match all_versions {
+        true => client.list_versions(&prefix).await?,
+        false => client
+            .list_all(&prefix)
+            .await?
+            .into_iter()
+            .filter_map(|object| {
+                Some(ObjectVersion {
+                    key: object.key()?.to_string(),
+                    version_id: String::new(),
+                    size: object
+                        .size()
+                        .and_then(|value| u64::try_from(value).ok())
+                        .unwrap_or(0),
+                    is_latest: true,
+                    delete_marker: false,
+                    last_modified: None,
+                })
+            })
+            .collect(),
+    };
+    if versions.is_empty() {
+        return Ok(format!("Nothing to delete under {}", described));
+    }
+    let total_bytes: u64 = versions.iter().map(|version| version.size).sum();
+    let markers = versions
+        .iter()
+        .filter(|version| version.delete_marker)
+        .count();
+    let found = match all_versions {
+        true => format!(
+            "{} versions and {} delete markers ({}) under {}",
+            versions.len() - markers,
+            markers,
+            format_size(total_bytes),
+            described
+        ),
+        false => format!(
+            "{} objects ({}) under {}",
+            versions.len(),
+            format_size(total_bytes),
+            described
+        ),
+    };
+
+    if dry_run {
+        for version in versions.iter() {
+            out.record(
+                &Record::new("delete", Some(&version.key))
+                    .outcome(Outcome::DryRun)
+                    .size(version.size)
+                    .detail(version),
+            );
+        }
+        for version in versions.iter() {
+            out.text(match all_versions {
+                true => format!(
+                    "Would delete s3://{}/{} version {} ({})",
+                    client.bucket(),
+                    version.key,
+                    version.version_id,
+                    format_size(version.size)
+                ),
+                false => format!(
+                    "Would delete s3://{}/{} ({})",
+                    client.bucket(),
+                    version.key,
+                    format_size(version.size)
+                ),
+            });
+        }
+        return Ok(format!("Would delete {}", found));
+    }
+    if !yes {
+        if !std::io::stdin().is_terminal() {
+            error!(
+                "Found {}, pass --yes to delete them when stdin isn't a terminal",
+                found
+            );
+            std::process::exit(exit::CONFIG);
+        }
+        if !confirm(&format!("Delete {}?", found)) {
+            error!("Nothing was deleted");
+            exit_with(out, exit::FAILURE, "Nothing was deleted");
+        }
+    }
+
+    let results = match all_versions {
+        true => client.delete_versions(&versions).await?,
+        false => {
+            let keys: Vec<String> = versions.into_iter().map(|version| version.key).collect();
+            client.delete_objects(&keys).await?
+        }
+    };
+    Ok(report_deletes(results, out))
+}
+
+/// Runs [selftest::run] on a new key under the prefix, with a line or record for each step
+async fn run_selftest(
+    client: &S3Client,
+    prefix: &str,
+    out: &Output,
+    dry_run: bool,
+) -> Result<String, S3Error> {
+    let key = unique_key(prefix, SELFTEST_PREFIX, "txt");
+    if dry_run {
+        return Ok(format!(
+            "Would upload s3://{}/{}, check it, download it and delete it",
+            client.bucket(),
+            key
+        ));
+    }
+    let steps = selftest::run(client, &key, &selftest::content(&key)).await?;
+    for step in &steps {
+        out.result(
+            || {
+                Record::new(&format!("selftest {}", step.operation), Some(&key))
+                    .duration(step.elapsed)
+            },
+            || format!("{:<12} {}", step.operation, format_elapsed(step.elapsed)),
+        );
+    }
+    Ok(format!(
+        "Selftest of s3://{}/{} passed, it's been deleted",
+        client.bucket(),
+        key
+    ))
+}
+
+/// Uploads `size` bytes of synthetic data under the prefix in parts of `part_size`, downloads
+/// it back in chunks the same size and checks it, then deletes it however that went, Ctrl-C included
+async fn bench(
+    client: &S3Client,
+    prefix: &str,
+    (size, part_size, concurrency): (u64, u64, usize),
+    out: &Output,
+    dry_run: bool,
+) -> Result<String, S3Error> {
+    let key = bench_key(prefix);
+    let parts = size.div_ceil(part_size).max(1);
+    if dry_run {
+        return Ok(format!(
+            "Would upload {} to s3://{}/{} in {} parts, {} at a time, download it back and delete it",
+            format_size(size),
+            client.bucket(),
+            key,
+            parts,
+            concurrency
+        ));
+    }
+    let name = key.rsplit('/').next().unwrap_or(&key);
+    let dir =
+        std::env::temp_dir().join(format!("s3upload-bench-{}", name.trim_end_matches(".bin")));
+    let latencies = Latencies::default();
+    let client = client.clone().with_latencies(Some(latencies.clone()));
+    let transfers = bench_transfers(&client, &key, &dir, (size, part_size, concurrency));
+    let result = tokio::select! {
+        result = transfers => Some(result),
+        _ = tokio::signal::ctrl_c() => None,
+    };
+    bench_cleanup(&client, &key, &dir).await;
+    let Some(result) = result else {
+        error!(
+            "Interrupted, s3://{}/{} has been cleaned up",
+            client.bucket(),
+            key
+        );
+        exit_with(out, exit::FAILURE, "interrupted");
+    };
+    let (upload, download) = result?;
+
+    let report = BenchReport {
+        key,
+        size,
+        parts,
+        part_size,
+        concurrency,
+        upload,
+        download,
+        latencies: latencies.summary(),
+    };
+    Ok(out.reply(
+        || {
+            Record::new("bench", Some(&report.key))
+                .size(size)
+                .detail(&report)
+        },
+        || report.describe(),
+    ))
+}
+
+/// The timed part of [bench], with the local files in `dir`
+async fn bench_transfers(
+    client: &S3Client,
+    key: &str,
+    dir: &Path,
+    (size, part_size, concurrency): (u64, u64, usize),
+) -> Result<(Phase, Phase), S3Error> {
+    let write_error = |path: &Path| {
+        let path = path.to_path_buf();
+        move |error| S3Error::FileWrite {
+            path,
+            source: error,
+        }
+    };
+    let source = dir.join("upload.bin");
+    tokio::fs::create_dir_all(dir)
+        .await
+        .map_err(write_error(dir))?;
+    let expected = write_synthetic(&source, size)
+        .await
+        .map_err(write_error(&source))?;
+    debug!(
+        "Wrote {} of synthetic data to {}",
+        format_size(size),
+        source.display()
+    );
+
+    let options = UploadOptions {
+        // a single part is a single PUT
+        multipart_threshold: match size > part_size {
+            true => part_size,
+            false => MAX_PUT_SIZE,
+        },
+        part_size,
+        concurrency,
+        resume: false,
+        ..UploadOptions::default()
+    };
+    let started = Instant::now();
+    client.upload_as(&source, key, &options).await?;
+    let upload = Phase::new(size, started.elapsed());
+
+    let dest = dir.join("download.bin");
+    let options = DownloadOptions {
+        force: true,
+        concurrency,
+        chunk_size: part_size,
+        // the parts were sent without checksums, so the SHA256 is compared below instead
+        verify: false,
+        ..DownloadOptions::default()
+    };
+    let started = Instant::now();
+    client.download(key, &dest, &options).await?;
+    let download = Phase::new(size, started.elapsed());
+
+    let actual = content_hash(&dest).await.map_err(|error| S3Error::Read {
+        key: dest.display().to_string(),
+        source: error,
+    })?;
+    if actual != expected {
+        return Err(S3Error::VerificationFailed {
+            key: key.to_string(),
+            attribute: "sha256",
+            expected,
+            actual,
+        });
+    }
+    Ok((upload, download))
+}
 
-    match head {
-        // TODO Reduced struct for nicer data
-        Ok(response) => Ok(format!("{:?}", response)),
-        Err(error) => Err(S3Result::HeadError(format!(
-            "Failed head_object() file: {:?}",
+/// Removes everything [bench] made, warning about anything that couldn't be
+async fn bench_cleanup(client: &S3Client, key: &str, dir: &Path) {
+    if let Err(error) = tokio::fs::remove_dir_all(dir).await {
+        if error.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove {}: {}", dir.display(), error);
+        }
+    }
+    if let Err(error) = client.delete(key).await {
+        warn!(
+            "Failed to delete s3://{}/{}: {}",
+            client.bucket(),
+            key,
             error
-        ))),
+        );
+    }
+    // an interrupted upload leaves its parts behind until it's aborted
+    match client.list_multipart_uploads(false).await {
+        Ok(uploads) => {
+            for upload in uploads.iter().filter(|upload| upload.key == key) {
+                if let Err(error) = client
+                    .abort_multipart_upload(&upload.key, &upload.upload_id)
+                    .await
+                {
+                    warn!(
+                        "Failed to abort multipart upload {} of {}: {}",
+                        upload.upload_id, key, error
+                    );
+                }
+            }
+        }
+        Err(error) => warn!(
+            "Failed to check for unfinished uploads of {}: {}",
+            key, error
+        ),
     }
 }
-async fn s3_upload_file(
-    filename: &str,
-    aws_client: Client,
-    bucket: &str,
-) -> Result<String, S3Result> {
-    let bytestream = match ByteStream::from_path(&filename).await {
-        Ok(value) => value,
+
+/// Deletes the objects under `prefix` that `filter` matches and `retention` doesn't keep, after
+/// listing them and asking unless `yes`
+///
+/// For JSON output the listing is left out and there's a record for each object removed instead.
+async fn prune_backups(
+    client: &S3Client,
+    prefix: &str,
+    filter: &ObjectFilter,
+    retention: &prune::Retention,
+    yes: bool,
+    out: &Output,
+    dry_run: bool,
+) -> Result<String, S3Error> {
+    let described = format!("s3://{}/{}", client.bucket(), prefix);
+    let pruned = prune::find(client, prefix, filter, retention, SystemTime::now()).await?;
+    if pruned.is_empty() {
+        return Ok(format!("Nothing to prune under {}", described));
+    }
+    let total_bytes: u64 = pruned.iter().map(|backup| backup.size).sum();
+    let found = format!(
+        "{} objects ({}) under {}",
+        pruned.len(),
+        format_size(total_bytes),
+        described
+    );
+    for backup in pruned.iter() {
+        out.text(format!(
+            "{}\t{:>10}\t{}",
+            backup.last_modified.map(format_time).unwrap_or_default(),
+            format_size(backup.size),
+            backup.key
+        ));
+    }
+    if dry_run {
+        for backup in pruned.iter() {
+            out.record(&prune_record(backup).outcome(Outcome::DryRun));
+        }
+        return Ok(format!("Would delete {}", found));
+    }
+    if !yes {
+        if !std::io::stdin().is_terminal() {
+            error!(
+                "Found {}, pass --yes to delete them when stdin isn't a terminal",
+                found
+            );
+            std::process::exit(exit::CONFIG);
+        }
+        if !confirm(&format!("Delete {}?", found)) {
+            error!("Nothing was deleted");
+            exit_with(out, exit::FAILURE, "Nothing was deleted");
+        }
+    }
+
+    let keys: Vec<String> = pruned.iter().map(|backup| backup.key.clone()).collect();
+    let results = client.delete_objects(&keys).await?;
+    let mut removed = Vec::new();
+    let mut failed = Vec::new();
+    for (backup, result) in pruned.into_iter().zip(results) {
+        match result {
+            Ok(_) => {
+                out.record(&prune_record(&backup));
+                removed.push(backup);
+            }
+            Err(error) => {
+                report_error(&error);
+                out.record(&Record::failed("prune", Some(&backup.key), &error));
+                failed.push(exit::code(&error));
+            }
+        }
+    }
+    let removed_bytes: u64 = removed.iter().map(|backup| backup.size).sum();
+    let summary = format!(
+        "Pruned {} objects ({}), {} failed",
+        removed.len(),
+        format_size(removed_bytes),
+        failed.len()
+    );
+    if !failed.is_empty() {
+        out.text(&summary);
+        exit_with(out, exit::combine(failed), &summary);
+    }
+    Ok(summary)
+}
+
+/// The record for a backup that was or would be pruned, for an audit log
+fn prune_record(backup: &Backup) -> Record {
+    Record::new("prune", Some(&backup.key))
+        .size(backup.size)
+        .detail(backup)
+}
+
+/// Asks a yes or no question on the terminal, anything but y or yes is a no
+fn confirm(question: &str) -> bool {
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok()
+        && matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Reads keys from stdin one per line, leaving out blank lines
+fn read_keys() -> Vec<String> {
+    std::io::stdin()
+        .lines()
+        .map_while(Result::ok)
+        .map(|line| line.trim_end_matches('\r').to_string())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Which objects to list, all of these have to match
+#[derive(Args)]
+struct ObjectFilterArgs {
+    /// Only objects last modified longer ago than this, like 90d or 12h
+    #[arg(long, value_parser = parse_duration)]
+    older_than: Option<Duration>,
+    /// Only objects last modified more recently than this, like 7d
+    #[arg(long, value_parser = parse_duration)]
+    newer_than: Option<Duration>,
+    /// Only objects at least this big, in bytes or with a K, M, G or T suffix like 100M
+    #[arg(long, value_parser = parse_size)]
+    min_size: Option<u64>,
+    /// Only objects at most this big
+    #[arg(long, value_parser = parse_size)]
+    max_size: Option<u64>,
+    /// Only keys this regex matches somewhere, anchor it with ^ and $ to match the whole key
+    #[arg(long)]
+    name_regex: Option<String>,
+}
+
+impl ObjectFilterArgs {
+    fn build(self) -> Result<ObjectFilter, ConfigError> {
+        let filter = ObjectFilter {
+            older_than: self.older_than,
+            newer_than: self.newer_than,
+            min_size: self.min_size,
+            max_size: self.max_size,
+            name_regex: None,
+        };
+        match self.name_regex {
+            Some(pattern) => filter.with_name_regex(&pattern),
+            None => Ok(filter),
+        }
+    }
+}
+
+/// The client and prefix for an `s3://bucket/prefix` location, or for a prefix in the configured
+/// bucket under `prefix`, exiting if the location isn't valid
+fn listing_location(client: &S3Client, prefix: &str, location: Option<&str>) -> (S3Client, String) {
+    match location {
+        Some(location) if S3Location::is_s3(location) => match location.parse::<S3Location>() {
+            Ok(location) => (client.clone().with_bucket(location.bucket), location.key),
+            Err(error) => {
+                error!("{}", error);
+                std::process::exit(exit::CONFIG);
+            }
+        },
+        location => (
+            client.clone(),
+            join_key(prefix, location.unwrap_or_default()),
+        ),
+    }
+}
+
+/// What part of a prefix to list
+struct Listing {
+    /// Every key rather than one level
+    recursive: bool,
+    /// Stop after this many, after filtering and sorting
+    max_items: Option<usize>,
+    /// Only objects matching this, prefixes are still shown
+    filter: ObjectFilter,
+}
+
+/// What to sort a listing by, anything but name in S3's order has to hold the whole listing
+#[derive(Args)]
+struct ListOrder {
+    /// name, size or modified, anything but name holds the whole listing in memory to sort it
+    /// before --max-items is applied, rather than printing each page as it arrives
+    #[arg(long, value_parser = ["name", "size", "modified"], default_value = "name")]
+    sort: String,
+    /// Largest, newest or last name first
+    #[arg(long)]
+    reverse: bool,
+}
+
+impl ListOrder {
+    /// S3 lists keys in UTF-8 byte order, which is already sorted by name
+    fn streams(&self) -> bool {
+        self.sort == "name" && !self.reverse
+    }
+
+    /// Sorts prefixes and objects, prefixes go first when sorting by size or time, ties and
+    /// objects without a time are ordered by name with the missing times first
+    fn sort(&self, entries: &mut [ListEntry]) {
+        entries.sort_by(|a, b| {
+            let order = match self.sort.as_str() {
+                "size" => a
+                    .is_object()
+                    .cmp(&b.is_object())
+                    .then(a.size().cmp(&b.size())),
+                "modified" => a
+                    .is_object()
+                    .cmp(&b.is_object())
+                    .then(a.modified().cmp(&b.modified())),
+                _ => std::cmp::Ordering::Equal,
+            };
+            order.then_with(|| a.name().cmp(b.name()))
+        });
+        if self.reverse {
+            entries.reverse();
+        }
+    }
+}
+
+/// A prefix or an object in a listing
+enum ListEntry {
+    Prefix(String),
+    Object(Box<Object>),
+}
+
+impl ListEntry {
+    fn is_object(&self) -> bool {
+        matches!(self, Self::Object(_))
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Self::Prefix(prefix) => prefix,
+            Self::Object(object) => object.key().unwrap_or_default(),
+        }
+    }
+
+    fn size(&self) -> i64 {
+        match self {
+            Self::Prefix(_) => 0,
+            Self::Object(object) => object.size().unwrap_or(0),
+        }
+    }
+
+    fn modified(&self) -> Option<(i64, u32)> {
+        match self {
+            Self::Prefix(_) => None,
+            Self::Object(object) => object
+                .last_modified()
+                .map(|time| (time.secs(), time.subsec_nanos())),
+        }
+    }
+}
+
+/// Prints a listing's entries in the chosen format, or as records, counting what's printed for
+/// the totals
+struct ListPrinter<'a> {
+    format: &'a ListFormat,
+    out: &'a Output,
+    /// Stripped from the front of names in a table
+    directory: &'a str,
+    count: usize,
+    total_bytes: u64,
+}
+
+impl ListPrinter<'_> {
+    fn print(&mut self, entry: &ListEntry) {
+        let format = self.format;
+        let output = format.output.as_str();
+        if self.out.is_json() {
+            let record = match entry {
+                ListEntry::Prefix(prefix) => Record::new("list", Some(prefix))
+                    .detail(&serde_json::json!({ "prefix": prefix })),
+                ListEntry::Object(object) => {
+                    let listed = ListedObject::from(object.as_ref());
+                    Record::new("list", Some(&listed.key))
+                        .size(listed.size)
+                        .etag(&listed.etag)
+                        .detail(&listed)
+                }
+            };
+            self.out.record(&record);
+            return;
+        }
+        match entry {
+            ListEntry::Prefix(prefix) => match output {
+                "json" => self.out.text(serde_json::json!({ "prefix": prefix })),
+                "csv" => self.out.text(format!("{},,,,", csv_field(prefix))),
+                _ => self.out.text(format!(
+                    "{:>19} {:>13} {:long$}{:etag$}{}",
+                    "",
+                    "PRE",
+                    "",
+                    "",
+                    prefix.strip_prefix(self.directory).unwrap_or(prefix),
+                    long = if format.long { 14 } else { 0 },
+                    etag = if format.etag { 34 } else { 0 },
+                )),
+            },
+            ListEntry::Object(object) => {
+                let listed = ListedObject::from(object.as_ref());
+                match output {
+                    "json" => self
+                        .out
+                        .text(serde_json::to_string(&listed).unwrap_or_default()),
+                    "csv" => self.out.text(listed.to_csv()),
+                    _ => {
+                        self.count += 1;
+                        self.total_bytes += listed.size;
+                        let mut line = format!(
+                            "{:<19} {:>13} ",
+                            format.time(object.last_modified()),
+                            format.size(listed.size)
+                        );
+                        if format.long {
+                            line.push_str(&format!("{:<13} ", listed.storage_class));
+                        }
+                        if format.etag {
+                            line.push_str(&format!("{:<33} ", listed.etag));
+                        }
+                        line.push_str(
+                            listed
+                                .key
+                                .strip_prefix(self.directory)
+                                .unwrap_or(&listed.key),
+                        );
+                        self.out.text(line);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The totals line for a table
+    fn finish(self) -> String {
+        match self.format.output.as_str() {
+            "table" if !self.out.is_json() => format!(
+                "Total: {} objects, {}",
+                self.count,
+                match self.format.human_readable {
+                    true => format_size(self.total_bytes),
+                    false => format!("{} bytes", self.total_bytes),
+                }
+            ),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Prints the objects under `listed`, with the keys below the next / grouped into `PRE name/`
+/// lines unless `recursive`
+///
+/// Pages are printed as they arrive unless `order` needs the whole listing sorted first. In a
+/// table names are shown relative to the last / in `listed`, like a directory listing, the folder
+/// marker for the directory being listed is left out, and it ends with the totals. JSON and CSV
+/// have the full keys, with prefixes as `{"prefix": ...}` objects or rows with only a key.
+async fn list_objects(
+    client: &S3Client,
+    listed: &str,
+    listing: &Listing,
+    order: &ListOrder,
+    format: &ListFormat,
+    out: &Output,
+) -> Result<String, S3Error> {
+    let Listing {
+        recursive,
+        max_items,
+        ref filter,
+    } = *listing;
+    let streams = order.streams();
+    // filtering happens here, so S3 can only be asked to stop early without it
+    let mut pages = client.list_pages(listed, max_items.filter(|_| streams && filter.is_empty()));
+    let mut remaining = max_items.unwrap_or(usize::MAX);
+    let now = SystemTime::now();
+    if !recursive {
+        pages = pages.with_delimiter("/");
+    }
+    let directory = match listed.rfind('/') {
+        Some(index) if !recursive => &listed[..=index],
+        _ => "",
+    };
+    if format.output == "csv" {
+        out.text(ListedObject::CSV_HEADER);
+    }
+    let mut printer = ListPrinter {
+        format,
+        out,
+        directory,
+        count: 0,
+        total_bytes: 0,
+    };
+    let mut buffered = Vec::new();
+    while let Some(page) = pages.next_page().await? {
+        let entries = page
+            .prefixes
+            .into_iter()
+            .map(ListEntry::Prefix)
+            .chain(
+                page.objects
+                    .into_iter()
+                    .map(|object| ListEntry::Object(Box::new(object))),
+            )
+            // the folder marker of the directory itself isn't in it
+            .filter(|entry| recursive || !entry.is_object() || entry.name() != directory)
+            .filter(|entry| match entry {
+                ListEntry::Prefix(_) => true,
+                ListEntry::Object(object) => filter.matches_object(object, now),
+            });
+        match streams {
+            true => {
+                for entry in entries.take(remaining) {
+                    printer.print(&entry);
+                    remaining -= 1;
+                }
+                if remaining == 0 {
+                    break;
+                }
+            }
+            false => buffered.extend(entries),
+        }
+    }
+    order.sort(&mut buffered);
+    buffered.truncate(max_items.unwrap_or(usize::MAX));
+    for entry in buffered.iter() {
+        printer.print(entry);
+    }
+    Ok(printer.finish())
+}
+
+/// The number of objects and bytes in a group
+#[derive(Default)]
+struct Usage {
+    objects: u64,
+    bytes: u64,
+}
+
+impl Usage {
+    fn add(&mut self, bytes: u64) {
+        self.objects += 1;
+        self.bytes += bytes;
+    }
+
+    fn row(&self, name: &str) -> String {
+        format!(
+            "{:>10}  {:>8}  {}",
+            format_size(self.bytes),
+            self.objects,
+            name
+        )
+    }
+
+    /// The group as a record, with the object count added to `detail`
+    fn record(&self, key: Option<&str>, mut detail: serde_json::Value) -> Record {
+        detail["objects"] = self.objects.into();
+        Record::new("du", key).size(self.bytes).detail(&detail)
+    }
+}
+
+/// The prefix a key is counted under, its first `depth` directories relative to the listing
+fn usage_group(relative: &str, depth: usize) -> &str {
+    match relative.match_indices('/').nth(depth.saturating_sub(1)) {
+        Some((index, _)) if depth > 0 => &relative[..=index],
+        // not that deep, it's counted in the directory it's in
+        _ if depth > 0 => relative.rfind('/').map_or("", |index| &relative[..=index]),
+        _ => "",
+    }
+}
+
+/// Pages through everything under `listed`, totalling the objects and bytes under each prefix
+/// `depth` levels down and in each storage class, and prints them largest first with a grand total
+///
+/// Only the totals are kept, not the listing.
+async fn disk_usage(
+    client: &S3Client,
+    listed: &str,
+    depth: usize,
+    out: &Output,
+) -> Result<String, S3Error> {
+    let mut groups: HashMap<String, Usage> = HashMap::new();
+    let mut classes: HashMap<String, Usage> = HashMap::new();
+    let mut total = Usage::default();
+    let mut pages = client.list_pages(listed, None);
+    while let Some(page) = pages.next_page().await? {
+        for object in page.objects {
+            let key = object.key().unwrap_or_default();
+            let relative = key.strip_prefix(listed).unwrap_or(key);
+            let bytes = object
+                .size()
+                .and_then(|value| u64::try_from(value).ok())
+                .unwrap_or(0);
+            groups
+                .entry(usage_group(relative, depth).to_string())
+                .or_default()
+                .add(bytes);
+            let class = object
+                .storage_class()
+                .map_or("STANDARD", |class| class.as_str());
+            classes.entry(class.to_string()).or_default().add(bytes);
+            total.add(bytes);
+        }
+    }
+    if total.objects == 0 {
+        return Ok(format!(
+            "No objects under s3://{}/{}",
+            client.bucket(),
+            listed
+        ));
+    }
+    let largest_first = |usage: HashMap<String, Usage>| {
+        let mut usage: Vec<(String, Usage)> = usage.into_iter().collect();
+        usage.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.cmp(&b.0)));
+        usage
+    };
+    if out.is_json() {
+        for (group, usage) in largest_first(groups) {
+            out.record(&usage.record(Some(&format!("{}{}", listed, group)), serde_json::json!({})));
+        }
+        out.record(&total.record(Some(listed), serde_json::json!({ "total": true })));
+        for (class, usage) in largest_first(classes) {
+            out.record(&usage.record(None, serde_json::json!({ "storage_class": class })));
+        }
+        return Ok(String::new());
+    }
+    out.text(format!("{:>10}  {:>8}  PREFIX", "SIZE", "OBJECTS"));
+    for (group, usage) in largest_first(groups) {
+        out.text(usage.row(&format!("s3://{}/{}{}", client.bucket(), listed, group)));
+    }
+    out.text(total.row("total"));
+    out.text("");
+    out.text(format!("{:>10}  {:>8}  STORAGE CLASS", "SIZE", "OBJECTS"));
+    for (class, usage) in largest_first(classes) {
+        out.text(usage.row(&class));
+    }
+    Ok(String::new())
+}
+
+/// The client pointed at another bucket, exiting if the name isn't valid
+fn bucket_client(client: &S3Client, name: String) -> S3Client {
+    if let Err(error) = validate_bucket_name(&name) {
+        error!("{}", error);
+        std::process::exit(exit::CONFIG);
+    }
+    client.clone().with_bucket(name)
+}
+
+/// Deletes the client's bucket, with `force` emptying it first and exiting with an error if
+/// anything in it couldn't be deleted
+async fn delete_bucket(
+    client: &S3Client,
+    force: bool,
+    out: &Output,
+    dry_run: bool,
+) -> Result<String, S3Error> {
+    if !force {
+        return match dry_run {
+            true => Ok(format!("Would delete bucket {}", client.bucket())),
+            false => client
+                .delete_bucket()
+                .await
+                .map(|()| format!("Deleted bucket {}", client.bucket())),
+        };
+    }
+    let versions = client.list_versions("").await?;
+    let uploads = client.list_multipart_uploads(false).await?;
+    if dry_run {
+        return Ok(format!(
+            "Would delete {} versions and delete markers, abort {} multipart uploads and delete bucket {}",
+            versions.len(),
+            uploads.len(),
+            client.bucket()
+        ));
+    }
+    let mut failed = Vec::new();
+    for result in client.delete_versions(&versions).await? {
+        if let Err(error) = result {
+            report_error(&error);
+            failed.push(exit::code(&error));
+        }
+    }
+    for upload in uploads.iter() {
+        if let Err(error) = client
+            .abort_multipart_upload(&upload.key, &upload.upload_id)
+            .await
+        {
+            report_error(&error);
+            failed.push(exit::code(&error));
+        }
+    }
+    if !failed.is_empty() {
+        let summary = format!(
+            "{} versions or uploads couldn't be removed, so bucket {} wasn't deleted",
+            failed.len(),
+            client.bucket()
+        );
+        error!("{}", summary);
+        exit_with(out, exit::combine(failed), &summary);
+    }
+    client.delete_bucket().await?;
+    Ok(format!(
+        "Deleted {} versions and delete markers, aborted {} multipart uploads and deleted bucket {}",
+        versions.len(),
+        uploads.len(),
+        client.bucket()
+    ))
+}
+
+/// Bucket tags as a table, JSON or CSV
+fn format_bucket_tags(tags: &BTreeMap<String, String>, output: &str) -> String {
+    match output {
+        "json" => serde_json::to_string_pretty(tags).unwrap_or_default(),
+        "csv" => std::iter::once(String::from("key,value"))
+            .chain(
+                tags.iter()
+                    .map(|(name, value)| format!("{},{}", csv_field(name), csv_field(value))),
+            )
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => {
+            let width = tags.keys().map(|name| name.len()).max().unwrap_or(0).max(3);
+            std::iter::once(format!("{:<width$}  VALUE", "KEY"))
+                .chain(
+                    tags.iter()
+                        .map(|(name, value)| format!("{:<width$}  {}", name, value)),
+                )
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    }
+}
+
+/// Describes where a restore of an archived object is up to
+fn format_restore_status(
+    key: &str,
+    storage_class: &str,
+    restore: Option<&RestoreStatus>,
+) -> String {
+    match restore {
+        Some(RestoreStatus {
+            in_progress: true, ..
+        }) => format!(
+            "The restore of {} from {} is in progress",
+            key, storage_class
+        ),
+        Some(status) => format!(
+            "{} is restored from {}, the copy is available until {}",
+            key,
+            storage_class,
+            status.expiry.as_deref().unwrap_or("an unknown time")
+        ),
+        None if needs_restore(storage_class) => format!(
+            "{} is in {} and hasn't been restored, run restore {} first",
+            key, storage_class, key
+        ),
+        None => format!(
+            "{} is in {}, it can be downloaded without restoring it",
+            key, storage_class
+        ),
+    }
+}
+
+/// Prints the files and objects that didn't match as they're found in `checks`, or a record for
+/// each of them, and a summary
+fn report_checks(checks: &[verify::Check], bucket: &str, out: &Output) -> String {
+    let count = |status: Status| checks.iter().filter(|check| check.status == status).count();
+    let problems = checks.len() - count(Status::Matches);
+    for check in checks.iter() {
+        let path = check
+            .path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        let detail = check.detail.as_deref().unwrap_or_default();
+        let problem = match check.status {
+            Status::Matches => None,
+            Status::MissingRemote => {
+                Some(format!("missing {} -> s3://{}/{}", path, bucket, check.key))
+            }
+            Status::Differs => Some(format!("differs {}: {}", path, detail)),
+            Status::OnlyRemote => Some(format!("only in S3 s3://{}/{}", bucket, check.key)),
+            Status::Error => Some(format!("error {}: {}", path, detail)),
+        };
+        let mut record = Record::new("verify", Some(&check.key)).detail(check);
+        if let Some(size) = check.remote_size {
+            record = record.size(size);
+        }
+        if let Some(problem) = &problem {
+            record = record.problem(problem);
+        }
+        out.record(&record);
+        if let Some(problem) = problem {
+            out.text(problem);
+        }
+    }
+    let summary = format!(
+        "Checked {} files, {} match, {} missing from S3, {} differ, {} only in S3, {} errors",
+        checks.len() - count(Status::OnlyRemote),
+        count(Status::Matches),
+        count(Status::MissingRemote),
+        count(Status::Differs),
+        count(Status::OnlyRemote),
+        count(Status::Error)
+    );
+    if problems > 0 {
+        out.text(&summary);
+        exit_with(out, exit::VERIFICATION, &summary);
+    }
+    summary
+}
+
+/// Reads a manifest from a local file, or from `s3://bucket/key` or a key under `prefix` if there's
+/// no such file, exiting if it isn't one
+async fn load_manifest(
+    client: &S3Client,
+    prefix: &str,
+    location: &str,
+) -> Result<Vec<ManifestEntry>, S3Error> {
+    let path = Path::new(location);
+    let parsed = match path.is_file() {
+        true => read_manifest(path),
+        false => {
+            let (client, key) = match S3Location::is_s3(location) {
+                true => listing_location(client, prefix, Some(location)),
+                false => (client.clone(), join_key(prefix, location)),
+            };
+            let mut body = Vec::new();
+            client.download_to(&key, &mut body).await?;
+            parse_manifest(&String::from_utf8_lossy(&body))
+        }
+    };
+    match parsed {
+        Ok(entries) => Ok(entries),
         Err(error) => {
-            return Err(S3Result::FileOpenFail(format!(
-                "Failed to open file: {:?}",
-                error
-            )))
+            error!("{}", error);
+            std::process::exit(exit::CONFIG);
+        }
+    }
+}
+
+/// Downloads the files a manifest records into `dest`, checking each against the etag or checksum
+/// it recorded and setting its modification time back, with a second try for any that don't match
+async fn restore_manifest(
+    client: &S3Client,
+    entries: Vec<ManifestEntry>,
+    dest: &Path,
+    filter: &PathFilter,
+    jobs: usize,
+    out: &Output,
+    dry_run: bool,
+) -> Result<String, S3Error> {
+    let record = |file: &FoundFile, entry: &ManifestEntry| {
+        let record = Record::new("restore", Some(&file.key))
+            .size(entry.size)
+            .detail(&serde_json::json!({ "path": file.path, "symlink": entry.symlink }));
+        match &entry.etag {
+            Some(etag) => record.etag(etag),
+            None => record,
+        }
+    };
+    let mut skipped = 0;
+    let mut wanted: HashMap<PathBuf, ManifestEntry> = HashMap::new();
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = match local_path(&entry.key) {
+            Ok(path) => path.map(|path| dest.join(path)),
+            Err(message) => {
+                warn!("Refusing to restore {}, {}", entry.key, message);
+                None
+            }
+        };
+        match path {
+            Some(path) if entry.is_stored() && filter.matches(&entry.key) => {
+                // files uploaded by content are fetched from there but named by their key
+                files.push(FoundFile {
+                    path: path.clone(),
+                    key: entry.object_key().to_string(),
+                    symlink: false,
+                });
+                wanted.insert(path, entry);
+            }
+            _ => skipped += 1,
         }
+    }
+    let total_bytes: u64 = wanted.values().map(|entry| entry.size).sum();
+    if dry_run {
+        for file in files.iter() {
+            out.result(
+                || record(file, &wanted[&file.path]).outcome(Outcome::DryRun),
+                || {
+                    format!(
+                        "Would restore s3://{}/{} -> {}",
+                        client.bucket(),
+                        file.key,
+                        file.path.display()
+                    )
+                },
+            );
+        }
+        return Ok(format!(
+            "Would restore {} files ({}), {} skipped",
+            files.len(),
+            format_size(total_bytes),
+            skipped
+        ));
+    }
+
+    // each file is checked against what the manifest recorded once it's downloaded
+    let options = DownloadOptions {
+        force: true,
+        verify: false,
+        ..Default::default()
     };
+    info!(
+        "Restoring {} files ({}) from the manifest, {} at a time",
+        files.len(),
+        format_size(total_bytes),
+        jobs
+    );
+    let customer_key = client.has_customer_key();
+    let mut restored = Vec::new();
+    let mut failed = Vec::new();
+    // anything that doesn't match what was recorded gets one more try
+    for attempt in 1..=2 {
+        let mut retry = Vec::new();
+        for (file, result) in client.download_files(&files, &options, jobs).await {
+            let entry = &wanted[&file.path];
+            let error = match result {
+                Ok(_) => match verify_file(entry, &file.path, customer_key).await {
+                    Ok(true) => {
+                        if let Some(modified) = entry.modified {
+                            if let Err(error) = std::fs::File::options()
+                                .write(true)
+                                .open(&file.path)
+                                .and_then(|opened| opened.set_modified(modified))
+                            {
+                                warn!(
+                                    "couldn't set the modification time of {}: {}",
+                                    file.path.display(),
+                                    error
+                                );
+                            }
+                        }
+                        out.result(
+                            || record(&file, entry),
+                            || {
+                                format!(
+                                    "restore s3://{}/{} -> {}",
+                                    client.bucket(),
+                                    file.key,
+                                    file.path.display()
+                                )
+                            },
+                        );
+                        restored.push(file);
+                        continue;
+                    }
+                    Ok(false) => (
+                        exit::VERIFICATION,
+                        String::from("doesn't match the manifest"),
+                    ),
+                    Err(error) => (exit::TRANSFER, error.to_string()),
+                },
+                Err(error) => (exit::code(&error), error.to_string()),
+            };
+            match attempt {
+                1 => retry.push(file),
+                _ => {
+                    let (code, error) = error;
+                    error!("{}: {}", file.path.display(), error);
+                    out.record(&record(&file, entry).problem(&error));
+                    failed.push((file, code));
+                }
+            }
+        }
+        files = retry;
+    }
 
-    let upload = aws_client
-        .put_object()
-        .key(filename)
-        .bucket(bucket)
-        .body(bytestream)
-        .send()
-        .await;
+    let restored_bytes: u64 = restored.iter().map(|file| wanted[&file.path].size).sum();
+    let summary = format!(
+        "Restored {} files ({}), {} skipped, {} failed",
+        restored.len(),
+        format_size(restored_bytes),
+        skipped,
+        failed.len()
+    );
+    if !failed.is_empty() {
+        out.text(&summary);
+        for (file, _) in failed.iter() {
+            out.text(format!("  failed: {}", file.path.display()));
+        }
+        exit_with(
+            out,
+            exit::combine(failed.iter().map(|(_, code)| *code)),
+            &summary,
+        );
+    }
+    Ok(summary)
+}
 
-    match upload {
-        Ok(response) => Ok(format!("{:?}", response)),
-        Err(error) => Err(S3Result::UploadFailure(format!(
-            "Failed to upload file: {:?}",
-            error
-        ))),
+/// Asks for the key to be restored, or with `recursive` every archived object under it as a
+/// prefix, printing what happens to each one and exiting with an error if any couldn't be started
+async fn restore_objects(
+    client: &S3Client,
+    args: RestoreArgs,
+    out: &Output,
+    dry_run: bool,
+) -> Result<String, S3Error> {
+    let key = args.key.unwrap_or_default();
+    let tier = match args.tier.to_ascii_lowercase().as_str() {
+        "bulk" => Tier::Bulk,
+        "expedited" => Tier::Expedited,
+        _ => Tier::Standard,
+    };
+    let how = format!("for {} days with the {} tier", args.days, tier.as_str());
+    if !args.recursive {
+        return match dry_run {
+            true => Ok(format!("Would restore {} {}", key, how)),
+            false => client
+                .restore(&key, args.days, tier)
+                .await
+                .map(|started| match started {
+                    true => format!(
+                        "Restoring {} {}, check on it with restore status {}",
+                        key, how, key
+                    ),
+                    false => format!("A restore of {} is already in progress", key),
+                }),
+        };
     }
+
+    let prefix = join_key(&key, "");
+    let mut pages = client.list_pages(&prefix, None);
+    let (mut requested, mut in_progress, mut failed) = (0, 0, Vec::new());
+    while let Some(page) = pages.next_page().await? {
+        for object in page.objects {
+            let archived = object
+                .storage_class()
+                .is_some_and(|class| needs_restore(class.as_str()));
+            let Some(key) = object.key().filter(|_| archived) else {
+                continue;
+            };
+            let record = Record::new("restore", Some(key));
+            if dry_run {
+                out.result(
+                    || record.outcome(Outcome::DryRun),
+                    || format!("Would restore {}", key),
+                );
+                requested += 1;
+                continue;
+            }
+            match client.restore(key, args.days, tier.clone()).await {
+                Ok(true) => {
+                    out.result(|| record, || format!("Restoring {}", key));
+                    requested += 1;
+                }
+                Ok(false) => {
+                    out.result(
+                        || record.outcome(Outcome::Skipped),
+                        || format!("Already restoring {}", key),
+                    );
+                    in_progress += 1;
+                }
+                Err(error) => {
+                    report_error(&error);
+                    out.record(&Record::failed("restore", Some(key), &error));
+                    failed.push(exit::code(&error));
+                }
+            }
+        }
+    }
+    let summary = format!(
+        "{} {} archived objects under s3://{}/{} {}, {} already in progress, {} failed",
+        if dry_run {
+            "Would restore"
+        } else {
+            "Restoring"
+        },
+        requested,
+        client.bucket(),
+        prefix,
+        how,
+        in_progress,
+        failed.len()
+    );
+    if !failed.is_empty() {
+        out.text(&summary);
+        exit_with(out, exit::combine(failed), &summary);
+    }
+    Ok(summary)
+}
+
+/// Lifecycle rules as a table, a number of days or `-` for each action
+fn format_rules(rules: &[Rule]) -> String {
+    let days = |days: Option<i32>| days.map_or(String::from("-"), |days| format!("{}d", days));
+    let prefix_width = rules
+        .iter()
+        .map(|rule| rule.prefix.len())
+        .chain([6])
+        .max()
+        .unwrap_or_default();
+    let mut lines = vec![format!(
+        "{:<8}  {:>6}  {:>10}  {:>9}  {:<20}  {:<prefix_width$}  ID",
+        "STATUS", "EXPIRE", "NONCURRENT", "ABORT MPU", "TRANSITION", "PREFIX"
+    )];
+    for rule in rules {
+        let transition = match (rule.transition_days, &rule.transition_storage_class) {
+            (Some(transition_days), class) => format!(
+                "{}d {}",
+                transition_days,
+                class.as_deref().unwrap_or_default()
+            ),
+            (None, _) => String::from("-"),
+        };
+        lines.push(format!(
+            "{:<8}  {:>6}  {:>10}  {:>9}  {:<20}  {:<prefix_width$}  {}",
+            if rule.enabled { "Enabled" } else { "Disabled" },
+            days(rule.expire_days),
+            days(rule.noncurrent_expire_days),
+            days(rule.abort_multipart_days),
+            transition,
+            match rule.prefix.is_empty() {
+                true => "(all)",
+                false => &rule.prefix,
+            },
+            rule.id
+        ));
+    }
+    lines.join("\n")
 }
 
-async fn s3_delete_file(
-    filename: &str,
-    aws_client: Client,
-    bucket: &str,
-) -> Result<String, S3Result> {
-    let delete = aws_client
-        .delete_object()
-        .key(filename)
-        .bucket(bucket)
-        .send()
+/// Aborts the matching multipart uploads, exiting with an error if any of them couldn't be aborted
+async fn abort_multipart_uploads(
+    client: &S3Client,
+    upload_id: Option<String>,
+    older_than: Option<Duration>,
+    out: &Output,
+) -> Result<String, S3Error> {
+    let cutoff = older_than.and_then(|value| SystemTime::now().checked_sub(value));
+    let uploads: Vec<_> = client
+        .list_multipart_uploads(false)
+        .await?
+        .into_iter()
+        .filter(|upload| match (&upload_id, cutoff) {
+            (Some(upload_id), _) => &upload.upload_id == upload_id,
+            (None, Some(cutoff)) => upload.initiated.is_some_and(|value| value < cutoff),
+            (None, None) => false,
+        })
+        .collect();
+    if let (Some(upload_id), true) = (&upload_id, uploads.is_empty()) {
+        let message = format!("Multipart upload {} not found", upload_id);
+        error!("{}", message);
+        exit_with(out, exit::NOT_FOUND, &message);
+    }
+
+    let mut failed = Vec::new();
+    for upload in uploads.iter() {
+        match client
+            .abort_multipart_upload(&upload.key, &upload.upload_id)
+            .await
+        {
+            Ok(()) => out.result(
+                || {
+                    Record::new("multipart abort", Some(&upload.key))
+                        .detail(&serde_json::json!({ "upload_id": upload.upload_id }))
+                },
+                || format!("Aborted {} {}", upload.upload_id, upload.key),
+            ),
+            Err(error) => {
+                error!("{}", error);
+                out.record(&Record::failed(
+                    "multipart abort",
+                    Some(&upload.key),
+                    &error,
+                ));
+                failed.push(exit::code(&error));
+            }
+        }
+    }
+    let summary = format!(
+        "Aborted {} multipart uploads, {} failed",
+        uploads.len() - failed.len(),
+        failed.len()
+    );
+    if !failed.is_empty() {
+        out.text(&summary);
+        exit_with(out, exit::combine(failed), &summary);
+    }
+    Ok(summary)
+}
+
+/// Uploads the files under `source` that don't match their objects under `dest`, and with
+/// `--delete` then deletes the objects that aren't in `source`, printing what happens to each one
+/// and exiting with an error if anything failed
+async fn sync_to_s3(
+    client: &S3Client,
+    source: &Path,
+    dest: &S3Location,
+    settings: &SyncSettings,
+    options: &UploadOptions,
+) -> Result<String, S3Error> {
+    let started = Instant::now();
+    let previous = match &settings.incremental {
+        Some(path) => read_manifest(path).unwrap_or_else(|error| {
+            warn!("{}, comparing every file", error);
+            Vec::new()
+        }),
+        None => Vec::new(),
+    };
+    let plan = sync::plan(
+        client,
+        source,
+        &dest.key,
+        &settings.filter,
+        settings.compare,
+        settings.links,
+        &previous,
+    )
+    .await?;
+    settings.check_source(
+        plan.examined(),
+        &source.display().to_string(),
+        &dest.to_string(),
+    );
+    info!(
+        "{} files to upload, {} unchanged, {} only in S3",
+        plan.uploads.len(),
+        plan.unchanged.len(),
+        plan.extra.len()
+    );
+    for (path, error) in plan.unreadable.iter() {
+        warn!("Skipping {}: {}", path.display(), error);
+    }
+    let out = &settings.out;
+    for file in plan.unchanged.iter() {
+        out.result(
+            || sync_record(file).outcome(Outcome::Skipped),
+            || {
+                format!(
+                    "skip {}, s3://{}/{} is unchanged",
+                    file.path.display(),
+                    client.bucket(),
+                    file.key
+                )
+            },
+        );
+    }
+
+    let progress = (show_progress(out) && !options.dry_run).then(|| {
+        Progress::new().with_total(
+            plan.uploads
+                .iter()
+                .filter_map(|file| std::fs::metadata(&file.path).ok())
+                .map(|metadata| metadata.len())
+                .sum(),
+        )
+    });
+    let results = client
+        .clone()
+        .with_progress(progress.clone())
+        .upload_files(&plan.uploads, options, settings.jobs)
         .await;
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    let missing: Vec<&ManifestEntry> = previous
+        .iter()
+        .filter(|entry| entry.is_stored() && !entry.path.exists())
+        .collect();
+    for entry in missing.iter() {
+        warn!(
+            "{} is in the manifest but missing locally",
+            entry.path.display()
+        );
+    }
+    if let Some(path) = settings.incremental.as_ref().filter(|_| !options.dry_run) {
+        let previous: HashMap<&Path, &ManifestEntry> = previous
+            .iter()
+            .map(|entry| (entry.path.as_path(), entry))
+            .collect();
+        let storage_class = options.storage_class.as_ref().map(StorageClass::as_str);
+        let entries: Vec<ManifestEntry> = plan
+            .unchanged
+            .iter()
+            .map(|file| match previous.get(file.path.as_path()) {
+                Some(entry) if entry.key == file.key => (*entry).clone(),
+                _ => ManifestEntry::from_object(file, &plan.remote[&file.key]),
+            })
+            .chain(
+                results
+                    .iter()
+                    .map(|(file, result)| ManifestEntry::new(file, result, storage_class)),
+            )
+            .collect();
+        if let Err(error) = write_manifest(path, &entries) {
+            error!("Failed to write manifest {}: {}", path.display(), error);
+            exit_with(out, exit::TRANSFER, "");
+        }
+    }
+    let mut uploaded = 0;
+    let mut total_bytes = 0;
+    let mut failed = Vec::new();
+    let mut codes = Vec::new();
+    for (file, result) in results.iter() {
+        match result {
+            Ok(upload) => {
+                out.result(
+                    || {
+                        sync_record(file)
+                            .outcome(planned_outcome(options.dry_run))
+                            .size(upload.size)
+                            .etag(&upload.etag)
+                            .detail(upload)
+                    },
+                    || {
+                        format!(
+                            "{} {} ({}) -> s3://{}/{}",
+                            match options.dry_run {
+                                true => "would upload",
+                                false => "upload",
+                            },
+                            file.path.display(),
+                            format_size(upload.size),
+                            client.bucket(),
+                            upload.key
+                        )
+                    },
+                );
+                uploaded += 1;
+                total_bytes += upload.size;
+            }
+            Err(error) => {
+                report_error(error);
+                out.record(&Record::failed("sync", Some(&file.key), error));
+                failed.push(file.path.display().to_string());
+                codes.push(exit::code(error));
+            }
+        }
+    }
+
+    let mut deleted = 0;
+    if settings.delete && !plan.unreadable.is_empty() {
+        // whatever couldn't be read may still be there, so its objects aren't extra
+        warn!("not deleting anything, as some local files couldn't be read");
+    } else if settings.delete && options.dry_run {
+        for key in plan.extra.iter() {
+            out.result(
+                || Record::new("sync delete", Some(key)).outcome(Outcome::DryRun),
+                || format!("would delete s3://{}/{}", client.bucket(), key),
+            );
+        }
+        deleted = plan.extra.len();
+    } else if settings.delete {
+        let results = client.delete_objects(&plan.extra).await?;
+        for (key, result) in plan.extra.iter().zip(results) {
+            match result {
+                Ok(_) => {
+                    out.result(
+                        || Record::new("sync delete", Some(key)),
+                        || format!("delete s3://{}/{}", client.bucket(), key),
+                    );
+                    deleted += 1;
+                }
+                Err(error) => {
+                    report_error(&error);
+                    out.record(&Record::failed("sync delete", Some(key), &error));
+                    failed.push(format!("s3://{}/{}", client.bucket(), key));
+                    codes.push(exit::code(&error));
+                }
+            }
+        }
+    }
 
-    match delete {
-        Ok(response) => Ok(format!("{:?}", response)),
-        Err(error) => Err(S3Result::DeleteFailure(format!(
-            "Failed to upload file: {:?}",
+    let mut summary = format!(
+        "Examined {} files in {:.1}s, {} {} ({}), {} unchanged, {} failed, {} unreadable",
+        plan.examined(),
+        started.elapsed().as_secs_f64(),
+        match options.dry_run {
+            true => "would upload",
+            false => "uploaded",
+        },
+        uploaded,
+        format_size(total_bytes),
+        plan.unchanged.len(),
+        failed.len(),
+        plan.unreadable.len()
+    );
+    if !missing.is_empty() {
+        summary.push_str(&format!(", {} missing locally", missing.len()));
+    }
+    if settings.delete {
+        summary.push_str(&format!(
+            ", {} {}",
+            match options.dry_run {
+                true => "would delete",
+                false => "deleted",
+            },
+            deleted
+        ));
+    }
+    if !failed.is_empty() || !plan.unreadable.is_empty() {
+        out.text(&summary);
+        for failure in failed.iter() {
+            out.text(format!("  failed: {}", failure));
+        }
+        let unreadable = plan.unreadable.iter().map(|_| exit::TRANSFER);
+        exit_with(
+            out,
+            exit::combine(codes.into_iter().chain(unreadable)),
+            &summary,
+        );
+    }
+    Ok(summary)
+}
+
+/// Downloads the objects under `source` that don't match their files under `dest`, and with
+/// `--delete` removes local files that aren't in S3, printing what happens to each one and exiting
+/// with an error if anything failed
+async fn sync_from_s3(
+    client: &S3Client,
+    source: &S3Location,
+    dest: &Path,
+    settings: &SyncSettings,
+) -> Result<String, S3Error> {
+    let started = Instant::now();
+    let plan = sync::plan_restore(
+        client,
+        &source.key,
+        dest,
+        &settings.filter,
+        settings.compare,
+        settings.links,
+    )
+    .await?;
+    settings.check_source(
+        plan.examined(),
+        &source.to_string(),
+        &dest.display().to_string(),
+    );
+    info!(
+        "{} files to download, {} unchanged, {} only locally",
+        plan.downloads.len(),
+        plan.unchanged.len(),
+        plan.extra.len()
+    );
+    for (path, error) in plan.unreadable.iter() {
+        warn!("Skipping {}: {}", path.display(), error);
+    }
+    let out = &settings.out;
+    for file in plan.unchanged.iter() {
+        out.result(
+            || sync_record(file).outcome(Outcome::Skipped),
+            || {
+                format!(
+                    "skip s3://{}/{}, {} is unchanged",
+                    client.bucket(),
+                    file.key,
+                    file.path.display()
+                )
+            },
+        );
+    }
+    let size = |file: &FoundFile| {
+        plan.remote
+            .get(&file.key)
+            .and_then(|object| object.size())
+            .and_then(|value| u64::try_from(value).ok())
+            .unwrap_or_default()
+    };
+
+    let mut downloaded = 0;
+    let mut total_bytes = 0;
+    let mut failed = Vec::new();
+    let mut codes = Vec::new();
+    match settings.dry_run {
+        true => {
+            for file in plan.downloads.iter() {
+                out.result(
+                    || sync_record(file).outcome(Outcome::DryRun).size(size(file)),
+                    || {
+                        format!(
+                            "would download s3://{}/{} ({}) -> {}",
+                            client.bucket(),
+                            file.key,
+                            format_size(size(file)),
+                            file.path.display()
+                        )
+                    },
+                );
+                downloaded += 1;
+                total_bytes += size(file);
+            }
+        }
+        false => {
+            let progress = show_progress(out)
+                .then(|| Progress::new().with_total(plan.downloads.iter().map(size).sum()));
+            // the plan already decided these files should be replaced
+            let options = DownloadOptions {
+                force: true,
+                verify: settings.verify,
+                ..DownloadOptions::default()
+            };
+            let results = client
+                .clone()
+                .with_progress(progress.clone())
+                .download_files(&plan.downloads, &options, settings.jobs)
+                .await;
+            if let Some(progress) = progress {
+                progress.finish();
+            }
+            for (file, result) in results.iter() {
+                match result {
+                    Ok(download) => {
+                        out.result(
+                            || {
+                                sync_record(file)
+                                    .size(download.size)
+                                    .etag(&download.etag)
+                                    .detail(download)
+                            },
+                            || {
+                                format!(
+                                    "download s3://{}/{} ({}) -> {}",
+                                    client.bucket(),
+                                    download.key,
+                                    format_size(download.size),
+                                    download.path.display()
+                                )
+                            },
+                        );
+                        downloaded += 1;
+                        total_bytes += download.size;
+                    }
+                    Err(error) => {
+                        report_error(error);
+                        out.record(&Record::failed("sync", Some(&file.key), error));
+                        failed.push(file.path.clone());
+                        codes.push(exit::code(error));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut deleted = 0;
+    if settings.delete {
+        for path in plan.extra.iter() {
+            let record =
+                || Record::new("sync delete", None).detail(&serde_json::json!({ "path": path }));
+            if settings.dry_run {
+                out.result(
+                    || record().outcome(Outcome::DryRun),
+                    || format!("would delete {}", path.display()),
+                );
+                deleted += 1;
+                continue;
+            }
+            match std::fs::remove_file(path) {
+                Ok(()) => {
+                    out.result(record, || format!("delete {}", path.display()));
+                    deleted += 1;
+                }
+                Err(error) => {
+                    error!("Couldn't delete {}: {}", path.display(), error);
+                    out.record(&record().problem(&error.to_string()));
+                    failed.push(path.clone());
+                    codes.push(exit::TRANSFER);
+                }
+            }
+        }
+    }
+
+    let mut summary = format!(
+        "Examined {} objects in {:.1}s, {} {} ({}), {} unchanged, {} failed, {} unreadable",
+        plan.examined(),
+        started.elapsed().as_secs_f64(),
+        match settings.dry_run {
+            true => "would download",
+            false => "downloaded",
+        },
+        downloaded,
+        format_size(total_bytes),
+        plan.unchanged.len(),
+        failed.len(),
+        plan.unreadable.len()
+    );
+    if settings.delete {
+        summary.push_str(&format!(
+            ", {} {}",
+            match settings.dry_run {
+                true => "would delete",
+                false => "deleted",
+            },
+            deleted
+        ));
+    }
+    if !failed.is_empty() || !plan.unreadable.is_empty() {
+        out.text(&summary);
+        for path in failed.iter() {
+            out.text(format!("  failed: {}", path.display()));
+        }
+        let unreadable = plan.unreadable.iter().map(|_| exit::TRANSFER);
+        exit_with(
+            out,
+            exit::combine(codes.into_iter().chain(unreadable)),
+            &summary,
+        );
+    }
+    Ok(summary)
+}
+
+/// The record for a file sync handled, with its path as the detail until there's more to say
+fn sync_record(file: &FoundFile) -> Record {
+    Record::new("sync", Some(&file.key)).detail(&serde_json::json!({ "path": file.path }))
+}
+
+/// The record for a file or stream that was uploaded, or skipped as it was unchanged
+fn upload_record(upload: &UploadResult, dry_run: bool) -> Record {
+    Record::new("upload", Some(&upload.key))
+        .outcome(match upload.skipped {
+            true => Outcome::Skipped,
+            false => planned_outcome(dry_run),
+        })
+        .size(upload.size)
+        .etag(&upload.etag)
+        .detail(upload)
+}
+
+/// The record for a copy or the copy half of a move, keyed by the source
+fn copy_record(operation: &str, copy: &CopyResult) -> Record {
+    Record::new(operation, Some(&copy.source_key))
+        .size(copy.size)
+        .etag(&copy.etag)
+}
+
+/// [Outcome::DryRun] on a dry run, otherwise [Outcome::Ok]
+fn planned_outcome(dry_run: bool) -> Outcome {
+    match dry_run {
+        true => Outcome::DryRun,
+        false => Outcome::Ok,
+    }
+}
+
+/// Prints an error with a hint about what to do about it, if there is one
+fn report_error(error: &S3Error) {
+    match error {
+        S3Error::AclNotSupported { .. } => error!(
+            "{}, its Object Ownership is set to bucket owner enforced, drop --acl and backup_s3_acl or use bucket-owner-full-control",
+            error
+        ),
+        S3Error::BucketAccessDenied { .. } => error!(
+            "{}, check the credentials are allowed s3:ListBucket on it",
+            error
+        ),
+        S3Error::BucketNotEmpty { .. } => {
+            error!("{}, use --force to delete everything in it first", error)
+        }
+        S3Error::BucketNotFound { .. } => {
+            error!("{}, check backup_s3_bucket or --bucket", error)
+        }
+        S3Error::ChecksumMismatch { .. } => {
+            error!("{}, the object may be corrupted, try the transfer again", error)
+        }
+        S3Error::Connection { .. } => error!(
+            "{}, check the endpoint and any proxy settings: {}",
+            error,
+            DisplayErrorContext(error)
+        ),
+        S3Error::ContentMismatch { .. } => error!(
+            "{}, the stored copy is corrupt or was overwritten, check it and delete it so the next --dedupe upload replaces it",
+            error
+        ),
+        S3Error::DeleteFailed { code: Some(code), .. } if code == "AccessDenied" => {
+            error!("{}, check the credentials are allowed s3:DeleteObject", error)
+        }
+        S3Error::DeleteFailed { .. } => error!("{}", error),
+        S3Error::DownloadFailure { .. }
+        | S3Error::FileOpen { .. }
+        | S3Error::FileWrite { .. }
+        | S3Error::Read { .. }
+        | S3Error::TaskFailed { .. }
+        | S3Error::Write { .. } => error!("{}", DisplayErrorContext(error)),
+        S3Error::EncryptionKeyMismatch { .. } => {
+            error!("{}, check --sse-c-key or backup_s3_sse_c_key", error)
+        }
+        S3Error::FileExists { .. } => {
+            error!("{}, use --force to overwrite it", error)
+        }
+        S3Error::IntegrityFailure { .. } => error!(
+            "{}, try the transfer again, or if decrypting check --encryption-key-file or backup_s3_encryption_key_file, or if a download has nothing to verify it against use --no-verify",
+            error
+        ),
+        S3Error::MoveIncomplete { source, .. } => {
+            error!("{}, delete the source yourself once the problem is fixed", error);
+            report_error(source);
+        }
+        S3Error::MoveOntoItself { .. } => error!("{}", error),
+        S3Error::Symlink { .. } => error!(
+            "{}, use --links follow to upload what it points to or --links skip to leave it out",
             error
-        ))),
+        ),
+        S3Error::ObjectLocked {
+            version_id: Some(_),
+            ..
+        } => error!(
+            "{}, GOVERNANCE retention can be bypassed with --bypass-governance and s3:BypassGovernanceRetention, COMPLIANCE retention can't and a legal hold has to be taken off first",
+            error
+        ),
+        S3Error::IncompleteDownload { .. }
+        | S3Error::InvalidExpiry { .. }
+        | S3Error::InvalidKey { .. }
+        | S3Error::InvalidMetadata { .. }
+        | S3Error::InvalidPartSize { .. }
+        | S3Error::InvalidRange { .. }
+        | S3Error::InvalidTags { .. }
+        | S3Error::NoVersions { .. }
+        | S3Error::NotDeleted { .. }
+        | S3Error::NotFound { .. }
+        | S3Error::ObjectLocked { .. }
+        | S3Error::VersionNotFound { .. } => error!("{}", error),
+        S3Error::RetriesExhausted { source, .. } => {
+            error!("{}", error);
+            report_error(source);
+        }
+        S3Error::RequesterPays { .. } => error!(
+            "{}, use --request-payer or backup_s3_request_payer to agree to pay for the requests",
+            error
+        ),
+        S3Error::SessionExpired { .. } => {
+            error!("{}, refresh your credentials and try again", error)
+        }
+        S3Error::Service {
+            code: Some(code), ..
+        } if code == "InvalidObjectState" => error!(
+            "{}, the object is archived, run restore for it and download it once restore status says it's restored",
+            error
+        ),
+        S3Error::Service {
+            operation: "put_bucket_lifecycle_configuration",
+            code: Some(code),
+            ..
+        } if matches!(
+            code.as_str(),
+            "NotImplemented" | "InvalidStorageClass" | "XMinioAdminTierNotFound"
+        ) => error!(
+            "{}, the endpoint may not support transitions to that storage class, MinIO needs it set up as a remote tier first, or leave out --transition-days",
+            error
+        ),
+        S3Error::Service {
+            operation: "put_bucket_versioning",
+            code: Some(code),
+            ..
+        } if code == "AccessDenied" => error!(
+            "{}, check the credentials are allowed s3:PutBucketVersioning",
+            error
+        ),
+        S3Error::Service { .. } => error!("{}", error),
+        S3Error::Timeout { .. } => error!(
+            "{}, the limits can be raised with --connect-timeout and --operation-timeout",
+            error
+        ),
+        S3Error::Unavailable { .. } => error!(
+            "{}, try again later or raise --retry-attempts and --retry-max-elapsed",
+            error
+        ),
+        S3Error::VerificationFailed { .. } => {
+            error!("{}, nothing was deleted, upload it again", error)
+        }
+        S3Error::WrongRegion { region: Some(_), .. } => {
+            error!("{}, set backup_s3_region to match", error)
+        }
+        S3Error::WrongRegion { .. } => error!("{}, check backup_s3_region", error),
+    }
+    // the errors wrapping another have already reported it, response and all
+    if !matches!(
+        error,
+        S3Error::RetriesExhausted { .. } | S3Error::MoveIncomplete { .. }
+    ) {
+        if let Some(response) = error.response() {
+            error!("S3 responded with {}", response);
+        }
     }
+    debug!("{:?}", error);
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct S3FileInfo {
-    etag: String,
-    size: u64,
-    server_side_encryption: bool,
-    version_id: Option<String>,
-    last_modified: Option<SystemTime>,
+/// Logs to stderr at INFO, or DEBUG and TRACE with -v and -vv, unless RUST_LOG is set
+///
+/// Only our own logs go down to DEBUG with -v, as the SDK's are a lot. Timestamps, targets and
+/// how long each operation took are left out until -v.
+fn init_logging(verbose: u8, quiet: bool) {
+    let default = match (quiet, verbose) {
+        (true, _) => "error",
+        (false, 0) => "warn,rust_test_s3_upload=info",
+        (false, 1) => "warn,rust_test_s3_upload=debug",
+        (false, _) => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match verbose {
+        0 => builder.without_time().with_target(false).init(),
+        _ => builder.with_span_events(FmtSpan::CLOSE).init(),
+    }
 }
 
 // main CLI
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    // load the config file
-    let configuration = S3Configuration::new();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+    let dry_run = cli.dry_run;
+    let out = Output::new(cli.json, &operation_name(&matches)).with_quiet(cli.quiet);
 
-    // create the creds object
-    let creds = Credentials::from_keys(
-        configuration.backup_s3_access_key_id,
-        configuration.backup_s3_secret_access_key,
-        None,
-    );
+    init_logging(cli.verbose, cli.quiet);
 
-    let aws_client = get_client(
-        creds,
-        configuration.backup_s3_region,
-        configuration.backup_s3_endpoint,
-    );
+    // load the config file
+    let mut configuration = match S3Configuration::load(&cli.config, &cli.profile) {
+        Ok(value) => value,
+        Err(error) => {
+            error!("{}", error);
+            std::process::exit(exit::CONFIG);
+        }
+    };
+    if cli.connect_timeout.is_some() {
+        configuration.backup_s3_connect_timeout = cli.connect_timeout;
+    }
+    if cli.operation_timeout.is_some() {
+        configuration.backup_s3_operation_timeout = cli.operation_timeout;
+    }
+    if cli.max_attempts.is_some() {
+        configuration.backup_s3_max_attempts = cli.max_attempts;
+    }
+    if cli.retry_attempts.is_some() {
+        configuration.backup_s3_retry_attempts = cli.retry_attempts;
+    }
+    if let Some(retry_max_elapsed) = cli.retry_max_elapsed {
+        configuration.backup_s3_retry_max_elapsed = Some(retry_max_elapsed.as_secs());
+    }
+    if cli.limit_rate.is_some() {
+        configuration.backup_s3_limit_rate = cli.limit_rate;
+    }
+    if cli.request_payer {
+        configuration.backup_s3_request_payer = Some(true);
+    }
+    if let Some(bucket) = cli.bucket {
+        if let Err(error) = validate_bucket_name(&bucket) {
+            error!("{}", error);
+            std::process::exit(exit::CONFIG);
+        }
+        configuration.backup_s3_bucket = bucket;
+    }
 
-    let bucketlist = aws_client
-        .list_objects_v2()
-        .bucket(&configuration.backup_s3_bucket)
-        .send()
-        .await;
-    let files = match bucketlist {
+    if cli.sse_c_key.is_some() || cli.sse_c_key_file.is_some() {
+        configuration.backup_s3_sse_c_key = cli.sse_c_key;
+        configuration.backup_s3_sse_c_key_file = cli.sse_c_key_file;
+        if let Err(error) = configuration.customer_key() {
+            error!("{}", error);
+            std::process::exit(exit::CONFIG);
+        }
+    }
+    if cli.encryption_key_file.is_some() {
+        configuration.backup_s3_encryption_key_file = cli.encryption_key_file;
+    }
+    let encryption_key_setting = match configuration.encryption_key() {
         Ok(value) => value,
         Err(error) => {
-            eprintln!("Failed to pull files: {:?}", error);
-            std::process::exit(1);
+            error!("{}", error);
+            std::process::exit(exit::CONFIG);
+        }
+    };
+    if cli.prefix.is_some() {
+        configuration.backup_s3_prefix = cli.prefix;
+    }
+    let prefix = configuration.backup_s3_prefix.clone().unwrap_or_default();
+    let key_template_setting = configuration.backup_s3_key_template.clone();
+    let metadata_setting = configuration.backup_s3_metadata.clone().unwrap_or_default();
+    // checked when the config was loaded
+    let storage_class_setting = configuration
+        .backup_s3_storage_class
+        .as_deref()
+        .and_then(|value| parse_storage_class(value).ok());
+    let acl_setting = configuration
+        .backup_s3_acl
+        .as_deref()
+        .and_then(|value| parse_acl(value).ok());
+
+    let client = match S3Client::new(&configuration).await {
+        Ok(value) => value.with_stats(Some(out.stats().clone())),
+        Err(error) => {
+            error!("{}", error);
+            match error {
+                ConfigError::Credentials(_) => std::process::exit(exit::AUTH),
+                _ => std::process::exit(exit::CONFIG),
+            }
         }
     };
 
-    println!("listing files...");
-    println!("================");
-    for file in files.contents().unwrap_or_default() {
-        println!("{}", file.key().unwrap());
+    // the bucket commands work on other buckets, which might not exist yet
+    if cli.preflight && !matches!(cli.command, Commands::Check | Commands::Bucket { .. }) {
+        if let Err(error) = client.check_bucket().await {
+            fail(&out, &error);
+        }
     }
 
-    println!("Uploading test_file.txt");
-    eprintln!(
-        "{:?}",
-        s3_upload_file(
-            "test_file.txt",
-            aws_client.to_owned(),
-            configuration.backup_s3_bucket.as_str()
-        )
-        .await
-    );
-    println!("HEAD test_file.txt");
-    eprintln!(
-        "{:?}",
-        s3_head_file(
-            "test_file.txt",
-            aws_client.to_owned(),
-            configuration.backup_s3_bucket.as_str()
-        )
-        .await
+    let client = match cli.command {
+        Commands::Delete {
+            bypass_governance: true,
+            ..
+        } => client.with_bypass_governance(true),
+        _ => client,
+    };
+    let moving = matches!(cli.command, Commands::Move(_));
+    let result = match cli.command {
+        Commands::Check => client.check_bucket().await.map(|region| {
+            format!(
+                "s3://{} exists and is accessible, in {}",
+                client.bucket(),
+                region.as_deref().unwrap_or("an unknown region")
+            )
+        }),
+        Commands::Selftest => run_selftest(&client, &prefix, &out, dry_run).await,
+        Commands::Upload(args) => {
+            let UploadArgs {
+                filenames,
+                key,
+                key_template,
+                recursive,
+                include,
+                exclude,
+                ignore_case,
+                links,
+                jobs,
+                multipart_threshold,
+                part_size,
+                concurrency,
+                no_resume,
+                content_type,
+                cache_control,
+                content_disposition,
+                content_encoding,
+                expires,
+                metadata,
+                tag,
+                storage_class,
+                acl,
+                sse,
+                sse_kms_key_id,
+                sse_bucket_key,
+                encrypt,
+                compress,
+                checksum_algorithm,
+                object_lock_mode,
+                object_lock_retain_until,
+                legal_hold,
+                verify,
+                if_changed,
+                force,
+                manifest,
+                upload_manifest,
+                incremental,
+                delete,
+                dedupe,
+            } = *args;
+            if dedupe && manifest.is_none() && !upload_manifest && incremental.is_none() {
+                error!(
+                    "--dedupe needs --manifest, --upload-manifest or --incremental to record which file is which"
+                );
+                std::process::exit(exit::CONFIG);
+            }
+            let encryption_key = match (encrypt, encryption_key_setting) {
+                (true, None) => {
+                    error!(
+                        "--encrypt needs a key from --encryption-key-file or backup_s3_encryption_key_file"
+                    );
+                    std::process::exit(exit::CONFIG);
+                }
+                (true, key) => key,
+                (false, _) => None,
+            };
+            if sse.is_some() {
+                configuration.backup_s3_sse = sse;
+            }
+            if sse_kms_key_id.is_some() {
+                configuration.backup_s3_sse_kms_key_id = sse_kms_key_id;
+            }
+            if sse_bucket_key {
+                configuration.backup_s3_sse_bucket_key = Some(true);
+            }
+            if multipart_threshold.is_some() {
+                configuration.backup_s3_multipart_threshold = multipart_threshold;
+            }
+            if part_size.is_some() {
+                configuration.backup_s3_part_size = part_size;
+            }
+            let server_side_encryption = match configuration.server_side_encryption() {
+                Ok(value) => value,
+                Err(error) => {
+                    error!("{}", error);
+                    std::process::exit(exit::CONFIG);
+                }
+            };
+            let options = UploadOptions {
+                multipart_threshold: configuration.multipart_threshold(),
+                part_size: configuration.part_size(),
+                concurrency,
+                resume: !no_resume,
+                content_type,
+                cache_control,
+                content_disposition,
+                content_encoding,
+                expires,
+                metadata: metadata_setting.into_iter().chain(metadata).collect(),
+                tags: tag.into_iter().collect(),
+                storage_class: storage_class.or(storage_class_setting),
+                acl: acl.or(acl_setting),
+                server_side_encryption,
+                sse_kms_key_id: configuration.backup_s3_sse_kms_key_id.clone(),
+                sse_bucket_key: configuration.backup_s3_sse_bucket_key,
+                encryption_key,
+                compression: compress,
+                checksum_algorithm,
+                verify,
+                if_changed: if_changed && !force,
+                dry_run,
+                object_lock_mode: object_lock_mode
+                    .map(|mode| ObjectLockMode::from(mode.to_ascii_uppercase().as_str())),
+                object_lock_retain_until,
+                legal_hold,
+            };
+            if let Err(error) =
+                validate_metadata(&options.metadata).and_then(|_| validate_tags(&options.tags))
+            {
+                error!("{}", error);
+                std::process::exit(exit::CONFIG);
+            }
+            let filter = match PathFilter::new(&include, &exclude, ignore_case) {
+                Ok(value) => value,
+                Err(error) => {
+                    error!("{}", error);
+                    std::process::exit(exit::CONFIG);
+                }
+            };
+            let template = match key_template.or(key_template_setting) {
+                Some(value) => match KeyTemplate::parse(&value) {
+                    Ok(value) => Some(value),
+                    Err(error) => {
+                        error!("{}", error);
+                        std::process::exit(exit::CONFIG);
+                    }
+                },
+                None => None,
+            };
+            // every file in the run gets the same date
+            let now = SystemTime::now();
+            let encrypted_key = |key: String| match encrypt && !key.ends_with(ENCRYPTED_SUFFIX) {
+                true => format!("{}{}", key, ENCRYPTED_SUFFIX),
+                false => key,
+            };
+            // the compression's extension goes before .enc, as that's the order they're undone in
+            let suffixed_key = |key: String, name: &str| match compress {
+                Some(compression) if compression.applies_to(name) => {
+                    encrypted_key(format!("{}{}", key, compression.format.extension()))
+                }
+                _ => encrypted_key(key),
+            };
+            let upload_key = |name: &str| {
+                let key = match &template {
+                    Some(template) => join_key(&prefix, &template.render(name, now)),
+                    None => join_key(&prefix, name),
+                };
+                suffixed_key(key, name)
+            };
+            let key = key.map(|value| join_key(&prefix, &clean_key(&value)));
+            if filenames.iter().any(|filename| filename == "-") {
+                let key = match (filenames.len(), recursive, key) {
+                    // there's no name to go by, so stdin is compressed whatever the key is
+                    (1, false, Some(value)) => suffixed_key(value, ""),
+                    (1, false, None) => {
+                        error!("Uploading from stdin needs --key to name the object");
+                        std::process::exit(exit::CONFIG);
+                    }
+                    _ => {
+                        error!("- can't be combined with other files or --recursive");
+                        std::process::exit(exit::CONFIG);
+                    }
+                };
+                if manifest.is_some() || upload_manifest || incremental.is_some() {
+                    error!("Manifests record files, there isn't one for stdin");
+                    std::process::exit(exit::CONFIG);
+                }
+                let client = client
+                    .with_progress((show_progress(&out) && !dry_run).then(Progress::new));
+                let started = Instant::now();
+                match client
+                    .upload_reader(tokio::io::stdin(), &key, &options)
+                    .await
+                {
+                    Ok(upload) => {
+                        out.result(
+                            || upload_record(&upload, dry_run).duration(started.elapsed()),
+                            || match dry_run {
+                                true => format!(
+                                    "Would upload stdin -> s3://{}/{}",
+                                    client.bucket(),
+                                    upload.key
+                                ),
+                                false => format_upload(&upload),
+                            },
+                        );
+                        out.record(&out.summary(planned_outcome(dry_run), "", exit::SUCCESS));
+                        return;
+                    }
+                    Err(error) => fail(&out, &error),
+                }
+            }
+            if key.is_some() && (recursive || filenames.len() > 1) {
+                error!("--key names a single file, use --prefix with --recursive");
+                std::process::exit(exit::CONFIG);
+            }
+
+            let mut files = Vec::new();
+            let mut ignored = 0;
+            for filename in filenames.iter() {
+                let path = PathBuf::from(filename);
+                match (recursive, path.is_dir()) {
+                    (true, true) => {
+                        let (found, errors) = match find_files(&path, "", &filter, links) {
+                            Ok(value) => value,
+                            Err(error) => fail(&out, &error),
+                        };
+                        for (path, error) in errors.iter() {
+                            warn!("Skipping {}: {}", path.display(), error);
+                        }
+                        ignored += errors.len();
+                        files.extend(found.into_iter().map(|file| FoundFile {
+                            key: upload_key(&file.key),
+                            path: file.path,
+                            symlink: file.symlink,
+                        }));
+                    }
+                    (false, true) => {
+                        warn!(
+                            "Skipping {}, it's a directory, use --recursive to upload what's in it",
+                            filename
+                        );
+                        ignored += 1;
+                    }
+                    _ => files.push(FoundFile {
+                        key: match key.clone() {
+                            Some(value) => suffixed_key(value, filename),
+                            None => upload_key(&default_key(&path)),
+                        },
+                        symlink: path.is_symlink(),
+                        path,
+                    }),
+                }
+            }
+
+            let mut carried = Vec::new();
+            let mut missing = Vec::new();
+            if let Some(path) = &incremental {
+                match read_manifest(path) {
+                    Ok(previous) => {
+                        let split = compare_previous(files, previous);
+                        files = split.changed;
+                        carried = split.unchanged;
+                        missing = split.missing;
+                    }
+                    Err(error) => warn!("{}, uploading every file", error),
+                }
+            }
+
+            let progress = (show_progress(&out) && !dry_run).then(|| match files.len() > 1 {
+                true => Progress::new().with_total(
+                    files
+                        .iter()
+                        .filter_map(|file| std::fs::metadata(&file.path).ok())
+                        .map(|metadata| metadata.len())
+                        .sum(),
+                ),
+                false => Progress::new(),
+            });
+            let client = client.with_progress(progress.clone());
+            info!(
+                "Uploading {} files to s3://{}/{}, {} at a time",
+                files.len(),
+                client.bucket(),
+                prefix,
+                jobs
+            );
+            let started = Instant::now();
+            let results = match dedupe {
+                true => {
+                    client
+                        .upload_files_deduplicated(&files, &options, jobs)
+                        .await
+                }
+                false => client.upload_files(&files, &options, jobs).await,
+            };
+            let elapsed = started.elapsed();
+            if let Some(progress) = progress {
+                progress.finish();
+            }
+            let storage_class = options.storage_class.as_ref().map(StorageClass::as_str);
+            let unchanged = carried.len();
+            let entries: Vec<ManifestEntry> = carried
+                .into_iter()
+                .chain(
+                    results
+                        .iter()
+                        .map(|(file, result)| ManifestEntry::new(file, result, storage_class)),
+                )
+                .collect();
+            let (uploaded, failed): (Vec<_>, Vec<_>) =
+                results.into_iter().partition(|(_, result)| result.is_ok());
+            let uploads: Vec<_> = uploaded
+                .iter()
+                .filter_map(|(_, result)| result.as_ref().ok())
+                .collect();
+            let skipped = uploads.iter().filter(|upload| upload.skipped).count();
+            let total_bytes: u64 = uploads
+                .iter()
+                .filter(|upload| !upload.skipped)
+                .map(|upload| upload.size)
+                .sum();
+            for (file, result) in uploaded.iter() {
+                if let Ok(upload) = result {
+                    out.result(
+                        || upload_record(upload, dry_run),
+                        || match (dry_run, dedupe) {
+                            (true, _) => format_planned(&file.path, upload, client.bucket()),
+                            (false, true) => {
+                                format!("{}: {}", file.path.display(), format_upload(upload))
+                            }
+                            (false, false) => format_upload(upload),
+                        },
+                    );
+                }
+            }
+            for (file, result) in failed.iter() {
+                if let Err(error) = result {
+                    report_error(error);
+                    out.record(&Record::failed("upload", Some(&file.key), error));
+                }
+            }
+            for entry in missing.iter() {
+                warn!(
+                    "{} is in the manifest but missing locally, its object is s3://{}/{}",
+                    entry.path.display(),
+                    client.bucket(),
+                    entry.object_key()
+                );
+            }
+            let mut removed = 0;
+            let mut delete_failed = Vec::new();
+            if delete && !missing.is_empty() {
+                // content is shared by every file with the same hash, so those stay put
+                let (shared, owned): (Vec<_>, Vec<_>) = missing
+                    .iter()
+                    .partition(|entry| entry.content_key.is_some());
+                for entry in shared.iter() {
+                    info!(
+                        "Not deleting s3://{}/{} for {}, other files may have the same content",
+                        client.bucket(),
+                        entry.object_key(),
+                        entry.path.display()
+                    );
+                }
+                let keys: Vec<String> = owned
+                    .iter()
+                    .map(|entry| entry.object_key().to_string())
+                    .collect();
+                match dry_run {
+                    true => {
+                        for key in keys.iter() {
+                            out.result(
+                                || Record::new("delete", Some(key)).outcome(Outcome::DryRun),
+                                || format!("Would delete s3://{}/{}", client.bucket(), key),
+                            );
+                        }
+                        removed = keys.len();
+                    }
+                    false => match client.delete_objects(&keys).await {
+                        Ok(results) => {
+                            for (key, result) in keys.iter().zip(results) {
+                                match result {
+                                    Ok(_) => {
+                                        out.result(
+                                            || Record::new("delete", Some(key)),
+                                            || format!("Deleted s3://{}/{}", client.bucket(), key),
+                                        );
+                                        removed += 1;
+                                    }
+                                    Err(error) => {
+                                        report_error(&error);
+                                        out.record(&Record::failed("delete", Some(key), &error));
+                                        delete_failed.push(exit::code(&error));
+                                    }
+                                }
+                            }
+                        }
+                        Err(error) => {
+                            report_error(&error);
+                            delete_failed = vec![exit::code(&error); keys.len()];
+                        }
+                    },
+                }
+            }
+            if out.is_text() && (recursive || filenames.len() > 1) {
+                let mut summary = match dry_run {
+                    true => format!(
+                        "Would upload {} files ({}), {} skipped, {} failed",
+                        uploads.len() - skipped,
+                        format_size(total_bytes),
+                        skipped,
+                        failed.len()
+                    ),
+                    false => format!(
+                        "Uploaded {} files ({} bytes) at {}, {} skipped, {} failed",
+                        uploads.len() - skipped,
+                        total_bytes,
+                        format_rate(total_bytes, elapsed),
+                        skipped,
+                        failed.len()
+                    ),
+                };
+                if ignored > 0 {
+                    summary.push_str(&format!(", {} ignored", ignored));
+                }
+                if incremental.is_some() {
+                    summary.push_str(&format!(", {} unchanged since the manifest", unchanged));
+                }
+                if !missing.is_empty() {
+                    summary.push_str(&format!(", {} missing locally", missing.len()));
+                }
+                if delete {
+                    summary.push_str(&format!(
+                        ", {} {}",
+                        match dry_run {
+                            true => "would delete",
+                            false => "deleted",
+                        },
+                        removed
+                    ));
+                }
+                out.text(summary);
+                for (file, _) in failed.iter() {
+                    out.text(format!("  failed: {}", file.path.display()));
+                }
+            }
+            let manifest = manifest.or_else(|| incremental.filter(|_| !dry_run));
+            if let Some(path) = &manifest {
+                if let Err(error) = write_manifest(path, &entries) {
+                    error!("Failed to write manifest {}: {}", path.display(), error);
+                    exit_with(&out, exit::TRANSFER, "");
+                }
+            }
+            if upload_manifest {
+                let manifest_object = join_key(&prefix, &manifest_key(now));
+                let lines = to_json_lines(&entries);
+                let manifest_options = UploadOptions {
+                    content_type: Some(String::from("application/x-ndjson")),
+                    server_side_encryption: options.server_side_encryption.clone(),
+                    sse_kms_key_id: options.sse_kms_key_id.clone(),
+                    sse_bucket_key: options.sse_bucket_key,
+                    dry_run,
+                    ..Default::default()
+                };
+                let client = client.clone().with_progress(None);
+                match client
+                    .upload_reader(lines.as_bytes(), &manifest_object, &manifest_options)
+                    .await
+                {
+                    Ok(upload) => out.result(
+                        || upload_record(&upload, dry_run),
+                        || {
+                            format!(
+                                "{} the manifest to s3://{}/{}",
+                                match dry_run {
+                                    true => "Would upload",
+                                    false => "Uploaded",
+                                },
+                                client.bucket(),
+                                manifest_object
+                            )
+                        },
+                    ),
+                    Err(error) => fail(&out, &error),
+                }
+            }
+            if !failed.is_empty() || ignored > 0 || !delete_failed.is_empty() {
+                let codes = failed
+                    .iter()
+                    .filter_map(|(_, result)| result.as_ref().err())
+                    .map(exit::code)
+                    .chain(delete_failed)
+                    .chain((0..ignored).map(|_| exit::FAILURE));
+                exit_with(&out, exit::combine(codes), "");
+            }
+            Ok(String::new())
+        }
+        Commands::Cat { key } => {
+            let mut stdout = tokio::io::stdout();
+            match client.download_to(&key, &mut stdout).await {
+                Ok(_) => Ok(String::new()),
+                // the reader went away, eg piped into head, which isn't a failure
+                Err(S3Error::Write { source, .. })
+                    if source.kind() == std::io::ErrorKind::BrokenPipe =>
+                {
+                    Ok(String::new())
+                }
+                Err(error) => Err(error),
+            }
+        }
+        Commands::Download {
+            key,
+            dest,
+            force,
+            range,
+            concurrency,
+            chunk_size,
+            decrypt,
+            decompress,
+            no_verify,
+            no_resume,
+        } => {
+            let decryption_key = match (decrypt, encryption_key_setting) {
+                (true, None) => {
+                    error!(
+                        "--decrypt needs a key from --encryption-key-file or backup_s3_encryption_key_file"
+                    );
+                    std::process::exit(exit::CONFIG);
+                }
+                (true, key) => key,
+                (false, _) => None,
+            };
+            let mut name = match decrypt {
+                true => key.strip_suffix(ENCRYPTED_SUFFIX).unwrap_or(&key),
+                false => &key,
+            };
+            let format = match decompress {
+                true => match Format::from_key(name) {
+                    Some(format) => Some(format),
+                    None => {
+                        error!(
+                            "--decompress can't tell how {} was compressed, it doesn't end in .gz or .zst",
+                            name
+                        );
+                        std::process::exit(exit::CONFIG);
+                    }
+                },
+                false => None,
+            };
+            if let Some(format) = format {
+                name = name.strip_suffix(format.extension()).unwrap_or(name);
+            }
+            let dest = dest.unwrap_or_else(|| PathBuf::from(name));
+            let options = DownloadOptions {
+                force,
+                range,
+                concurrency,
+                chunk_size: chunk_size * 1024 * 1024,
+                decryption_key,
+                decompress: format,
+                verify: !no_verify,
+                resume: !no_resume,
+            };
+            let started = Instant::now();
+            client
+                .with_progress(show_progress(&out).then(Progress::new))
+                .download(&key, &dest, &options)
+                .await
+                .map(|download| {
+                    if out.is_json() {
+                        return out.reply(
+                            || {
+                                Record::new("download", Some(&download.key))
+                                    .size(download.size)
+                                    .etag(&download.etag)
+                                    .detail(&download)
+                            },
+                            String::new,
+                        );
+                    }
+                    let mut line = format!(
+                        "Downloaded {} to {} ({} bytes) at {}",
+                        download.key,
+                        download.path.display(),
+                        download.size,
+                        format_rate(download.size, started.elapsed())
+                    );
+                    if let Some((start, end)) = download.range {
+                        line.push_str(&format!(" from bytes {}-{}", start, end));
+                    }
+                    if let Some(algorithm) = download.checksum_algorithm {
+                        line.push_str(&format!(", {} checksum verified", algorithm));
+                    }
+                    line
+                })
+        }
+        Commands::Copy(args) | Commands::Move(args) => {
+            let CopyArgs {
+                source,
+                dest,
+                dest_bucket,
+                metadata_directive,
+                content_type,
+                cache_control,
+                content_disposition,
+                content_encoding,
+                expires,
+                metadata,
+                tag,
+                storage_class,
+                acl,
+                sse,
+                sse_kms_key_id,
+                sse_bucket_key,
+                part_size,
+                concurrency,
+            } = *args;
+            let replaces = content_type.is_some()
+                || cache_control.is_some()
+                || content_disposition.is_some()
+                || content_encoding.is_some()
+                || expires.is_some()
+                || !metadata.is_empty();
+            if replaces && metadata_directive != MetadataDirective::Replace {
+                error!("Changing the content type, headers or metadata needs --metadata-directive REPLACE");
+                std::process::exit(exit::CONFIG);
+            }
+            if let Some(Err(error)) = dest_bucket.as_deref().map(validate_bucket_name) {
+                error!("{}", error);
+                std::process::exit(exit::CONFIG);
+            }
+            let same_bucket = dest_bucket
+                .as_deref()
+                .is_none_or(|bucket| bucket == client.bucket());
+            if moving && same_bucket && source == dest {
+                error!("Can't move {} onto itself", source);
+                std::process::exit(exit::CONFIG);
+            }
+            if sse.is_some() {
+                configuration.backup_s3_sse = sse;
+            }
+            if sse_kms_key_id.is_some() {
+                configuration.backup_s3_sse_kms_key_id = sse_kms_key_id;
+            }
+            if sse_bucket_key {
+                configuration.backup_s3_sse_bucket_key = Some(true);
+            }
+            let server_side_encryption = match configuration.server_side_encryption() {
+                Ok(value) => value,
+                Err(error) => {
+                    error!("{}", error);
+                    std::process::exit(exit::CONFIG);
+                }
+            };
+            let options = CopyOptions {
+                dest_bucket,
+                metadata_directive,
+                attributes: UploadOptions {
+                    part_size: part_size * 1024 * 1024,
+                    concurrency,
+                    content_type,
+                    cache_control,
+                    content_disposition,
+                    content_encoding,
+                    expires,
+                    metadata: metadata_setting.into_iter().chain(metadata).collect(),
+                    tags: tag.into_iter().collect(),
+                    storage_class: storage_class.or(storage_class_setting),
+                    acl: acl.or(acl_setting),
+                    server_side_encryption,
+                    sse_kms_key_id: configuration.backup_s3_sse_kms_key_id.clone(),
+                    sse_bucket_key: configuration.backup_s3_sse_bucket_key,
+                    ..UploadOptions::default()
+                },
+            };
+            if let Err(error) = validate_metadata(&options.attributes.metadata)
+                .and_then(|_| validate_tags(&options.attributes.tags))
+            {
+                error!("{}", error);
+                std::process::exit(exit::CONFIG);
+            }
+            let action = match moving {
+                true => "move",
+                false => "copy",
+            };
+            match dry_run {
+                true => client.head(&source).await.map(|info| {
+                    out.reply(
+                        || {
+                            Record::new(action, Some(&source))
+                                .outcome(Outcome::DryRun)
+                                .size(info.size)
+                                .etag(&info.etag)
+                        },
+                        || {
+                            format!(
+                                "Would {} s3://{}/{} ({}) to s3://{}/{}",
+                                action,
+                                client.bucket(),
+                                source,
+                                format_size(info.size),
+                                options.dest_bucket.as_deref().unwrap_or(client.bucket()),
+                                dest
+                            )
+                        },
+                    )
+                }),
+                false if moving => client
+                    .rename(&source, &dest, &options)
+                    .await
+                    .map(|moved| {
+                        out.reply(
+                            || copy_record(action, &moved.copy).detail(&moved),
+                            || format_move(&moved),
+                        )
+                    }),
+                false => client.copy(&source, &dest, &options).await.map(|copy| {
+                    out.reply(
+                        || copy_record(action, &copy).detail(&copy),
+                        || format_copy(&copy),
+                    )
+                }),
+            }
+        }
+        Commands::Head { key } => client.head(&key).await.map(|info| {
+            out.reply(
+                || {
+                    Record::new("head", Some(&key))
+                        .size(info.size)
+                        .etag(&info.etag)
+                        .detail(&info)
+                },
+                || format_file_info(&info),
+            )
+        }),
+        Commands::Presign {
+            key,
+            method,
+            expires_in,
+            content_disposition,
+            content_type,
+            curl,
+        } => {
+            let url = match method.as_str() {
+                "put" if content_disposition.is_some() => {
+                    error!("--content-disposition only applies to get");
+                    std::process::exit(exit::CONFIG);
+                }
+                "put" => {
+                    client
+                        .presign_put(&key, expires_in, content_type.as_deref())
+                        .await
+                }
+                _ if content_type.is_some() => {
+                    error!("--content-type only applies to put");
+                    std::process::exit(exit::CONFIG);
+                }
+                _ => {
+                    client
+                        .presign_get(&key, expires_in, content_disposition.as_deref())
+                        .await
+                }
+            };
+            url.map(|url| {
+                out.reply(
+                    || {
+                        Record::new("presign", Some(&key))
+                            .detail(&serde_json::json!({ "url": url }))
+                    },
+                    || match curl {
+                        true => curl_command(&method, &key, &url, content_type.as_deref()),
+                        false => url.clone(),
+                    },
+                )
+            })
+        }
+        Commands::Delete {
+            keys,
+            recursive: true,
+            all_versions,
+            yes,
+            ..
+        } => match keys.as_slice() {
+            [location] => delete_prefix(&client, location, all_versions, yes, &out, dry_run).await,
+            _ => {
+                error!("--recursive takes one prefix");
+                std::process::exit(exit::CONFIG);
+            }
+        },
+        Commands::Delete {
+            keys,
+            version_id: Some(version_id),
+            ..
+        } => {
+            let [key] = keys.as_slice() else {
+                error!("--version-id takes one key");
+                std::process::exit(exit::CONFIG);
+            };
+            match dry_run {
+                true => client.versions_of(key).await.and_then(|versions| {
+                    let version = versions
+                        .into_iter()
+                        .find(|version| version.version_id == version_id)
+                        .ok_or_else(|| S3Error::VersionNotFound {
+                            key: key.clone(),
+                            version_id: version_id.clone(),
+                        })?;
+                    Ok(out.reply(
+                        || {
+                            Record::new("delete", Some(key))
+                                .outcome(Outcome::DryRun)
+                                .size(version.size)
+                                .detail(&version)
+                        },
+                        || {
+                            format!(
+                                "Would destroy s3://{}/{} version {}{}",
+                                client.bucket(),
+                                key,
+                                version_id,
+                                match version.delete_marker {
+                                    true => String::from(", a delete marker"),
+                                    false => format!(" ({})", format_size(version.size)),
+                                }
+                            )
+                        },
+                    ))
+                }),
+                false => client
+                    .delete_version(key, &version_id)
+                    .await
+                    .map(|delete| {
+                        out.reply(
+                            || Record::new("delete", Some(key)).detail(&delete),
+                            || format_delete(&delete),
+                        )
+                    }),
+            }
+        }
+        Commands::Delete { keys, .. } => {
+            let keys = match keys.iter().any(|key| key == "-") {
+                true if keys.len() > 1 => {
+                    error!("- can't be combined with other keys");
+                    std::process::exit(exit::CONFIG);
+                }
+                true => read_keys(),
+                false => keys,
+            };
+            if keys.is_empty() {
+                error!("No keys to delete");
+                std::process::exit(exit::CONFIG);
+            }
+            delete_keys(&client, &keys, &out, dry_run).await
+        }
+        Commands::Undelete { key, .. } if dry_run => {
+            client.plan_undelete(&key).await.map(|(markers, restored)| {
+                out.reply(
+                    || {
+                        Record::new("undelete", Some(&key))
+                            .outcome(Outcome::DryRun)
+                            .detail(&serde_json::json!({
+                                "removed": markers,
+                                "version_id": restored.version_id,
+                            }))
+                    },
+                    || {
+                        format!(
+                            "Would remove {} delete marker{} from s3://{}/{}, making version {} current",
+                            markers.len(),
+                            if markers.len() == 1 { "" } else { "s" },
+                            client.bucket(),
+                            key,
+                            restored.version_id
+                        )
+                    },
+                )
+            })
+        }
+        Commands::Undelete { key } => client.undelete(&key).await.map(|undelete| {
+            out.reply(
+                || Record::new("undelete", Some(&key)).detail(&undelete),
+                || {
+                    format!(
+                        "Undeleted {}, removed delete marker {}, version {} is current",
+                        undelete.key,
+                        undelete.removed.join(", "),
+                        undelete.version_id
+                    )
+                },
+            )
+        }),
+        Commands::Versions { key_or_prefix } => list_versions(&client, &key_or_prefix, &out).await,
+        Commands::List {
+            location,
+            prefix: list_prefix,
+            recursive,
+            max_items,
+            filter,
+            order,
+            format,
+        } => {
+            let filter = match filter.build() {
+                Ok(filter) => filter,
+                Err(error) => {
+                    error!("{}", error);
+                    std::process::exit(exit::CONFIG);
+                }
+            };
+            let (client, listed) = listing_location(
+                &client,
+                &prefix,
+                location.as_deref().or(list_prefix.as_deref()),
+            );
+            let listing = Listing {
+                recursive,
+                max_items,
+                filter,
+            };
+            list_objects(&client, &listed, &listing, &order, &format, &out).await
+        }
+        Commands::Du { location, depth } => {
+            let (client, listed) = listing_location(&client, &prefix, location.as_deref());
+            // a folder, so hosts doesn't also count hosts-old/
+            disk_usage(&client, &join_key(&listed, ""), depth, &out).await
+        }
+        Commands::Prune {
+            location,
+            keep_days,
+            keep_count,
+            filter,
+            yes,
+        } => {
+            let filter = match filter.build() {
+                Ok(filter) => filter,
+                Err(error) => {
+                    error!("{}", error);
+                    std::process::exit(exit::CONFIG);
+                }
+            };
+            let retention = prune::Retention {
+                keep_days,
+                keep_count,
+            };
+            let (client, listed) = listing_location(&client, &prefix, location.as_deref());
+            // a folder, so hosts doesn't also prune hosts-old/
+            let listed = join_key(&listed, "");
+            prune_backups(&client, &listed, &filter, &retention, yes, &out, dry_run).await
+        }
+        Commands::Multipart {
+            command: MultipartCommands::List,
+        } => client.list_multipart_uploads(true).await.map(|uploads| {
+            uploads
+                .iter()
+                .filter_map(|upload| {
+                    let record = Record::new("multipart list", Some(&upload.key)).detail(upload);
+                    out.record(&match upload.size {
+                        Some(size) => record.size(size),
+                        None => record,
+                    });
+                    (!out.is_json()).then(|| format!(
+                        "{}\t{}\t{}\t{} parts\t{} bytes",
+                        upload.upload_id,
+                        upload.key,
+                        upload.initiated.map(format_time).unwrap_or_default(),
+                        upload.parts.unwrap_or_default(),
+                        upload.size.unwrap_or_default()
+                    ))
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }),
+        Commands::Multipart {
+            command:
+                MultipartCommands::Abort {
+                    upload_id,
+                    older_than,
+                },
+        } => abort_multipart_uploads(&client, upload_id, older_than, &out).await,
+        Commands::Bucket {
+            command: BucketCommands::Create { name },
+        } => {
+            let client = bucket_client(&client, name);
+            match dry_run {
+                true => Ok(format!("Would create bucket {}", client.bucket())),
+                false => client.create_bucket().await.map(|created| match created {
+                    true => format!("Created bucket {}", client.bucket()),
+                    false => format!(
+                        "Bucket {} already exists and is owned by you, leaving it as it is",
+                        client.bucket()
+                    ),
+                }),
+            }
+        }
+        Commands::Bucket {
+            command: BucketCommands::Delete { name, force },
+        } => delete_bucket(&bucket_client(&client, name), force, &out, dry_run).await,
+        Commands::Bucket {
+            command:
+                BucketCommands::Versioning {
+                    command: VersioningCommands::Get,
+                },
+        } => client.versioning().await.map(|versioning| {
+            let summary = format!(
+                "Versioning on {} is {}, MFA delete is {}",
+                client.bucket(),
+                versioning.status.as_deref().unwrap_or("not enabled"),
+                versioning.mfa_delete.as_deref().unwrap_or("Disabled")
+            );
+            if !versioning.is_enabled() {
+                let reply = out.reply(
+                    || Record::new("bucket versioning get", None).detail(&versioning),
+                    || summary,
+                );
+                if !reply.is_empty() {
+                    out.text(reply);
+                }
+                // so a cron job can tell this apart from failing to check
+                exit_with(&out, exit::VERIFICATION, "");
+            }
+            out.reply(
+                || Record::new("bucket versioning get", None).detail(&versioning),
+                || summary,
+            )
+        }),
+        Commands::Bucket {
+            command: BucketCommands::Versioning { command },
+        } => {
+            let enabled = matches!(command, VersioningCommands::Enable);
+            let (action, done) = match enabled {
+                true => ("enable", "enabled"),
+                false => ("suspend", "suspended"),
+            };
+            match dry_run {
+                true => Ok(format!(
+                    "Would {} versioning on {}",
+                    action,
+                    client.bucket()
+                )),
+                false => client
+                    .set_versioning(enabled)
+                    .await
+                    .map(|()| format!("Versioning on {} is {}", client.bucket(), done)),
+            }
+        }
+        Commands::Bucket {
+            command:
+                BucketCommands::Tags {
+                    command: BucketTagCommands::Get { output },
+                },
+        } => client
+            .get_bucket_tags()
+            .await
+            .map(|tags| {
+                out.reply(
+                    || Record::new("bucket tags get", None).detail(&tags),
+                    || match (tags.is_empty(), output.as_str()) {
+                        (true, "table") => format!("No tags on {}", client.bucket()),
+                        _ => format_bucket_tags(&tags, &output),
+                    },
+                )
+            }),
+        Commands::Bucket {
+            command:
+                BucketCommands::Tags {
+                    command: BucketTagCommands::Set { tags, replace },
+                },
+        } => {
+            let tags: BTreeMap<String, String> = tags.into_iter().collect();
+            let merged = match replace {
+                true => Ok(tags),
+                false => client.get_bucket_tags().await.map(|mut existing| {
+                    existing.extend(tags);
+                    existing
+                }),
+            };
+            match (merged, dry_run) {
+                (Ok(tags), true) => Ok(format!(
+                    "Would set {} tags on {}:\n{}",
+                    tags.len(),
+                    client.bucket(),
+                    format_bucket_tags(&tags, "table")
+                )),
+                (Ok(tags), false) => client.set_bucket_tags(&tags).await.map(|()| {
+                    format!(
+                        "Set {} tags on {}:\n{}",
+                        tags.len(),
+                        client.bucket(),
+                        format_bucket_tags(&tags, "table")
+                    )
+                }),
+                (Err(error), _) => Err(error),
+            }
+        }
+        Commands::Lifecycle {
+            command: LifecycleCommands::Get,
+        } => client.lifecycle_rules().await.map(|rules| {
+            let rules: Vec<Rule> = rules.iter().map(Rule::from).collect();
+            out.reply(
+                || Record::new("lifecycle get", None).detail(&rules),
+                || match rules.is_empty() {
+                    true => format!("No lifecycle rules on {}", client.bucket()),
+                    false => format_rules(&rules),
+                },
+            )
+        }),
+        Commands::Lifecycle {
+            command: LifecycleCommands::Set(args),
+        } => {
+            let mut rule = Rule {
+                enabled: !args.disabled,
+                expire_days: args.expire_days,
+                noncurrent_expire_days: args.noncurrent_expire_days,
+                abort_multipart_days: args.abort_multipart_days,
+                transition_days: args.transition_days,
+                transition_storage_class: args.transition_storage_class,
+                ..Rule::new(&args.prefix)
+            };
+            if let Some(id) = args.id {
+                rule.id = id;
+            }
+            if !rule.has_action() {
+                error!("Give at least one of --expire-days, --noncurrent-expire-days, --abort-multipart-days or --transition-days");
+                std::process::exit(exit::CONFIG);
+            }
+            match dry_run {
+                true => Ok(format!(
+                    "Would set lifecycle rule {} on {}:\n{}",
+                    rule.id,
+                    client.bucket(),
+                    format_rules(std::slice::from_ref(&rule))
+                )),
+                false => client.set_lifecycle_rule(&rule).await.map(|()| {
+                    format!(
+                        "Set lifecycle rule {} on {}:\n{}",
+                        rule.id,
+                        client.bucket(),
+                        format_rules(std::slice::from_ref(&rule))
+                    )
+                }),
+            }
+        }
+        Commands::Restore {
+            command: Some(RestoreCommands::Status { key }),
+            ..
+        } => client.head(&key).await.map(|info| {
+            out.reply(
+                || {
+                    Record::new("restore status", Some(&key))
+                        .size(info.size)
+                        .etag(&info.etag)
+                        .detail(&serde_json::json!({
+                            "storage_class": info.storage_class,
+                            "needs_restore": needs_restore(&info.storage_class),
+                            "restore": info.restore,
+                        }))
+                },
+                || format_restore_status(&key, &info.storage_class, info.restore.as_ref()),
+            )
+        }),
+        Commands::Verify {
+            local,
+            location,
+            manifest,
+            checksum,
+            include,
+            exclude,
+            jobs,
+        } => {
+            let filter = match PathFilter::new(&include, &exclude, false) {
+                Ok(value) => value,
+                Err(error) => {
+                    error!("{}", error);
+                    std::process::exit(exit::CONFIG);
+                }
+            };
+            let (client, files, listed) = match manifest {
+                Some(manifest) => match load_manifest(&client, &prefix, &manifest).await {
+                    Ok(entries) => {
+                        let files: Vec<FoundFile> = entries
+                            .into_iter()
+                            .filter(|entry| entry.is_stored() && filter.matches(&entry.key))
+                            .map(|entry| FoundFile {
+                                key: entry.object_key().to_string(),
+                                path: entry.path,
+                                symlink: entry.symlink,
+                            })
+                            .collect();
+                        (client.clone(), files, None)
+                    }
+                    Err(error) => fail(&out, &error),
+                },
+                None => {
+                    let local = local.unwrap_or_default();
+                    if !local.is_dir() {
+                        error!("{} isn't a directory", local.display());
+                        std::process::exit(exit::CONFIG);
+                    }
+                    let (client, listed) = listing_location(&client, &prefix, location.as_deref());
+                    // a folder, so hosts doesn't also match hosts-old/
+                    let listed = join_key(&listed, "");
+                    let (files, unreadable) =
+                        match find_files(&local, &listed, &filter, Links::Follow) {
+                            Ok(value) => value,
+                            Err(error) => fail(&out, &error),
+                        };
+                    for (path, error) in unreadable.iter() {
+                        warn!("Skipping {}: {}", path.display(), error);
+                    }
+                    (client, files, Some(listed))
+                }
+            };
+            verify::verify(&client, &files, listed.as_deref(), checksum, jobs)
+                .await
+                .map(|checks| report_checks(&checks, client.bucket(), &out))
+        }
+        Commands::Restore {
+            command: None,
+            args:
+                RestoreArgs {
+                    manifest: Some(manifest),
+                    dest: Some(dest),
+                    include,
+                    exclude,
+                    jobs,
+                    ..
+                },
+        } => {
+            let filter = match PathFilter::new(&include, &exclude, false) {
+                Ok(value) => value,
+                Err(error) => {
+                    error!("{}", error);
+                    std::process::exit(exit::CONFIG);
+                }
+            };
+            match load_manifest(&client, &prefix, &manifest).await {
+                Ok(entries) => {
+                    restore_manifest(&client, entries, &dest, &filter, jobs, &out, dry_run).await
+                }
+                Err(error) => Err(error),
+            }
+        }
+        Commands::Restore {
+            command: None,
+            args,
+        } => restore_objects(&client, args, &out, dry_run).await,
+        Commands::Retention {
+            command: RetentionCommands::Get { key },
+        } => client.retention(&key).await.map(|retention| {
+            out.reply(
+                || Record::new("retention get", Some(&key)).detail(&retention),
+                || match &retention {
+                    Some(retention) => format!(
+                        "{} is under {} retention until {}",
+                        key,
+                        retention.mode,
+                        retention
+                            .retain_until
+                            .map(format_time)
+                            .unwrap_or_else(|| String::from("an unknown time"))
+                    ),
+                    None => format!("{} has no retention", key),
+                },
+            )
+        }),
+        Commands::LegalHold {
+            command: LegalHoldCommands::Get { key },
+        } => client.legal_hold(&key).await.map(|on| {
+            format!(
+                "The legal hold on {} is {}",
+                key,
+                if on { "on" } else { "off" }
+            )
+        }),
+        Commands::LegalHold {
+            command: LegalHoldCommands::Set { key, status },
+        } => match dry_run {
+            true => Ok(format!("Would turn the legal hold on {} {}", key, status)),
+            false => client
+                .set_legal_hold(&key, status == "on")
+                .await
+                .map(|()| format!("Turned the legal hold on {} {}", key, status)),
+        },
+        Commands::Tag {
+            command: TagCommands::Get { key },
+        } => client.get_tags(&key).await.map(|tags| {
+            out.reply(
+                || Record::new("tag get", Some(&key)).detail(&tags),
+                || {
+                    tags.iter()
+                        .map(|(name, value)| format!("{}={}", name, value))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                },
+            )
+        }),
+        Commands::Tag {
+            command: TagCommands::Set { key, tags },
+        } => {
+            let tags = tags.into_iter().collect();
+            client
+                .set_tags(&key, &tags)
+                .await
+                .map(|()| format!("Set {} tags on {}", tags.len(), key))
+        }
+        Commands::Sync(SyncArgs {
+            source,
+            dest,
+            compare,
+            include,
+            exclude,
+            ignore_case,
+            links,
+            delete,
+            allow_empty_source,
+            jobs,
+            multipart_threshold,
+            part_size,
+            incremental,
+            no_verify,
+        }) => {
+            let filter = match PathFilter::new(&include, &exclude, ignore_case) {
+                Ok(value) => value,
+                Err(error) => {
+                    error!("{}", error);
+                    std::process::exit(exit::CONFIG);
+                }
+            };
+            let settings = SyncSettings {
+                filter,
+                compare,
+                links,
+                delete,
+                allow_empty_source,
+                jobs,
+                dry_run,
+                incremental,
+                verify: !no_verify,
+                out: out.clone(),
+            };
+            let location = |value: &str| match value.parse::<S3Location>() {
+                Ok(value) => value,
+                Err(error) => {
+                    error!("{}", error);
+                    std::process::exit(exit::CONFIG);
+                }
+            };
+            match (S3Location::is_s3(&source), S3Location::is_s3(&dest)) {
+                (false, true) => {
+                    let dest = location(&dest);
+                    let source = PathBuf::from(source);
+                    if !source.is_dir() {
+                        error!("{} isn't a directory", source.display());
+                        std::process::exit(exit::CONFIG);
+                    }
+                    let server_side_encryption = match configuration.server_side_encryption() {
+                        Ok(value) => value,
+                        Err(error) => {
+                            error!("{}", error);
+                            std::process::exit(exit::CONFIG);
+                        }
+                    };
+                    if multipart_threshold.is_some() {
+                        configuration.backup_s3_multipart_threshold = multipart_threshold;
+                    }
+                    if part_size.is_some() {
+                        configuration.backup_s3_part_size = part_size;
+                    }
+                    let options = UploadOptions {
+                        multipart_threshold: configuration.multipart_threshold(),
+                        part_size: configuration.part_size(),
+                        metadata: metadata_setting,
+                        storage_class: storage_class_setting,
+                        acl: acl_setting,
+                        server_side_encryption,
+                        sse_kms_key_id: configuration.backup_s3_sse_kms_key_id.clone(),
+                        sse_bucket_key: configuration.backup_s3_sse_bucket_key,
+                        dry_run,
+                        ..UploadOptions::default()
+                    };
+                    let client = client.with_bucket(&dest.bucket);
+                    sync_to_s3(&client, &source, &dest, &settings, &options).await
+                }
+                (true, false) if settings.incremental.is_some() => {
+                    error!("--incremental only works when syncing to S3");
+                    std::process::exit(exit::CONFIG);
+                }
+                (true, false) => {
+                    let source = location(&source);
+                    let dest = PathBuf::from(dest);
+                    if dest.exists() && !dest.is_dir() {
+                        error!("{} isn't a directory", dest.display());
+                        std::process::exit(exit::CONFIG);
+                    }
+                    let client = client.with_bucket(&source.bucket);
+                    sync_from_s3(&client, &source, &dest, &settings).await
+                }
+                _ => {
+                    error!("One of the source and destination has to be a local directory and the other s3://bucket/prefix");
+                    std::process::exit(exit::CONFIG);
+                }
+            }
+        }
+        Commands::Bench {
+            size,
+            parts,
+            concurrency,
+        } => {
+            let part_size = size.div_ceil(parts).max(1);
+            if parts > 1 && part_size < MIN_PART_SIZE {
+                error!(
+                    "{} in {} parts is under the {} S3 allows for a part, use fewer parts or a bigger --size",
+                    format_size(size),
+                    parts,
+                    format_size(MIN_PART_SIZE)
+                );
+                std::process::exit(exit::CONFIG);
+            }
+            let settings = (size, part_size, concurrency.max(1));
+            bench(&client, &prefix, settings, &out, dry_run).await
+        }
+    };
+
+    match result {
+        Ok(response) => {
+            if !response.is_empty() {
+                out.text(&response);
+            }
+            out.print_stats();
+            out.record(&out.summary(planned_outcome(dry_run), &response, exit::SUCCESS));
+        }
+        Err(error) => fail(&out, &error),
+    }
+}
+
+/// Reports `error` and exits with its code, for JSON output after a final record with both
+fn fail(out: &Output, error: &S3Error) -> ! {
+    report_error(error);
+    out.print_stats();
+    let code = exit::code(error);
+    out.record(
+        &Record::failed(out.operation(), None, error)
+            .duration(out.elapsed())
+            .exit_code(code),
     );
-    println!("DELETE test_file.txt");
-    eprintln!(
-        "{:?}",
-        s3_delete_file(
-            "test_file.txt",
-            aws_client.to_owned(),
-            configuration.backup_s3_bucket.as_str()
+    std::process::exit(code);
+}
+
+/// Exits with `code` after the text summary has been printed, for JSON output after a final
+/// record with the summary and code instead
+fn exit_with(out: &Output, code: i32, summary: &str) -> ! {
+    out.print_stats();
+    out.record(&out.summary(Outcome::Failed, summary, code));
+    std::process::exit(code);
+}
+
+/// The subcommand that was run, eg `bucket tags get`, for records
+fn operation_name(matches: &ArgMatches) -> String {
+    let mut names = Vec::new();
+    let mut matches = matches;
+    while let Some((name, subcommand)) = matches.subcommand() {
+        names.push(name);
+        matches = subcommand;
+    }
+    names.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The only test in the binary, so nothing else runs with the variable set
+    #[test]
+    fn config_path_from_environment() {
+        let path = std::env::temp_dir().join("s3upload-config-env-test.toml");
+        std::fs::write(
+            &path,
+            "backup_s3_bucket = \"from-env\"\nbackup_s3_region = \"us-east-1\"\n",
         )
-        .await
-    );
+        .unwrap();
+
+        std::env::set_var("S3UPLOAD_CONFIG", &path);
+        let cli = Cli::try_parse_from(["rust-test-s3-upload", "check"]).unwrap();
+        assert_eq!(cli.config, path);
+        let configuration = S3Configuration::load(&cli.config, &cli.profile).unwrap();
+        assert_eq!(configuration.backup_s3_bucket, "from-env");
+
+        // the option still wins over the variable
+        let cli = Cli::try_parse_from(["rust-test-s3-upload", "--config", "other.toml", "check"])
+            .unwrap();
+        assert_eq!(cli.config, Path::new("other.toml"));
+
+        std::env::remove_var("S3UPLOAD_CONFIG");
+        let cli = Cli::try_parse_from(["rust-test-s3-upload", "check"]).unwrap();
+        assert_eq!(cli.config, Path::new("config.toml"));
+        std::fs::remove_file(&path).unwrap();
+    }
 }
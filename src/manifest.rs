@@ -0,0 +1,367 @@
+//! Manifests record what an upload run did, one line of JSON per file, so a later run can restore
+//! or check exactly what was uploaded
+//!
+use crate::checksum::{etag_is_md5, parse_checksum_algorithm, Checksum, ContentMd5};
+use crate::client::UploadResult;
+use crate::error::S3Error;
+use crate::walk::FoundFile;
+use aws_sdk_s3::primitives::{DateTime, DateTimeFormat};
+use aws_sdk_s3::types::Object;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use thiserror::Error;
+
+/// Where uploaded manifests go, under the prefix the files were uploaded to
+pub const MANIFEST_PREFIX: &str = "manifests/";
+
+/// What happened to a file in the run
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Uploaded,
+    /// The object already matched the file, see [UploadOptions::if_changed](crate::client::UploadOptions::if_changed)
+    Skipped,
+    /// Nothing was sent, the run was a dry run
+    DryRun,
+    Failed,
+}
+
+/// A file from an upload run and where it went
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub path: PathBuf,
+    pub key: String,
+    /// Where the file is actually stored when it was uploaded by its content, see
+    /// [crate::dedupe], `key` is then only the name it was uploaded under
+    pub content_key: Option<String>,
+    pub size: u64,
+    /// When the local file was last modified
+    pub modified: Option<SystemTime>,
+    /// Whether the file was found through a symlink, see [FoundFile::symlink], so a restore that
+    /// writes a regular file in its place can be accounted for
+    #[serde(default)]
+    pub symlink: bool,
+    pub etag: Option<String>,
+    pub version_id: Option<String>,
+    pub checksum_algorithm: Option<String>,
+    pub checksum: Option<String>,
+    pub storage_class: Option<String>,
+    pub server_side_encryption: Option<String>,
+    pub sse_kms_key_id: Option<String>,
+    /// When the upload finished
+    pub timestamp: SystemTime,
+    pub outcome: Outcome,
+    /// Why the upload failed
+    pub error: Option<String>,
+}
+
+impl ManifestEntry {
+    /// The entry for a file from [S3Client::upload_files](crate::client::S3Client::upload_files),
+    /// `storage_class` is the one the upload asked for
+    pub fn new(
+        file: &FoundFile,
+        result: &Result<UploadResult, S3Error>,
+        storage_class: Option<&str>,
+    ) -> Self {
+        let metadata = std::fs::metadata(&file.path).ok();
+        let mut entry = Self {
+            path: file.path.clone(),
+            key: file.key.clone(),
+            content_key: None,
+            size: metadata.as_ref().map_or(0, |metadata| metadata.len()),
+            modified: metadata.and_then(|metadata| metadata.modified().ok()),
+            symlink: file.symlink,
+            etag: None,
+            version_id: None,
+            checksum_algorithm: None,
+            checksum: None,
+            storage_class: storage_class.map(str::to_string),
+            server_side_encryption: None,
+            sse_kms_key_id: None,
+            timestamp: SystemTime::now(),
+            outcome: Outcome::Failed,
+            error: None,
+        };
+        match result {
+            Ok(upload) => {
+                // the upload goes somewhere other than the file's key when it's by content
+                if upload.key != file.key {
+                    entry.content_key = Some(upload.key.clone());
+                }
+                entry.size = upload.size;
+                entry.etag = Some(upload.etag.clone());
+                entry.version_id = upload.version_id.clone();
+                entry.checksum_algorithm = upload.checksum_algorithm.clone();
+                entry.checksum = upload.checksum.clone();
+                entry.server_side_encryption = upload.server_side_encryption.clone();
+                entry.sse_kms_key_id = upload.sse_kms_key_id.clone();
+                entry.outcome = match (upload.dry_run, upload.skipped) {
+                    (true, _) => Outcome::DryRun,
+                    (false, true) => Outcome::Skipped,
+                    (false, false) => Outcome::Uploaded,
+                };
+            }
+            Err(error) => entry.error = Some(error.to_string()),
+        }
+        entry
+    }
+
+    /// The entry for a file a sync found its object already matched, from the listing
+    pub fn from_object(file: &FoundFile, object: &Object) -> Self {
+        let metadata = std::fs::metadata(&file.path).ok();
+        Self {
+            path: file.path.clone(),
+            key: file.key.clone(),
+            content_key: None,
+            size: metadata.as_ref().map_or(0, |metadata| metadata.len()),
+            modified: metadata.and_then(|metadata| metadata.modified().ok()),
+            symlink: file.symlink,
+            etag: object
+                .e_tag()
+                .map(|etag| etag.trim_matches('"').to_string()),
+            version_id: None,
+            checksum_algorithm: None,
+            checksum: None,
+            storage_class: object
+                .storage_class()
+                .map(|class| class.as_str().to_string()),
+            server_side_encryption: None,
+            sse_kms_key_id: None,
+            timestamp: SystemTime::now(),
+            outcome: Outcome::Skipped,
+            error: None,
+        }
+    }
+
+    /// The object the file's content is in, which is only `key` if it wasn't uploaded by content
+    pub fn object_key(&self) -> &str {
+        self.content_key.as_deref().unwrap_or(&self.key)
+    }
+
+    /// Whether the object in the bucket holds the file, ie it was uploaded now or before
+    pub fn is_stored(&self) -> bool {
+        matches!(self.outcome, Outcome::Uploaded | Outcome::Skipped)
+    }
+
+    /// Whether the file is still what was stored, going by the key it goes to and its size and
+    /// modification time, without reading it
+    pub fn matches_file(&self, file: &FoundFile, metadata: &Metadata) -> bool {
+        self.is_stored()
+            && self.key == file.key
+            && self.size == metadata.len()
+            && self.modified.is_some()
+            && self.modified == metadata.modified().ok()
+    }
+}
+
+/// Files sorted against the manifest of a previous run
+#[derive(Debug, Default)]
+pub struct Incremental {
+    /// Files that are new or have changed since, or that the run didn't store
+    pub changed: Vec<FoundFile>,
+    /// The entries of files that haven't, to carry forward into the next manifest
+    pub unchanged: Vec<ManifestEntry>,
+    /// Entries for files that were stored but aren't there any more
+    pub missing: Vec<ManifestEntry>,
+}
+
+/// Sorts `files` into those that need uploading and those [ManifestEntry::matches_file] says are
+/// already stored, and finds the stored files that have gone
+///
+/// Entries are found by local path, so the paths need to be given the same way as in the run
+/// that wrote the manifest. Entries for files this run doesn't cover but that are still there
+/// are dropped.
+///
+/// ```
+/// use rust_test_s3_upload::manifest::{compare_previous, ManifestEntry, Outcome};
+/// use rust_test_s3_upload::walk::FoundFile;
+/// use std::time::SystemTime;
+///
+/// let path = std::env::temp_dir().join("manifest-incremental-doctest.txt");
+/// std::fs::write(&path, "hello").unwrap();
+/// let metadata = std::fs::metadata(&path).unwrap();
+/// let entry = |path: &std::path::Path, size: u64| ManifestEntry {
+///     path: path.to_path_buf(),
+///     key: "backups/hello.txt".to_string(),
+///     content_key: None,
+///     size,
+///     modified: metadata.modified().ok(),
+///     symlink: false,
+///     etag: None,
+///     version_id: None,
+///     checksum_algorithm: None,
+///     checksum: None,
+///     storage_class: None,
+///     server_side_encryption: None,
+///     sse_kms_key_id: None,
+///     timestamp: SystemTime::now(),
+///     outcome: Outcome::Uploaded,
+///     error: None,
+/// };
+/// let file = FoundFile { path: path.clone(), key: "backups/hello.txt".to_string(), symlink: false };
+/// let gone = std::env::temp_dir().join("manifest-incremental-doctest-gone.txt");
+///
+/// let split = compare_previous(vec![file.clone()], vec![entry(&path, 5), entry(&gone, 5)]);
+/// assert!(split.changed.is_empty());
+/// assert_eq!(split.unchanged.len(), 1);
+/// assert_eq!(split.missing[0].path, gone);
+///
+/// // a different size means it was written since
+/// let split = compare_previous(vec![file], vec![entry(&path, 4)]);
+/// assert_eq!(split.changed.len(), 1);
+/// std::fs::remove_file(&path).unwrap();
+/// ```
+pub fn compare_previous(files: Vec<FoundFile>, previous: Vec<ManifestEntry>) -> Incremental {
+    let mut previous: HashMap<PathBuf, ManifestEntry> = previous
+        .into_iter()
+        .filter(ManifestEntry::is_stored)
+        .map(|entry| (entry.path.clone(), entry))
+        .collect();
+    let mut split = Incremental::default();
+    for file in files {
+        let entry = previous.remove(&file.path);
+        let metadata = std::fs::metadata(&file.path);
+        match (entry, metadata) {
+            (Some(entry), Ok(metadata)) if entry.matches_file(&file, &metadata) => {
+                split.unchanged.push(entry)
+            }
+            _ => split.changed.push(file),
+        }
+    }
+    split.missing = previous
+        .into_values()
+        .filter(|entry| !entry.path.exists())
+        .collect();
+    split.missing.sort_by(|a, b| a.path.cmp(&b.path));
+    split
+}
+
+/// Checks a downloaded file against what its entry recorded, its size and then its MD5 where
+/// that's the etag or else its additional checksum, if there is one for the whole object
+///
+/// Multipart uploads without an additional checksum only have their size to go on, and the etag
+/// isn't the MD5 when `customer_key` says the uploads used an SSE-C key.
+pub async fn verify_file(
+    entry: &ManifestEntry,
+    path: &Path,
+    customer_key: bool,
+) -> std::io::Result<bool> {
+    if tokio::fs::metadata(path).await?.len() != entry.size {
+        return Ok(false);
+    }
+    let etag = entry.etag.as_deref().unwrap_or_default();
+    if etag_is_md5(etag, entry.server_side_encryption.as_deref(), customer_key) {
+        return Ok(ContentMd5::of_file(path).await?.matches_etag(etag));
+    }
+    let algorithm = entry
+        .checksum_algorithm
+        .as_deref()
+        .and_then(|value| parse_checksum_algorithm(value).ok());
+    match (algorithm, entry.checksum.as_deref()) {
+        // a composite checksum of the parts can't be worked out without knowing where they split
+        (Some(algorithm), Some(checksum)) if !checksum.contains('-') => {
+            Ok(Checksum::of_file(&algorithm, path)
+                .await?
+                .is_some_and(|local| local.matches(Some(checksum))))
+        }
+        _ => Ok(true),
+    }
+}
+
+/// Problems reading a manifest
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("Failed to read manifest {}: {}", .0.display(), .1)]
+    Read(PathBuf, std::io::Error),
+    #[error("Manifest line {0} is invalid: {1}")]
+    Invalid(usize, String),
+}
+
+/// The entries as JSON lines
+pub fn to_json_lines(entries: &[ManifestEntry]) -> String {
+    entries
+        .iter()
+        .filter_map(|entry| serde_json::to_string(entry).ok())
+        .map(|line| line + "\n")
+        .collect()
+}
+
+/// Reads JSON lines back into entries, blank lines are skipped but anything else that isn't an
+/// entry is an error, so a truncated manifest isn't mistaken for a short one
+///
+/// ```
+/// use rust_test_s3_upload::manifest::{parse_manifest, to_json_lines, ManifestEntry, Outcome};
+/// use std::time::SystemTime;
+///
+/// let entry = ManifestEntry {
+///     path: "backups/db.sql".into(),
+///     key: "hosts/web1/db.sql".to_string(),
+///     content_key: None,
+///     size: 1024,
+///     modified: Some(SystemTime::UNIX_EPOCH),
+///     symlink: true,
+///     etag: Some("9e107d9d372bb6826bd81d3542a419d6".to_string()),
+///     version_id: None,
+///     checksum_algorithm: None,
+///     checksum: None,
+///     storage_class: Some("STANDARD_IA".to_string()),
+///     server_side_encryption: Some("AES256".to_string()),
+///     sse_kms_key_id: None,
+///     timestamp: SystemTime::UNIX_EPOCH,
+///     outcome: Outcome::Uploaded,
+///     error: None,
+/// };
+/// let lines = to_json_lines(&[entry.clone(), entry.clone()]);
+/// assert_eq!(parse_manifest(&lines).unwrap(), vec![entry.clone(), entry]);
+///
+/// let truncated = &lines[..lines.len() - 10];
+/// assert!(parse_manifest(truncated).unwrap_err().to_string().starts_with("Manifest line 2 is invalid"));
+/// ```
+pub fn parse_manifest(text: &str) -> Result<Vec<ManifestEntry>, ManifestError> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line)
+                .map_err(|error| ManifestError::Invalid(index + 1, error.to_string()))
+        })
+        .collect()
+}
+
+/// Reads a manifest written by [write_manifest]
+pub fn read_manifest(path: &Path) -> Result<Vec<ManifestEntry>, ManifestError> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|error| ManifestError::Read(path.to_path_buf(), error))?;
+    parse_manifest(&text)
+}
+
+/// Writes the entries to `path` as JSON lines, replacing anything already there
+///
+/// They're written to a temporary file next to it first, so a run that's interrupted leaves the
+/// old manifest in place rather than half of a new one.
+pub fn write_manifest(path: &Path, entries: &[ManifestEntry]) -> std::io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    std::fs::write(&partial, to_json_lines(entries))?;
+    std::fs::rename(&partial, path)
+}
+
+/// The key a manifest for a run started at `started` is uploaded to, under the upload's prefix
+///
+/// ```
+/// use rust_test_s3_upload::manifest::manifest_key;
+/// use std::time::{Duration, SystemTime};
+///
+/// let started = SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_528_800);
+/// assert_eq!(manifest_key(started), "manifests/2024-05-01T020000Z.json");
+/// ```
+pub fn manifest_key(started: SystemTime) -> String {
+    let timestamp = DateTime::from(started)
+        .fmt(DateTimeFormat::DateTime)
+        .unwrap_or_default()
+        .replace(':', "");
+    format!("{}{}.json", MANIFEST_PREFIX, timestamp)
+}
@@ -0,0 +1,146 @@
+//! User metadata for uploaded objects, sent as `x-amz-meta-*` headers
+//!
+use aws_sdk_s3::types::MetadataDirective;
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// S3's limit on the total size of the user metadata keys and values, in bytes
+pub const MAX_METADATA_SIZE: usize = 2048;
+
+/// The user metadata an upload keeps the file's modification time in, `x-amz-meta-mtime` like
+/// rclone and s3cmd use
+pub const MTIME_METADATA: &str = "mtime";
+
+/// Splits a `key=value` argument, the value can contain more `=`s
+///
+/// ```
+/// use rust_test_s3_upload::metadata::parse_pair;
+///
+/// assert_eq!(
+///     parse_pair("job=nightly=1"),
+///     Ok((String::from("job"), String::from("nightly=1")))
+/// );
+/// assert!(parse_pair("job").is_err());
+/// assert!(parse_pair("=nightly").is_err());
+/// ```
+pub fn parse_pair(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("{:?} should look like key=value", value)),
+    }
+}
+
+/// Parses a `--metadata-directive` value, ignoring case
+///
+/// ```
+/// use aws_sdk_s3::types::MetadataDirective;
+/// use rust_test_s3_upload::metadata::parse_metadata_directive;
+///
+/// assert_eq!(parse_metadata_directive("replace"), Ok(MetadataDirective::Replace));
+/// assert!(parse_metadata_directive("merge").is_err());
+/// ```
+pub fn parse_metadata_directive(value: &str) -> Result<MetadataDirective, String> {
+    match value.to_ascii_uppercase().as_str() {
+        "COPY" => Ok(MetadataDirective::Copy),
+        "REPLACE" => Ok(MetadataDirective::Replace),
+        _ => Err(format!("{:?} isn't supported, use COPY or REPLACE", value)),
+    }
+}
+
+/// Checks the metadata can be sent as headers and fits in S3's size limit
+///
+/// Keys have to be valid header names and values printable ASCII, S3 would otherwise reject them
+/// or store them mangled.
+///
+/// ```
+/// use rust_test_s3_upload::metadata::validate_metadata;
+/// use std::collections::BTreeMap;
+///
+/// let mut metadata = BTreeMap::new();
+/// metadata.insert(String::from("source-host"), String::from("web1"));
+/// assert!(validate_metadata(&metadata).is_ok());
+///
+/// metadata.insert(String::from("notes"), "x".repeat(2048));
+/// assert!(validate_metadata(&metadata).is_err());
+///
+/// let mut metadata = BTreeMap::new();
+/// metadata.insert(String::from("job id"), String::from("1"));
+/// assert!(validate_metadata(&metadata).is_err());
+/// ```
+pub fn validate_metadata(metadata: &BTreeMap<String, String>) -> Result<(), String> {
+    for (key, value) in metadata.iter() {
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+        {
+            return Err(format!(
+                "metadata key {:?} can only contain letters, numbers and !#$%&'*+-.^_`|~",
+                key
+            ));
+        }
+        if !value.chars().all(|c| c == ' ' || c.is_ascii_graphic()) {
+            return Err(format!(
+                "metadata value for {} can only contain printable ASCII",
+                key
+            ));
+        }
+    }
+    let size: usize = metadata
+        .iter()
+        .map(|(key, value)| key.len() + value.len())
+        .sum();
+    if size > MAX_METADATA_SIZE {
+        return Err(format!(
+            "metadata is {} bytes but S3 allows {}",
+            size, MAX_METADATA_SIZE
+        ));
+    }
+    Ok(())
+}
+
+/// A modification time for [MTIME_METADATA], seconds since the epoch with the nanoseconds after a
+/// `.`, `None` for times before 1970
+///
+/// ```
+/// use rust_test_s3_upload::metadata::format_mtime;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let modified = UNIX_EPOCH + Duration::new(1_714_564_800, 5_000_000);
+/// assert_eq!(format_mtime(modified).as_deref(), Some("1714564800.005000000"));
+/// ```
+pub fn format_mtime(modified: SystemTime) -> Option<String> {
+    let since = modified.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!("{}.{:09}", since.as_secs(), since.subsec_nanos()))
+}
+
+/// Reads a [MTIME_METADATA] value, which other tools write as whole or fractional seconds
+///
+/// ```
+/// use rust_test_s3_upload::metadata::parse_mtime;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// assert_eq!(parse_mtime("1714564800"), Some(UNIX_EPOCH + Duration::from_secs(1_714_564_800)));
+/// assert_eq!(
+///     parse_mtime("1714564800.5"),
+///     Some(UNIX_EPOCH + Duration::from_millis(1_714_564_800_500))
+/// );
+/// assert_eq!(
+///     parse_mtime("1714564800.005000000"),
+///     Some(UNIX_EPOCH + Duration::from_millis(1_714_564_800_005))
+/// );
+/// assert_eq!(parse_mtime("yesterday"), None);
+/// assert_eq!(parse_mtime("-1"), None);
+/// ```
+pub fn parse_mtime(value: &str) -> Option<SystemTime> {
+    let (seconds, fraction) = value.trim().split_once('.').unwrap_or((value.trim(), ""));
+    if !seconds.chars().all(|c| c.is_ascii_digit()) || !fraction.chars().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let seconds: u64 = seconds.parse().ok()?;
+    // anything past nanoseconds is dropped
+    let nanos = format!("{:0<9}", &fraction[..fraction.len().min(9)]);
+    let nanos: u32 = nanos.parse().ok()?;
+    UNIX_EPOCH.checked_add(Duration::new(seconds, nanos))
+}
@@ -0,0 +1,298 @@
+//! An in-memory [ObjectStore], for testing code that works with objects without an S3 endpoint
+//!
+use crate::checksum::ContentMd5;
+use crate::client::{CopyResult, DeleteResult, S3FileInfo, UploadResult};
+use crate::error::S3Error;
+use crate::store::{ObjectStore, StorePage};
+use aws_sdk_s3::primitives::DateTime;
+use aws_sdk_s3::types::Object;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+/// Keys listed in each page unless [MockStore::with_page_size] says otherwise, the same as S3
+const DEFAULT_PAGE_SIZE: usize = 1000;
+
+/// Something that was asked of a [MockStore], so a test can check what was and wasn't done
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Call {
+    Put(String),
+    Get(String),
+    Head(String),
+    Delete(String),
+    /// The prefix that was listed
+    List(String),
+    /// From the first key to the second
+    Copy(String, String),
+}
+
+#[derive(Debug, Clone)]
+struct MockObject {
+    body: Vec<u8>,
+    last_modified: SystemTime,
+}
+
+impl MockObject {
+    /// The MD5 of the body, as S3 makes the etag of a single PUT
+    fn etag(&self) -> String {
+        ContentMd5::of(&self.body).hex()
+    }
+}
+
+/// Objects held in memory, with every call recorded
+///
+/// Clones share the same objects and calls, so one can be handed to the code under test and the
+/// other kept to check on it.
+///
+/// ```
+/// use rust_test_s3_upload::mock::{Call, MockStore};
+/// use rust_test_s3_upload::store::ObjectStore;
+/// use rust_test_s3_upload::S3Error;
+/// use std::time::{Duration, SystemTime};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let last_week = SystemTime::now() - Duration::from_secs(7 * 24 * 60 * 60);
+/// let store = MockStore::default().with_object("db.sql", b"select 1;", last_week);
+///
+/// let info = store.head("db.sql").await.unwrap();
+/// assert_eq!((info.size, info.last_modified), (9, Some(last_week)));
+/// store.copy("db.sql", "archive/db.sql").await.unwrap();
+/// assert_eq!(store.get("archive/db.sql").await.unwrap(), b"select 1;");
+/// store.delete("db.sql").await.unwrap();
+/// assert!(matches!(store.head("db.sql").await, Err(S3Error::NotFound { .. })));
+///
+/// assert_eq!(store.keys(), ["archive/db.sql"]);
+/// assert_eq!(store.deleted(), ["db.sql"]);
+/// assert_eq!(store.calls()[1], Call::Copy(String::from("db.sql"), String::from("archive/db.sql")));
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockStore {
+    objects: Arc<Mutex<BTreeMap<String, MockObject>>>,
+    calls: Arc<Mutex<Vec<Call>>>,
+    page_size: usize,
+}
+
+impl Default for MockStore {
+    fn default() -> Self {
+        Self {
+            objects: Arc::default(),
+            calls: Arc::default(),
+            page_size: DEFAULT_PAGE_SIZE,
+        }
+    }
+}
+
+/// Locks a mutex, carrying on with what's there if a test panicked while holding it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(value) => value,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+impl MockStore {
+    /// Lists at most `page_size` keys at a time, so paging can be tested without thousands of keys
+    pub fn with_page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    /// Adds an object without recording a call, for setting up a test
+    pub fn with_object(self, key: &str, body: &[u8], last_modified: SystemTime) -> Self {
+        self.insert(key, body, last_modified);
+        self
+    }
+
+    /// [MockStore::with_object] for a store that's already been handed out
+    pub fn insert(&self, key: &str, body: &[u8], last_modified: SystemTime) {
+        lock(&self.objects).insert(
+            key.to_string(),
+            MockObject {
+                body: body.to_vec(),
+                last_modified,
+            },
+        );
+    }
+
+    /// Every key in the store, in order
+    pub fn keys(&self) -> Vec<String> {
+        lock(&self.objects).keys().cloned().collect()
+    }
+
+    /// Everything that's been asked of the store, in order
+    pub fn calls(&self) -> Vec<Call> {
+        lock(&self.calls).clone()
+    }
+
+    /// The keys that have been put, in order
+    pub fn put_keys(&self) -> Vec<String> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::Put(key) => Some(key),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The keys that have been deleted, in order
+    pub fn deleted(&self) -> Vec<String> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::Delete(key) => Some(key),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn record(&self, call: Call) {
+        lock(&self.calls).push(call);
+    }
+
+    fn object(&self, operation: &'static str, key: &str) -> Result<MockObject, S3Error> {
+        lock(&self.objects)
+            .get(key)
+            .cloned()
+            .ok_or_else(|| S3Error::NotFound {
+                operation,
+                key: key.to_string(),
+                source: "NoSuchKey".into(),
+            })
+    }
+
+    fn store(&self, key: &str, object: MockObject) -> UploadResult {
+        let result = UploadResult {
+            etag: object.etag(),
+            version_id: None,
+            key: key.to_string(),
+            size: object.body.len() as u64,
+            content_type: String::from("application/octet-stream"),
+            server_side_encryption: None,
+            sse_kms_key_id: None,
+            checksum_algorithm: None,
+            checksum: None,
+            verified: None,
+            multipart: None,
+            skipped: false,
+            dry_run: false,
+        };
+        lock(&self.objects).insert(key.to_string(), object);
+        result
+    }
+}
+
+impl ObjectStore for MockStore {
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<UploadResult, S3Error> {
+        self.record(Call::Put(key.to_string()));
+        let object = MockObject {
+            body,
+            last_modified: SystemTime::now(),
+        };
+        Ok(self.store(key, object))
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, S3Error> {
+        self.record(Call::Get(key.to_string()));
+        Ok(self.object("get_object", key)?.body)
+    }
+
+    async fn head(&self, key: &str) -> Result<S3FileInfo, S3Error> {
+        self.record(Call::Head(key.to_string()));
+        let object = self.object("head_object", key)?;
+        Ok(S3FileInfo {
+            etag: object.etag(),
+            size: object.body.len() as u64,
+            server_side_encryption: None,
+            sse_kms_key_id: None,
+            version_id: None,
+            last_modified: Some(object.last_modified),
+            content_type: Some(String::from("application/octet-stream")),
+            cache_control: None,
+            content_disposition: None,
+            content_encoding: None,
+            expires: None,
+            metadata: BTreeMap::new(),
+            storage_class: String::from("STANDARD"),
+            checksum_algorithm: None,
+            checksum: None,
+            object_lock_mode: None,
+            object_lock_retain_until: None,
+            legal_hold: None,
+            restore: None,
+        })
+    }
+
+    async fn delete(&self, key: &str) -> Result<DeleteResult, S3Error> {
+        self.record(Call::Delete(key.to_string()));
+        // like S3, deleting a key that isn't there works
+        lock(&self.objects).remove(key);
+        Ok(DeleteResult {
+            key: key.to_string(),
+            delete_marker: false,
+            version_id: None,
+            permanent: false,
+        })
+    }
+
+    async fn list_objects(
+        &self,
+        prefix: &str,
+        continuation: Option<&str>,
+    ) -> Result<StorePage, S3Error> {
+        self.record(Call::List(prefix.to_string()));
+        let objects = lock(&self.objects);
+        let mut matching = objects
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .filter(|(key, _)| continuation.is_none_or(|after| key.as_str() > after));
+        let page: Vec<Object> = matching
+            .by_ref()
+            .take(self.page_size)
+            .map(|(key, object)| {
+                Object::builder()
+                    .key(key)
+                    .size(object.body.len() as i64)
+                    .e_tag(format!("\"{}\"", object.etag()))
+                    .last_modified(DateTime::from(object.last_modified))
+                    .build()
+            })
+            .collect();
+        // the token is the last key listed, so the next page starts after it
+        let next = match matching.next() {
+            Some(_) => page
+                .last()
+                .and_then(|object| object.key())
+                .map(str::to_string),
+            None => None,
+        };
+        Ok(StorePage {
+            objects: page,
+            next,
+        })
+    }
+
+    async fn copy(&self, source_key: &str, key: &str) -> Result<CopyResult, S3Error> {
+        self.record(Call::Copy(source_key.to_string(), key.to_string()));
+        let object = self.object("copy_object", source_key)?;
+        let stored = self.store(
+            key,
+            MockObject {
+                body: object.body,
+                last_modified: SystemTime::now(),
+            },
+        );
+        Ok(CopyResult {
+            source_bucket: String::from("mock"),
+            source_key: source_key.to_string(),
+            bucket: String::from("mock"),
+            key: key.to_string(),
+            etag: stored.etag,
+            version_id: None,
+            size: stored.size,
+            multipart: false,
+        })
+    }
+}
@@ -0,0 +1,287 @@
+//! Output for scripts, where `--json` turns every result into a line of JSON on stdout and the
+//! text meant for people is dropped
+//!
+use crate::error::{ResponseDetails, S3Error};
+use crate::stats::TransferStats;
+use serde_derive::Serialize;
+use std::time::{Duration, Instant};
+
+/// How a record's operation went
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    /// There was nothing to do, eg the object was already up to date
+    Skipped,
+    /// Nothing was changed, the run was a dry run
+    DryRun,
+    Failed,
+}
+
+/// One result, printed as a line of JSON
+///
+/// ```
+/// use rust_test_s3_upload::output::{Outcome, Record};
+/// use rust_test_s3_upload::S3Error;
+/// use std::time::Duration;
+///
+/// let record = Record::new("download", Some("db.sql"))
+///     .size(1024)
+///     .etag("9e107d9d372bb6826bd81d3542a419d6")
+///     .duration(Duration::from_millis(1500));
+/// assert_eq!(
+///     record.to_line(),
+///     r#"{"operation":"download","key":"db.sql","outcome":"ok","size":1024,"etag":"9e107d9d372bb6826bd81d3542a419d6","error_kind":null,"error":null,"duration_ms":1500}"#
+/// );
+///
+/// let error = S3Error::NoVersions { key: String::from("db.sql") };
+/// let failed = Record::failed("undelete", Some("db.sql"), &error);
+/// assert_eq!(failed.outcome, Outcome::Failed);
+/// assert_eq!(failed.error_kind, Some("no_versions"));
+/// ```
+#[derive(Serialize, Debug, Clone)]
+pub struct Record {
+    /// The subcommand, eg `upload` or `bucket create`
+    pub operation: String,
+    pub key: Option<String>,
+    pub outcome: Outcome,
+    pub size: Option<u64>,
+    pub etag: Option<String>,
+    /// [S3Error::kind] for failures
+    pub error_kind: Option<&'static str>,
+    pub error: Option<String>,
+    /// What S3 said about the request that failed, with the ids for a support ticket
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<ResponseDetails>,
+    pub duration_ms: Option<u64>,
+    /// The summary a person would have been shown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Everything else known about the result, eg the whole upload result
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<serde_json::Value>,
+    /// What the process exits with, only on the summary at the end of a run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+}
+
+impl Record {
+    /// A record of `operation` working
+    pub fn new(operation: &str, key: Option<&str>) -> Self {
+        Self {
+            operation: operation.to_string(),
+            key: key.map(str::to_string),
+            outcome: Outcome::Ok,
+            size: None,
+            etag: None,
+            error_kind: None,
+            error: None,
+            response: None,
+            duration_ms: None,
+            message: None,
+            detail: None,
+            exit_code: None,
+        }
+    }
+
+    /// A record of `operation` failing with `error`
+    pub fn failed(operation: &str, key: Option<&str>, error: &S3Error) -> Self {
+        let mut record = Self::new(operation, key).outcome(Outcome::Failed);
+        record.error_kind = Some(error.kind());
+        record.error = Some(error.to_string());
+        record.response = error.response().cloned();
+        record
+    }
+
+    /// Marks the record failed for a reason that isn't an [S3Error], eg a file that didn't match
+    pub fn problem(mut self, problem: &str) -> Self {
+        self.outcome = Outcome::Failed;
+        self.error = Some(problem.to_string());
+        self
+    }
+
+    pub fn outcome(mut self, outcome: Outcome) -> Self {
+        self.outcome = outcome;
+        self
+    }
+
+    pub fn size(mut self, size: u64) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn etag(mut self, etag: &str) -> Self {
+        self.etag = Some(etag.to_string());
+        self
+    }
+
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration_ms = Some(duration.as_millis() as u64);
+        self
+    }
+
+    pub fn exit_code(mut self, exit_code: i32) -> Self {
+        self.exit_code = Some(exit_code);
+        self
+    }
+
+    pub fn message(mut self, message: &str) -> Self {
+        self.message = Some(message.to_string());
+        self
+    }
+
+    /// Adds `detail` as it serializes, it's left out if it can't be
+    pub fn detail<T: serde::Serialize>(mut self, detail: &T) -> Self {
+        self.detail = serde_json::to_value(detail).ok();
+        self
+    }
+
+    /// The record as a line of JSON, without the newline
+    pub fn to_line(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Where results go, so a subcommand doesn't need to know if it's writing for a person or a script
+///
+/// Only stdout is affected, logs and errors still go to stderr either way. Quiet drops the text
+/// too, but never records, so `--quiet --json` is the same as `--json`.
+///
+/// ```
+/// use rust_test_s3_upload::output::{Outcome, Output, Record};
+///
+/// let out = Output::new(false, "head");
+/// let text = out.reply(|| Record::new("head", Some("db.sql")), || String::from("db.sql"));
+/// assert_eq!(text, "db.sql");
+///
+/// // the record was printed, so there's nothing left for the caller to
+/// let out = Output::new(true, "head");
+/// let text = out.reply(|| Record::new("head", Some("db.sql")), || String::from("db.sql"));
+/// assert!(text.is_empty());
+///
+/// let summary = out.summary(Outcome::Failed, "", 4);
+/// assert_eq!((summary.operation.as_str(), summary.exit_code), ("head", Some(4)));
+///
+/// let out = Output::new(false, "head").with_quiet(true);
+/// let text = out.reply(|| Record::new("head", Some("db.sql")), || String::from("db.sql"));
+/// assert!(text.is_empty());
+/// ```
+#[derive(Clone, Debug)]
+pub struct Output {
+    json: bool,
+    /// Only errors, on stderr, and records
+    quiet: bool,
+    /// The subcommand being run, for the summary
+    operation: String,
+    /// When the command started, for the duration of whole command results
+    started: Instant,
+    /// What the run transferred, shared with the client doing it
+    stats: TransferStats,
+}
+
+impl Output {
+    /// `json` prints records and drops text, otherwise it's the other way around
+    pub fn new(json: bool, operation: &str) -> Self {
+        Self {
+            json,
+            quiet: false,
+            operation: operation.to_string(),
+            started: Instant::now(),
+            stats: TransferStats::default(),
+        }
+    }
+
+    /// Drops text meant for people, records are still printed
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.json
+    }
+
+    pub fn is_quiet(&self) -> bool {
+        self.quiet
+    }
+
+    /// Whether there's a person reading stdout, so text is worth building
+    pub fn is_text(&self) -> bool {
+        !self.json && !self.quiet
+    }
+
+    pub fn operation(&self) -> &str {
+        &self.operation
+    }
+
+    /// The counts for the summary, to hand to [crate::S3Client::with_stats]
+    pub fn stats(&self) -> &TransferStats {
+        &self.stats
+    }
+
+    /// How long it's been since the output was created
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Prints text for a person, unless the output is JSON or quiet
+    pub fn text(&self, text: impl std::fmt::Display) {
+        if self.is_text() {
+            println!("{}", text);
+        }
+    }
+
+    /// Prints a record as a line of JSON, if the output is JSON
+    pub fn record(&self, record: &Record) {
+        if self.json {
+            println!("{}", record.to_line());
+        }
+    }
+
+    /// Prints whichever of the record or text suits the output, only building that one
+    pub fn result(&self, record: impl FnOnce() -> Record, text: impl FnOnce() -> String) {
+        match (self.json, self.quiet) {
+            (true, _) => println!("{}", record().to_line()),
+            (false, false) => println!("{}", text()),
+            (false, true) => {}
+        }
+    }
+
+    /// For a command with one result, prints the record with how long the command took and
+    /// returns nothing, or returns the text for the caller to print, nothing if quiet
+    pub fn reply(&self, record: impl FnOnce() -> Record, text: impl FnOnce() -> String) -> String {
+        match (self.json, self.quiet) {
+            (true, _) => {
+                self.record(&record().duration(self.elapsed()));
+                String::new()
+            }
+            (false, false) => text(),
+            (false, true) => String::new(),
+        }
+    }
+
+    /// Prints the line of transfer stats, for runs that handled more than one object
+    pub fn print_stats(&self) {
+        let stats = self.stats.summary();
+        if stats.handled() > 1 {
+            self.text(stats.describe(self.elapsed()));
+        }
+    }
+
+    /// The last record of a run, with how long it took and the exit code, the text summary as
+    /// its message if there was one, and the transfer stats as its detail if anything was counted
+    pub fn summary(&self, outcome: Outcome, message: &str, exit_code: i32) -> Record {
+        let mut record = Record::new(&self.operation, None)
+            .outcome(outcome)
+            .duration(self.elapsed())
+            .exit_code(exit_code);
+        if !message.is_empty() {
+            record = record.message(message);
+        }
+        let stats = self.stats.summary();
+        match stats.handled() {
+            0 => record,
+            _ => record.detail(&stats),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Progress bars for uploads and downloads, drawn on stderr
+//!
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const BAR_TEMPLATE: &str =
+    "{msg:30!} [{bar:30}] {binary_bytes}/{binary_total_bytes} {percent:>3}% {binary_bytes_per_sec} eta {eta}";
+/// For streams, where the size isn't known until they end
+const STREAM_TEMPLATE: &str = "{msg:30!} {binary_bytes} {binary_bytes_per_sec}";
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template)
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ")
+}
+
+/// A bar for each transfer in flight, and optionally one for the whole run
+///
+/// Clones share the same bars, so it can be handed to every task. Transfers find their bar by key.
+///
+/// ```
+/// use rust_test_s3_upload::progress::Progress;
+///
+/// // nothing is drawn, eg when stderr isn't a terminal
+/// let progress = Progress::hidden().with_total(3 * 1024 * 1024);
+/// progress.finish();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Progress {
+    bars: MultiProgress,
+    files: Arc<Mutex<HashMap<String, ProgressBar>>>,
+    total: Option<ProgressBar>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Progress {
+    /// Draws on stderr
+    pub fn new() -> Self {
+        Self::with_target(ProgressDrawTarget::stderr())
+    }
+
+    /// Keeps count without drawing anything
+    pub fn hidden() -> Self {
+        Self::with_target(ProgressDrawTarget::hidden())
+    }
+
+    fn with_target(target: ProgressDrawTarget) -> Self {
+        Self {
+            bars: MultiProgress::with_draw_target(target),
+            files: Arc::new(Mutex::new(HashMap::new())),
+            total: None,
+        }
+    }
+
+    /// Adds a bar for the whole run, for when several files are in flight at once
+    pub fn with_total(mut self, bytes: u64) -> Self {
+        let total = self.bars.add(ProgressBar::new(bytes));
+        total.set_style(style(BAR_TEMPLATE));
+        total.set_message("total");
+        self.total = Some(total);
+        self
+    }
+
+    /// Clears the total bar once everything's finished
+    pub fn finish(&self) {
+        if let Some(total) = &self.total {
+            total.finish_and_clear();
+        }
+    }
+
+    fn files(&self) -> std::sync::MutexGuard<'_, HashMap<String, ProgressBar>> {
+        match self.files.lock() {
+            Ok(value) => value,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Shows a bar for `key` until the returned guard is dropped, `size` is `None` for streams
+    pub(crate) fn start(&self, key: &str, size: Option<u64>) -> FileProgress {
+        let bar = match size {
+            Some(size) => {
+                let bar = ProgressBar::new(size);
+                bar.set_style(style(BAR_TEMPLATE));
+                bar
+            }
+            None => {
+                let bar = ProgressBar::no_length();
+                bar.set_style(style(STREAM_TEMPLATE));
+                bar
+            }
+        };
+        let bar = match &self.total {
+            Some(total) => self.bars.insert_before(total, bar),
+            None => self.bars.add(bar),
+        };
+        bar.set_message(key.to_string());
+        self.files().insert(key.to_string(), bar);
+        FileProgress {
+            progress: self.clone(),
+            key: key.to_string(),
+        }
+    }
+
+    /// Gives `key`'s bar a size once it's known, eg from the response to a GET
+    pub(crate) fn set_size(&self, key: &str, size: u64) {
+        if let Some(bar) = self.files().get(key) {
+            bar.set_length(size);
+            bar.set_style(style(BAR_TEMPLATE));
+        }
+    }
+
+    /// The counter for `key`'s bar, if it has one
+    pub(crate) fn counter(&self, key: &str) -> Option<Counter> {
+        let file = self.files().get(key).cloned()?;
+        Some(Counter {
+            file,
+            total: self.total.clone(),
+        })
+    }
+
+    /// Counts `bytes` towards `key`'s bar and the total
+    pub(crate) fn advance(&self, key: &str, bytes: u64) {
+        if let Some(counter) = self.counter(key) {
+            counter.inc(bytes);
+        }
+    }
+}
+
+/// Removes a transfer's bar when it's dropped, whether the transfer worked or not
+pub(crate) struct FileProgress {
+    progress: Progress,
+    key: String,
+}
+
+impl Drop for FileProgress {
+    fn drop(&mut self) {
+        if let Some(bar) = self.progress.files().remove(&self.key) {
+            bar.finish_and_clear();
+            self.progress.bars.remove(&bar);
+        }
+    }
+}
+
+/// Counts bytes towards one transfer's bar and the total, for handing to a request body
+#[derive(Clone, Debug)]
+pub(crate) struct Counter {
+    file: ProgressBar,
+    total: Option<ProgressBar>,
+}
+
+impl Counter {
+    pub(crate) fn inc(&self, bytes: u64) {
+        self.file.inc(bytes);
+        if let Some(total) = &self.total {
+            total.inc(bytes);
+        }
+    }
+
+    /// Takes back bytes that have to be sent again, eg when a request is retried
+    pub(crate) fn dec(&self, bytes: u64) {
+        self.file.dec(bytes);
+        if let Some(total) = &self.total {
+            total.dec(bytes);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Picking which old backups to delete, by age or by keeping only the newest few of each kind
+//!
+use crate::error::S3Error;
+use crate::filter::ObjectFilter;
+use crate::store::ObjectStore;
+use aws_sdk_s3::types::Object;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How many backups to keep, an object is only pruned if every rule that's set lets it go
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct Retention {
+    /// Keep anything last modified within this many days
+    pub keep_days: Option<u32>,
+    /// Keep the newest this many objects of each [key_pattern]
+    pub keep_count: Option<u32>,
+}
+
+/// An object that's up for pruning
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Backup {
+    /// The backup for an object from a listing, `None` if it has no key
+    pub fn from_object(object: &Object) -> Option<Self> {
+        Some(Self {
+            key: object.key()?.to_string(),
+            size: object
+                .size()
+                .and_then(|value| u64::try_from(value).ok())
+                .unwrap_or(0),
+            last_modified: object
+                .last_modified()
+                .and_then(|value| SystemTime::try_from(*value).ok()),
+        })
+    }
+}
+
+/// Groups keys that are copies of the same backup taken at different times, by replacing each run
+/// of digits in the file name with `*`, the rest of the key has to match exactly
+///
+/// ```
+/// use rust_test_s3_upload::prune::key_pattern;
+///
+/// assert_eq!(key_pattern("hosts/web1/db-2024-01-31.sql.gz"), "hosts/web1/db-*-*-*.sql.gz");
+/// assert_eq!(key_pattern("hosts/web1/db-2024-02-01.sql.gz"), key_pattern("hosts/web1/db-2024-01-31.sql.gz"));
+/// assert_ne!(key_pattern("hosts/web2/db-2024-01-31.sql.gz"), key_pattern("hosts/web1/db-2024-01-31.sql.gz"));
+/// assert_eq!(key_pattern("notes.txt"), "notes.txt");
+/// ```
+pub fn key_pattern(key: &str) -> String {
+    let (folder, name) = match key.rfind('/') {
+        Some(index) => key.split_at(index + 1),
+        None => ("", key),
+    };
+    let mut pattern = String::from(folder);
+    let mut in_digits = false;
+    for c in name.chars() {
+        match c.is_ascii_digit() {
+            true if in_digits => {}
+            true => pattern.push('*'),
+            false => pattern.push(c),
+        }
+        in_digits = c.is_ascii_digit();
+    }
+    pattern
+}
+
+/// The backups `retention` doesn't keep, sorted by key
+///
+/// With `keep_count` each [key_pattern] keeps its newest copies, so the last copy of a backup is
+/// never pruned. Objects without a last-modified time are never old enough to prune by age, and
+/// count as the oldest of their pattern.
+///
+/// ```
+/// use rust_test_s3_upload::prune::{select, Backup, Retention};
+/// use std::time::{Duration, SystemTime};
+///
+/// let now = SystemTime::now();
+/// let backup = |key: &str, days: u64| Backup {
+///     key: key.to_string(),
+///     size: 100,
+///     last_modified: Some(now - Duration::from_secs(days * 24 * 60 * 60)),
+/// };
+/// let backups = vec![
+///     backup("db-2024-01-01.sql", 40),
+///     backup("db-2024-01-10.sql", 31),
+///     backup("db-2024-02-01.sql", 9),
+///     backup("config-2023.tar", 400),
+/// ];
+/// let keys = |retention: Retention| -> Vec<String> {
+///     let pruned = select(backups.clone(), &retention, now);
+///     pruned.into_iter().map(|backup| backup.key).collect()
+/// };
+///
+/// let by_age = Retention { keep_days: Some(30), ..Default::default() };
+/// assert_eq!(keys(by_age), ["config-2023.tar", "db-2024-01-01.sql", "db-2024-01-10.sql"]);
+///
+/// // config-2023.tar is the only copy of its backup, so it stays
+/// let by_count = Retention { keep_count: Some(1), ..Default::default() };
+/// assert_eq!(keys(by_count), ["db-2024-01-01.sql", "db-2024-01-10.sql"]);
+///
+/// let both = Retention { keep_days: Some(35), keep_count: Some(1) };
+/// assert_eq!(keys(both), ["db-2024-01-01.sql"]);
+/// ```
+pub fn select(backups: Vec<Backup>, retention: &Retention, now: SystemTime) -> Vec<Backup> {
+    let old = ObjectFilter {
+        older_than: retention.keep_days.map(|days| DAY * days),
+        ..Default::default()
+    };
+    let mut patterns: BTreeMap<String, Vec<Backup>> = BTreeMap::new();
+    for backup in backups {
+        patterns
+            .entry(key_pattern(&backup.key))
+            .or_default()
+            .push(backup);
+    }
+    let mut pruned: Vec<Backup> = patterns
+        .into_values()
+        .flat_map(|mut copies| {
+            // newest first, None sorts before any time so it ends up last
+            copies.sort_by_key(|b| std::cmp::Reverse(b.last_modified));
+            copies
+                .into_iter()
+                .skip(retention.keep_count.unwrap_or(0) as usize)
+        })
+        .filter(|backup| old.matches(&backup.key, backup.size, backup.last_modified, now))
+        .collect();
+    pruned.sort_by(|a, b| a.key.cmp(&b.key));
+    pruned
+}
+
+/// Lists the objects under `prefix` that `filter` accepts and picks the backups `retention`
+/// doesn't keep, see [select], folder markers are never picked
+///
+/// ```
+/// use rust_test_s3_upload::filter::ObjectFilter;
+/// use rust_test_s3_upload::mock::MockStore;
+/// use rust_test_s3_upload::prune::{find, Retention};
+/// use rust_test_s3_upload::store::ObjectStore;
+/// use std::time::{Duration, SystemTime};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let now = SystemTime::now();
+/// let days_ago = |days: u64| now - Duration::from_secs(days * 24 * 60 * 60);
+/// let store = MockStore::default()
+///     .with_page_size(2)
+///     .with_object("db/db-2024-01-01.sql", b"old", days_ago(45))
+///     .with_object("db/db-2024-01-20.sql", b"old", days_ago(31))
+///     .with_object("db/db-2024-02-10.sql", b"new", days_ago(10))
+///     .with_object("db/db-2024-02-19.sql", b"new", days_ago(1))
+///     .with_object("db/", b"", days_ago(400));
+///
+/// let retention = Retention { keep_days: Some(30), ..Default::default() };
+/// let pruned = find(&store, "db/", &ObjectFilter::default(), &retention, now).await.unwrap();
+/// let keys: Vec<String> = pruned.into_iter().map(|backup| backup.key).collect();
+/// assert_eq!(keys, ["db/db-2024-01-01.sql", "db/db-2024-01-20.sql"]);
+///
+/// store.delete_many(&keys).await.unwrap();
+/// assert_eq!(store.keys(), ["db/", "db/db-2024-02-10.sql", "db/db-2024-02-19.sql"]);
+/// # }
+/// ```
+pub async fn find<S: ObjectStore>(
+    store: &S,
+    prefix: &str,
+    filter: &ObjectFilter,
+    retention: &Retention,
+    now: SystemTime,
+) -> Result<Vec<Backup>, S3Error> {
+    let backups: Vec<Backup> = store
+        .list_all(prefix)
+        .await?
+        .iter()
+        .filter(|object| filter.matches_object(object, now))
+        .filter_map(Backup::from_object)
+        .filter(|backup| !backup.key.ends_with('/'))
+        .collect();
+    Ok(select(backups, retention, now))
+}
@@ -0,0 +1,81 @@
+//! Byte ranges for partial downloads
+//!
+use std::fmt;
+use std::str::FromStr;
+
+/// A range of bytes in an object, `end` is inclusive like the HTTP Range header
+///
+/// ```
+/// use rust_test_s3_upload::range::ByteRange;
+///
+/// let range: ByteRange = "1048576-".parse().unwrap();
+/// assert_eq!(range.start, 1048576);
+/// assert_eq!(range.end, None);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    /// `None` reads to the end of the object
+    pub end: Option<u64>,
+}
+
+impl ByteRange {
+    /// Checks the range against the object size, returning the first and last byte that will be read
+    ///
+    /// ```
+    /// use rust_test_s3_upload::range::ByteRange;
+    ///
+    /// let range: ByteRange = "10-".parse().unwrap();
+    /// assert_eq!(range.resolve(100), Ok((10, 99)));
+    /// assert!(range.resolve(5).is_err());
+    /// ```
+    pub fn resolve(&self, size: u64) -> Result<(u64, u64), String> {
+        if self.start >= size {
+            return Err(format!(
+                "the range starts at byte {} but the object is only {} bytes",
+                self.start, size
+            ));
+        }
+        let end = match self.end {
+            // S3 truncates ranges that run past the end, so we do the same
+            Some(end) => end.min(size - 1),
+            None => size - 1,
+        };
+        Ok((self.start, end))
+    }
+}
+
+impl FromStr for ByteRange {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (start, end) = value
+            .split_once('-')
+            .ok_or_else(|| format!("{:?} should look like start-end or start-", value))?;
+        let start = start
+            .parse()
+            .map_err(|_| format!("{:?} is not a valid start byte", start))?;
+        let end = match end {
+            "" => None,
+            end => Some(
+                end.parse()
+                    .map_err(|_| format!("{:?} is not a valid end byte", end))?,
+            ),
+        };
+        if let Some(end) = end {
+            if end < start {
+                return Err(format!("the end of {:?} is before the start", value));
+            }
+        }
+        Ok(Self { start, end })
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.end {
+            Some(end) => write!(f, "{}-{}", self.start, end),
+            None => write!(f, "{}-", self.start),
+        }
+    }
+}
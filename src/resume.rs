@@ -0,0 +1,174 @@
+//! State files that let an interrupted multipart upload or download pick up where it left off
+//!
+use crate::client::S3FileInfo;
+use crate::error::S3Error;
+use serde_derive::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::debug;
+
+/// Added to the source file's name to get the state file's
+pub(crate) const STATE_SUFFIX: &str = ".s3upload.json";
+
+fn state_path(path: &Path) -> PathBuf {
+    let mut state_path = path.as_os_str().to_owned();
+    state_path.push(STATE_SUFFIX);
+    PathBuf::from(state_path)
+}
+
+/// Reads a state file, anything unreadable is treated as there being nothing to resume
+fn load<T: serde::de::DeserializeOwned>(path: &Path) -> Option<T> {
+    let contents = std::fs::read(path).ok()?;
+    match serde_json::from_slice(&contents) {
+        Ok(value) => Some(value),
+        Err(error) => {
+            debug!(
+                "Ignoring unreadable state file {}: {}",
+                path.display(),
+                error
+            );
+            None
+        }
+    }
+}
+
+/// Writes the state to a temporary file then renames it, so a crash never leaves half a file
+fn save<T: serde::Serialize>(state: &T, path: &Path) -> Result<(), S3Error> {
+    let write_error = |error| S3Error::FileWrite {
+        path: path.to_path_buf(),
+        source: error,
+    };
+    let contents = serde_json::to_vec_pretty(state)
+        .map_err(std::io::Error::from)
+        .map_err(write_error)?;
+    let temp_path = path.with_extension("json.tmp");
+    std::fs::write(&temp_path, contents).map_err(write_error)?;
+    std::fs::rename(&temp_path, path).map_err(write_error)
+}
+
+fn remove(path: &Path) {
+    if let Err(error) = std::fs::remove_file(path) {
+        if error.kind() != std::io::ErrorKind::NotFound {
+            debug!("Failed to remove state file {}: {}", path.display(), error);
+        }
+    }
+}
+
+/// A part the server has confirmed it received
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct UploadedPart {
+    pub part_number: i32,
+    pub etag: String,
+    /// The part's additional checksum, base64 encoded, if the upload has a checksum algorithm
+    #[serde(default)]
+    pub checksum: Option<String>,
+}
+
+/// What we need to know to carry on with a multipart upload, saved next to the source file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct UploadState {
+    pub bucket: String,
+    pub key: String,
+    pub upload_id: String,
+    pub part_size: u64,
+    /// The source file as it was when the upload started, if either changes the parts are stale
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// The additional checksum the upload was started with, every part has to be sent with it
+    #[serde(default)]
+    pub checksum_algorithm: Option<String>,
+    pub parts: Vec<UploadedPart>,
+}
+
+impl UploadState {
+    /// Where the state for uploading `path` is kept
+    pub fn path_for(path: &Path) -> PathBuf {
+        state_path(path)
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        load(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), S3Error> {
+        save(self, path)
+    }
+
+    pub fn remove(path: &Path) {
+        remove(path)
+    }
+
+    /// Checks the state is for this upload of this version of the file, with the same checksum
+    pub fn matches(
+        &self,
+        bucket: &str,
+        key: &str,
+        size: u64,
+        modified: Option<SystemTime>,
+        checksum_algorithm: Option<&str>,
+    ) -> bool {
+        self.bucket == bucket
+            && self.key == key
+            && self.size == size
+            && self.modified == modified
+            && self.checksum_algorithm.as_deref() == checksum_algorithm
+    }
+}
+
+/// Which version of an object a partial download is of, saved next to the partial file so it's
+/// only carried on with if the object hasn't changed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct DownloadState {
+    pub bucket: String,
+    pub key: String,
+    pub etag: String,
+    pub size: u64,
+    /// The object's additional checksum, base64 encoded, if it has one
+    #[serde(default)]
+    pub checksum: Option<String>,
+}
+
+impl DownloadState {
+    /// Where the state for the partial download at `path` is kept
+    pub fn path_for(path: &Path) -> PathBuf {
+        state_path(path)
+    }
+
+    pub fn load(path: &Path) -> Option<Self> {
+        load(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), S3Error> {
+        save(self, path)
+    }
+
+    pub fn remove(path: &Path) {
+        remove(path)
+    }
+
+    /// Why `info` isn't the object the partial download is of, `None` if it is
+    pub fn changed(&self, bucket: &str, key: &str, info: &S3FileInfo) -> Option<String> {
+        if self.bucket != bucket || self.key != key {
+            return Some(format!("it was of s3://{}/{}", self.bucket, self.key));
+        }
+        if self.etag != info.etag {
+            return Some(format!(
+                "its etag changed from {} to {}",
+                self.etag, info.etag
+            ));
+        }
+        if self.size != info.size {
+            return Some(format!(
+                "its size changed from {} to {} bytes",
+                self.size, info.size
+            ));
+        }
+        match (&self.checksum, &info.checksum) {
+            (Some(saved), Some(current)) if saved != current => Some(format!(
+                "its checksum changed from {} to {}",
+                saved, current
+            )),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! Trying operations again when S3 is throttling, failing on its end or can't be reached
+//!
+//! The SDK retries each request a few times itself, this is a slower second layer on top for
+//! when S3 stays busy for longer than that, eg a sustained 503 SlowDown.
+use crate::error::S3Error;
+use crate::stats::TransferStats;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tracing::{debug_span, warn, Instrument};
+
+pub const DEFAULT_RETRY_ATTEMPTS: u32 = 3;
+const INITIAL_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Error codes that mean S3 wants us to slow down or had a problem of its own
+const TRANSIENT_CODES: [&str; 9] = [
+    "SlowDown",
+    "Throttling",
+    "ThrottlingException",
+    "RequestThrottled",
+    "TooManyRequestsException",
+    "ServiceUnavailable",
+    "InternalError",
+    "RequestTimeout",
+    "RequestTimeoutException",
+];
+
+/// Error codes that won't go away by trying again, whatever the status code
+const PERMANENT_CODES: [&str; 6] = [
+    "AccessDenied",
+    "NoSuchBucket",
+    "InvalidAccessKeyId",
+    "SignatureDoesNotMatch",
+    "AllAccessDisabled",
+    "InvalidBucketName",
+];
+
+/// Checks if a request failed in a way that trying again later might fix
+///
+/// Timeouts, dropped connections, throttling and 5xx responses are, anything the server refused
+/// on its merits isn't.
+///
+/// ```
+/// use aws_sdk_s3::config::http::HttpResponse;
+/// use aws_sdk_s3::error::{ErrorMetadata, SdkError};
+/// use aws_sdk_s3::operation::head_object::HeadObjectError;
+/// use aws_smithy_types::body::SdkBody;
+/// use rust_test_s3_upload::retry::is_transient;
+///
+/// let error = |status: u16, code: &str| {
+///     SdkError::service_error(
+///         HeadObjectError::generic(ErrorMetadata::builder().code(code).build()),
+///         HttpResponse::new(status.try_into().unwrap(), SdkBody::empty()),
+///     )
+/// };
+/// assert!(is_transient(&error(503, "SlowDown")));
+/// assert!(is_transient(&error(500, "InternalError")));
+/// assert!(is_transient(&error(502, "")));
+/// assert!(!is_transient(&error(403, "AccessDenied")));
+/// assert!(!is_transient(&error(404, "NoSuchBucket")));
+/// assert!(!is_transient(&error(503, "AccessDenied")));
+/// ```
+pub fn is_transient<E: ProvideErrorMetadata>(error: &SdkError<E, HttpResponse>) -> bool {
+    match error {
+        SdkError::TimeoutError(_) | SdkError::ResponseError(_) => true,
+        SdkError::DispatchFailure(failure) => !failure.is_user(),
+        SdkError::ServiceError(_) => {
+            let code = error.code().unwrap_or_default();
+            if PERMANENT_CODES.contains(&code) {
+                return false;
+            }
+            let status = error
+                .raw_response()
+                .map(|response| response.status().as_u16())
+                .unwrap_or_default();
+            TRANSIENT_CODES.contains(&code) || status == 429 || status >= 500
+        }
+        _ => false,
+    }
+}
+
+/// How many times to try an operation, and how long to keep at it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Including the first attempt, 1 turns retrying off
+    pub max_attempts: u32,
+    /// Don't start another attempt once this much time has gone, unlimited if `None`
+    pub max_elapsed: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_RETRY_ATTEMPTS,
+            max_elapsed: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The longest wait before attempt `attempt + 1`, doubling each time up to 30 seconds
+    ///
+    /// ```
+    /// use rust_test_s3_upload::retry::RetryPolicy;
+    /// use std::time::Duration;
+    ///
+    /// let policy = RetryPolicy::default();
+    /// assert_eq!(policy.backoff(1), Duration::from_secs(1));
+    /// assert_eq!(policy.backoff(3), Duration::from_secs(4));
+    /// assert_eq!(policy.backoff(20), Duration::from_secs(30));
+    /// ```
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        INITIAL_DELAY
+            .checked_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .unwrap_or(MAX_DELAY)
+            .min(MAX_DELAY)
+    }
+
+    /// A random wait up to [RetryPolicy::backoff], so clients that failed together don't retry together
+    fn delay(&self, attempt: u32) -> Duration {
+        let mut bytes = [0u8; 4];
+        let fraction = match SystemRandom::new().fill(&mut bytes) {
+            Ok(()) => u32::from_be_bytes(bytes) as f64 / u32::MAX as f64,
+            Err(_) => 1.0,
+        };
+        self.backoff(attempt).mul_f64(fraction)
+    }
+
+    /// Runs `request` until it works, fails with an error that isn't transient, or runs out of
+    /// attempts or time
+    ///
+    /// Giving up on a transient error returns [S3Error::RetriesExhausted] with the last error as
+    /// its source, other errors are returned as they are. It all runs in a DEBUG span named for the
+    /// operation and key, so the SDK's requests are logged under it. Each retry is counted in
+    /// `stats` if there are any.
+    pub(crate) async fn run<T, F, Fut>(
+        &self,
+        operation: &'static str,
+        key: &str,
+        stats: Option<&TransferStats>,
+        request: F,
+    ) -> Result<T, S3Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, S3Error>>,
+    {
+        let span = debug_span!("s3", operation, key);
+        self.attempts(operation, key, stats, request)
+            .instrument(span)
+            .await
+    }
+
+    /// The body of [RetryPolicy::run]
+    async fn attempts<T, F, Fut>(
+        &self,
+        operation: &'static str,
+        key: &str,
+        stats: Option<&TransferStats>,
+        mut request: F,
+    ) -> Result<T, S3Error>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, S3Error>>,
+    {
+        let started = Instant::now();
+        let mut attempt = 1;
+        loop {
+            let error = match request().await {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            if !error.is_transient() {
+                return Err(error);
+            }
+            let delay = self.delay(attempt);
+            let out_of_time = self
+                .max_elapsed
+                .is_some_and(|max_elapsed| started.elapsed() + delay > max_elapsed);
+            if attempt >= self.max_attempts || out_of_time {
+                return Err(match attempt {
+                    1 => error,
+                    _ => S3Error::RetriesExhausted {
+                        operation,
+                        key: key.to_string(),
+                        attempts: attempt,
+                        source: Box::new(error),
+                    },
+                });
+            }
+            warn!(
+                "{} for {} failed on attempt {} of {}, retrying in {:.1}s: {}",
+                operation,
+                key,
+                attempt,
+                self.max_attempts,
+                delay.as_secs_f64(),
+                DisplayErrorContext(&error)
+            );
+            if let Some(stats) = stats {
+                stats.retried();
+            }
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
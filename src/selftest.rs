@@ -0,0 +1,152 @@
+//! A round trip through the bucket with an object of its own, to check the endpoint, credentials
+//! and settings work together before trusting them with a backup
+//!
+use crate::checksum::{etag_is_md5, ContentMd5};
+use crate::error::S3Error;
+use crate::store::ObjectStore;
+use serde_derive::Serialize;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Where the test object goes, under the prefix
+pub const SELFTEST_PREFIX: &str = "selftest/";
+
+/// One step of [run] that worked
+#[derive(Serialize, Debug, Clone)]
+pub struct Step {
+    pub operation: &'static str,
+    #[serde(rename = "duration_ms", serialize_with = "as_millis")]
+    pub elapsed: Duration,
+}
+
+fn as_millis<S: serde::Serializer>(elapsed: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(elapsed.as_millis() as u64)
+}
+
+/// Something to upload that's different for every key
+pub fn content(key: &str) -> Vec<u8> {
+    format!("s3upload selftest object {}\n", key)
+        .repeat(256)
+        .into_bytes()
+}
+
+/// Puts `body` at `key`, checks its size, etag and content come back the same, then deletes it
+/// and checks it's gone
+///
+/// If any step fails the object is deleted anyway, so nothing's left behind.
+///
+/// ```
+/// use rust_test_s3_upload::mock::MockStore;
+/// use rust_test_s3_upload::selftest;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let store = MockStore::default();
+/// let body = selftest::content("selftest/0123.txt");
+/// let steps = selftest::run(&store, "selftest/0123.txt", &body).await.unwrap();
+/// let operations: Vec<_> = steps.iter().map(|step| step.operation).collect();
+/// assert_eq!(operations, ["put", "head", "get", "delete", "head deleted"]);
+/// assert!(store.keys().is_empty());
+/// # }
+/// ```
+pub async fn run<S: ObjectStore>(store: &S, key: &str, body: &[u8]) -> Result<Vec<Step>, S3Error> {
+    let mut steps = Vec::new();
+    let result = round_trip(store, key, body, &mut steps).await;
+    if result.is_err() {
+        // deleting a key that isn't there works, so this is safe whichever step failed
+        if let Err(error) = store.delete(key).await {
+            warn!(
+                "Failed to delete {} after the selftest failed: {}",
+                key, error
+            );
+        }
+    }
+    result.map(|()| steps)
+}
+
+/// The steps of [run], each one added to `steps` as it works
+async fn round_trip<S: ObjectStore>(
+    store: &S,
+    key: &str,
+    body: &[u8],
+    steps: &mut Vec<Step>,
+) -> Result<(), S3Error> {
+    let mismatch = |attribute, expected: String, actual: String| S3Error::VerificationFailed {
+        key: key.to_string(),
+        attribute,
+        expected,
+        actual,
+    };
+    let md5 = ContentMd5::of(body);
+
+    let started = Instant::now();
+    store.put(key, body.to_vec()).await?;
+    steps.push(Step {
+        operation: "put",
+        elapsed: started.elapsed(),
+    });
+
+    let started = Instant::now();
+    let info = store.head(key).await?;
+    if info.size != body.len() as u64 {
+        return Err(mismatch(
+            "size",
+            body.len().to_string(),
+            info.size.to_string(),
+        ));
+    }
+    // with KMS or an SSE-C key the etag isn't the MD5
+    let server_side_encryption = info.server_side_encryption.as_deref();
+    if etag_is_md5(&info.etag, server_side_encryption, store.has_customer_key())
+        && !md5.matches_etag(&info.etag)
+    {
+        return Err(mismatch("etag", md5.hex(), info.etag));
+    }
+    steps.push(Step {
+        operation: "head",
+        elapsed: started.elapsed(),
+    });
+
+    let started = Instant::now();
+    let downloaded = store.get(key).await?;
+    if downloaded != body {
+        return Err(mismatch(
+            "content",
+            format!("{} bytes with MD5 {}", body.len(), md5.hex()),
+            format!(
+                "{} bytes with MD5 {}",
+                downloaded.len(),
+                ContentMd5::of(&downloaded).hex()
+            ),
+        ));
+    }
+    steps.push(Step {
+        operation: "get",
+        elapsed: started.elapsed(),
+    });
+
+    let started = Instant::now();
+    store.delete(key).await?;
+    steps.push(Step {
+        operation: "delete",
+        elapsed: started.elapsed(),
+    });
+
+    let started = Instant::now();
+    match store.head(key).await {
+        Err(S3Error::NotFound { .. }) => {}
+        Ok(_) => {
+            return Err(mismatch(
+                "deleted",
+                String::from("no object"),
+                String::from("the object is still there"),
+            ))
+        }
+        Err(error) => return Err(error),
+    }
+    steps.push(Step {
+        operation: "head deleted",
+        elapsed: started.elapsed(),
+    });
+    Ok(())
+}
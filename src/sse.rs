@@ -0,0 +1,81 @@
+//! Customer-provided keys for SSE-C, where S3 encrypts with a key we send on every request
+//!
+use crate::config::ConfigError;
+use aws_smithy_types::base64;
+use md5::{Digest, Md5};
+use std::fmt;
+use std::path::Path;
+
+/// The only algorithm SSE-C supports
+pub const SSE_C_ALGORITHM: &str = "AES256";
+const KEY_LENGTH: usize = 32;
+
+/// A 256-bit SSE-C key, the same one has to be sent to read the object back
+///
+/// ```
+/// use rust_test_s3_upload::sse::CustomerKey;
+///
+/// let key = CustomerKey::from_base64("MDEyMzQ1Njc4OTAxMjM0NTY3ODkwMTIzNDU2Nzg5MDE=").unwrap();
+/// assert_eq!(key.key_md5(), "KYvwGXoFFJ42a2u2GDWhwQ==");
+/// // the key itself is never printed
+/// assert!(!format!("{:?}", key).contains("MDEy"));
+/// assert!(CustomerKey::from_base64("dG9vIHNob3J0").is_err());
+/// ```
+#[derive(Clone)]
+pub struct CustomerKey {
+    key: String,
+    key_md5: String,
+}
+
+impl CustomerKey {
+    /// Parses a base64 encoded key, as sent in the SSE-C headers
+    pub fn from_base64(value: &str) -> Result<Self, ConfigError> {
+        let bytes = base64::decode(value.trim()).map_err(|error| {
+            ConfigError::InvalidCustomerKey(format!("it isn't valid base64: {}", error))
+        })?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Reads a key file, either the 32 raw bytes or them base64 encoded
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read(path).map_err(|error| {
+            ConfigError::InvalidCustomerKey(format!("failed to read {}: {}", path.display(), error))
+        })?;
+        match contents.len() == KEY_LENGTH {
+            true => Self::from_bytes(&contents),
+            false => Self::from_base64(&String::from_utf8_lossy(&contents)),
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, ConfigError> {
+        if bytes.len() != KEY_LENGTH {
+            return Err(ConfigError::InvalidCustomerKey(format!(
+                "it's {} bytes but SSE-C keys are {}",
+                bytes.len(),
+                KEY_LENGTH
+            )));
+        }
+        Ok(Self {
+            key: base64::encode(bytes),
+            key_md5: base64::encode(Md5::digest(bytes)),
+        })
+    }
+
+    /// The key, base64 encoded
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The base64 encoded MD5 of the key, which S3 uses to check it arrived intact
+    pub fn key_md5(&self) -> &str {
+        &self.key_md5
+    }
+}
+
+impl fmt::Debug for CustomerKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomerKey")
+            .field("key_md5", &self.key_md5)
+            .finish_non_exhaustive()
+    }
+}
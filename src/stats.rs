@@ -0,0 +1,255 @@
+//! Counting what a run transferred, so it can end with one line like "uploaded 142 files, 18.4 GiB
+//! in 6m12s (50.6 MiB/s), 3 skipped, 1 failed, 2 retries"
+//!
+use serde_derive::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A size in the largest unit it's at least one of, eg "12.3 MiB"
+///
+/// ```
+/// use rust_test_s3_upload::stats::format_size;
+///
+/// assert_eq!(format_size(512), "512 B");
+/// assert_eq!(format_size(3 * 1024 * 1024 / 2), "1.5 MiB");
+/// ```
+pub fn format_size(bytes: u64) -> String {
+    let units = [("GiB", 1u64 << 30), ("MiB", 1 << 20), ("KiB", 1 << 10)];
+    match units.iter().find(|(_, size)| bytes >= *size) {
+        Some((unit, size)) => format!("{:.1} {}", bytes as f64 / *size as f64, unit),
+        None => format!("{} B", bytes),
+    }
+}
+
+/// Average speed of a transfer, eg "1.5 MiB/s"
+///
+/// ```
+/// use rust_test_s3_upload::stats::format_rate;
+/// use std::time::Duration;
+///
+/// assert_eq!(format_rate(3 * 1024 * 1024, Duration::from_secs(2)), "1.5 MiB/s");
+/// ```
+pub fn format_rate(bytes: u64, elapsed: Duration) -> String {
+    let rate = bytes as f64 / elapsed.as_secs_f64().max(0.001);
+    format!("{}/s", format_size(rate as u64))
+}
+
+/// How long something took, to the second once it's over a minute, eg "6m12s"
+///
+/// ```
+/// use rust_test_s3_upload::stats::format_elapsed;
+/// use std::time::Duration;
+///
+/// assert_eq!(format_elapsed(Duration::from_millis(2500)), "2.5s");
+/// assert_eq!(format_elapsed(Duration::from_secs(372)), "6m12s");
+/// assert_eq!(format_elapsed(Duration::from_secs(7384)), "2h3m4s");
+/// ```
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    match seconds {
+        0..=59 => format!("{:.1}s", elapsed.as_secs_f64()),
+        60..=3599 => format!("{}m{}s", seconds / 60, seconds % 60),
+        _ => format!(
+            "{}h{}m{}s",
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60
+        ),
+    }
+}
+
+/// Which way an object went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+/// The counters behind [TransferStats]
+#[derive(Debug, Default)]
+struct Counters {
+    uploaded: AtomicU64,
+    downloaded: AtomicU64,
+    deleted: AtomicU64,
+    bytes: AtomicU64,
+    skipped: AtomicU64,
+    failed: AtomicU64,
+    retries: AtomicU64,
+    slowest: Mutex<Option<Slowest>>,
+}
+
+/// Counts for a whole run, updated as each object finishes
+///
+/// Clones share the same counts, so it can be handed to every task that's transferring at once.
+///
+/// ```
+/// use rust_test_s3_upload::stats::{Direction, TransferStats};
+/// use std::time::Duration;
+///
+/// let stats = TransferStats::default();
+/// let task = stats.clone();
+/// task.transferred(Direction::Upload, "db.sql", 3 << 30, Duration::from_secs(90));
+/// task.transferred(Direction::Upload, "notes.txt", 1 << 20, Duration::from_secs(1));
+/// task.skipped();
+/// task.failed();
+/// task.retried();
+///
+/// let summary = stats.summary();
+/// assert_eq!((summary.uploaded, summary.handled()), (2, 4));
+/// assert_eq!(summary.slowest.as_ref().map(|slowest| slowest.key.as_str()), Some("db.sql"));
+/// assert_eq!(
+///     summary.describe(Duration::from_secs(120)),
+///     "uploaded 2 files, 3.0 GiB in 2m0s (25.6 MiB/s), 1 skipped, 1 failed, 1 retry, slowest db.sql (3.0 GiB in 1m30s)"
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TransferStats {
+    counters: Arc<Counters>,
+}
+
+impl TransferStats {
+    /// Counts an object that was sent or received whole
+    pub fn transferred(&self, direction: Direction, key: &str, bytes: u64, elapsed: Duration) {
+        let counter = match direction {
+            Direction::Upload => &self.counters.uploaded,
+            Direction::Download => &self.counters.downloaded,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.counters.bytes.fetch_add(bytes, Ordering::Relaxed);
+        let mut slowest = match self.counters.slowest.lock() {
+            Ok(value) => value,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let slower = match slowest.as_ref() {
+            Some(slowest) => elapsed > slowest.elapsed,
+            None => true,
+        };
+        if slower {
+            *slowest = Some(Slowest {
+                key: key.to_string(),
+                size: bytes,
+                elapsed,
+            });
+        }
+    }
+
+    pub fn deleted(&self) {
+        self.counters.deleted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an object there was nothing to do for, eg because it was already up to date
+    pub fn skipped(&self) {
+        self.counters.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn failed(&self) {
+        self.counters.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts one more attempt at a request that failed, on top of the SDK's own retries
+    pub fn retried(&self) {
+        self.counters.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The counts so far
+    pub fn summary(&self) -> StatsSummary {
+        let counters = &self.counters;
+        StatsSummary {
+            uploaded: counters.uploaded.load(Ordering::Relaxed),
+            downloaded: counters.downloaded.load(Ordering::Relaxed),
+            deleted: counters.deleted.load(Ordering::Relaxed),
+            bytes: counters.bytes.load(Ordering::Relaxed),
+            skipped: counters.skipped.load(Ordering::Relaxed),
+            failed: counters.failed.load(Ordering::Relaxed),
+            retries: counters.retries.load(Ordering::Relaxed),
+            slowest: match counters.slowest.lock() {
+                Ok(value) => value.clone(),
+                Err(poisoned) => poisoned.into_inner().clone(),
+            },
+        }
+    }
+}
+
+/// The object that took longest to transfer
+#[derive(Serialize, Debug, Clone)]
+pub struct Slowest {
+    pub key: String,
+    pub size: u64,
+    #[serde(rename = "duration_ms", serialize_with = "as_millis")]
+    pub elapsed: Duration,
+}
+
+fn as_millis<S: serde::Serializer>(elapsed: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(elapsed.as_millis() as u64)
+}
+
+/// The counts from [TransferStats] at one point in time
+#[derive(Serialize, Debug, Clone)]
+pub struct StatsSummary {
+    pub uploaded: u64,
+    pub downloaded: u64,
+    pub deleted: u64,
+    /// Uploaded and downloaded together
+    pub bytes: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub retries: u64,
+    pub slowest: Option<Slowest>,
+}
+
+impl StatsSummary {
+    /// How many objects were counted, whatever happened to them
+    pub fn handled(&self) -> u64 {
+        self.uploaded + self.downloaded + self.deleted + self.skipped + self.failed
+    }
+
+    /// One line for a person, with `elapsed` as how long the whole run took
+    pub fn describe(&self, elapsed: Duration) -> String {
+        let files = |count: u64| match count {
+            1 => String::from("1 file"),
+            _ => format!("{} files", count),
+        };
+        let mut parts: Vec<String> = [
+            ("uploaded", self.uploaded),
+            ("downloaded", self.downloaded),
+            ("deleted", self.deleted),
+        ]
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(verb, count)| format!("{} {}", verb, files(count)))
+        .collect();
+        if parts.is_empty() {
+            parts.push(String::from("transferred nothing"));
+        }
+        let mut line = parts.join(", ");
+        if self.bytes > 0 {
+            line.push_str(&format!(
+                ", {} in {} ({})",
+                format_size(self.bytes),
+                format_elapsed(elapsed),
+                format_rate(self.bytes, elapsed)
+            ));
+        }
+        if self.skipped > 0 {
+            line.push_str(&format!(", {} skipped", self.skipped));
+        }
+        if self.failed > 0 {
+            line.push_str(&format!(", {} failed", self.failed));
+        }
+        match self.retries {
+            0 => {}
+            1 => line.push_str(", 1 retry"),
+            retries => line.push_str(&format!(", {} retries", retries)),
+        }
+        if let Some(slowest) = &self.slowest {
+            line.push_str(&format!(
+                ", slowest {} ({} in {})",
+                slowest.key,
+                format_size(slowest.size),
+                format_elapsed(slowest.elapsed)
+            ));
+        }
+        line
+    }
+}
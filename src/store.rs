@@ -0,0 +1,189 @@
+//! The object operations the higher-level logic needs, as a trait so it can run against something
+//! other than S3, eg a [MockStore](crate::mock::MockStore) in tests
+//!
+use crate::client::{
+    CopyOptions, CopyResult, DeleteResult, S3Client, S3FileInfo, UploadOptions, UploadResult,
+};
+use crate::error::S3Error;
+use crate::walk::FoundFile;
+use aws_sdk_s3::types::Object;
+use std::future::Future;
+
+/// One page of a listing from [ObjectStore::list_objects]
+#[derive(Debug, Default)]
+pub struct StorePage {
+    pub objects: Vec<Object>,
+    /// Where the next page starts, `None` on the last page
+    pub next: Option<String>,
+}
+
+/// Somewhere objects are kept, [S3Client] for S3 itself
+///
+/// The futures are `Send`, so they can be spawned. Methods with a default are built on the others,
+/// a store only overrides them when it can do better, like [S3Client] deleting in batches.
+///
+/// ```
+/// use rust_test_s3_upload::mock::MockStore;
+/// use rust_test_s3_upload::store::ObjectStore;
+///
+/// // code that takes any store works the same against S3 and the mock
+/// async fn backup_sizes(store: &impl ObjectStore, prefix: &str) -> u64 {
+///     let objects = store.list_all(prefix).await.unwrap();
+///     objects.iter().filter_map(|object| object.size()).sum::<i64>() as u64
+/// }
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let store = MockStore::default().with_page_size(1);
+/// store.put("hosts/web1/db.sql", b"select 1;".to_vec()).await.unwrap();
+/// store.put("hosts/web1/notes.txt", b"hi".to_vec()).await.unwrap();
+/// store.put("hosts/web2/db.sql", b"select 2;".to_vec()).await.unwrap();
+/// assert_eq!(backup_sizes(&store, "hosts/web1/").await, 11);
+/// # }
+/// ```
+pub trait ObjectStore: Sync {
+    /// Stores `body` at `key`, replacing anything that's there
+    fn put(
+        &self,
+        key: &str,
+        body: Vec<u8>,
+    ) -> impl Future<Output = Result<UploadResult, S3Error>> + Send;
+
+    /// The whole content of `key`
+    fn get(&self, key: &str) -> impl Future<Output = Result<Vec<u8>, S3Error>> + Send;
+
+    /// The metadata of `key`, [S3Error::NotFound] if there's nothing there
+    fn head(&self, key: &str) -> impl Future<Output = Result<S3FileInfo, S3Error>> + Send;
+
+    fn delete(&self, key: &str) -> impl Future<Output = Result<DeleteResult, S3Error>> + Send;
+
+    /// One page of the objects with keys starting with `prefix`, in key order, from where
+    /// `continuation` says the last page stopped
+    fn list_objects(
+        &self,
+        prefix: &str,
+        continuation: Option<&str>,
+    ) -> impl Future<Output = Result<StorePage, S3Error>> + Send;
+
+    /// Copies `source_key` to `key` within the store
+    fn copy(
+        &self,
+        source_key: &str,
+        key: &str,
+    ) -> impl Future<Output = Result<CopyResult, S3Error>> + Send;
+
+    /// Objects are read and written with an SSE-C key, so their etags aren't an MD5
+    fn has_customer_key(&self) -> bool {
+        false
+    }
+
+    /// Every object with a key starting with `prefix`, going through the pages
+    fn list_all(&self, prefix: &str) -> impl Future<Output = Result<Vec<Object>, S3Error>> + Send {
+        async move {
+            let mut objects = Vec::new();
+            let mut continuation = None;
+            loop {
+                let page = self.list_objects(prefix, continuation.as_deref()).await?;
+                objects.extend(page.objects);
+                match page.next {
+                    Some(next) => continuation = Some(next),
+                    None => return Ok(objects),
+                }
+            }
+        }
+    }
+
+    /// HEADs the object for each file, up to `jobs` at a time if the store can, returning the
+    /// results in the same order as `files`
+    fn head_many(
+        &self,
+        files: &[FoundFile],
+        _jobs: usize,
+    ) -> impl Future<Output = Vec<(FoundFile, Result<S3FileInfo, S3Error>)>> + Send {
+        async move {
+            let mut results = Vec::with_capacity(files.len());
+            for file in files {
+                results.push((file.clone(), self.head(&file.key).await));
+            }
+            results
+        }
+    }
+
+    /// Deletes each key, returning a result for each in the order given, or an error if the
+    /// store couldn't be asked at all
+    fn delete_many(
+        &self,
+        keys: &[String],
+    ) -> impl Future<Output = Result<Vec<Result<DeleteResult, S3Error>>, S3Error>> + Send {
+        async move {
+            let mut results = Vec::with_capacity(keys.len());
+            for key in keys {
+                results.push(self.delete(key).await);
+            }
+            Ok(results)
+        }
+    }
+}
+
+/// S3 through the client, with its retries, rate limit and any SSE-C key
+impl ObjectStore for S3Client {
+    async fn put(&self, key: &str, body: Vec<u8>) -> Result<UploadResult, S3Error> {
+        S3Client::upload_reader(self, body.as_slice(), key, &UploadOptions::default()).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, S3Error> {
+        let mut body = Vec::new();
+        S3Client::download_to(self, key, &mut body).await?;
+        Ok(body)
+    }
+
+    async fn head(&self, key: &str) -> Result<S3FileInfo, S3Error> {
+        S3Client::head(self, key).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<DeleteResult, S3Error> {
+        S3Client::delete(self, key).await
+    }
+
+    async fn list_objects(
+        &self,
+        prefix: &str,
+        continuation: Option<&str>,
+    ) -> Result<StorePage, S3Error> {
+        let page = self.list_page(prefix, None, None, continuation).await?;
+        Ok(StorePage {
+            objects: page.contents.unwrap_or_default(),
+            next: match page.is_truncated {
+                Some(true) => page.next_continuation_token,
+                _ => None,
+            },
+        })
+    }
+
+    async fn copy(&self, source_key: &str, key: &str) -> Result<CopyResult, S3Error> {
+        S3Client::copy(self, source_key, key, &CopyOptions::default()).await
+    }
+
+    fn has_customer_key(&self) -> bool {
+        S3Client::has_customer_key(self)
+    }
+
+    async fn list_all(&self, prefix: &str) -> Result<Vec<Object>, S3Error> {
+        S3Client::list_all(self, prefix).await
+    }
+
+    async fn head_many(
+        &self,
+        files: &[FoundFile],
+        jobs: usize,
+    ) -> Vec<(FoundFile, Result<S3FileInfo, S3Error>)> {
+        self.head_files(files, jobs).await
+    }
+
+    async fn delete_many(
+        &self,
+        keys: &[String],
+    ) -> Result<Vec<Result<DeleteResult, S3Error>>, S3Error> {
+        self.delete_objects(keys).await
+    }
+}
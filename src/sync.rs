@@ -0,0 +1,507 @@
+//! Working out what a sync between a local directory and a prefix in S3 needs to transfer
+//!
+use crate::checksum::{etag_is_md5, ContentMd5};
+use crate::client::S3FileInfo;
+use crate::error::S3Error;
+use crate::filter::PathFilter;
+use crate::manifest::ManifestEntry;
+use crate::metadata::{parse_mtime, MTIME_METADATA};
+use crate::store::ObjectStore;
+use crate::walk::{find_files, join_key, local_path, FoundFile, Links};
+use aws_sdk_s3::types::Object;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// How a local file is compared with its object to decide if it's changed, the sizes always have
+/// to match
+///
+/// ```
+/// use rust_test_s3_upload::sync::Compare;
+///
+/// assert_eq!("checksum".parse::<Compare>(), Ok(Compare::Checksum));
+/// assert_eq!("MTIME".parse::<Compare>(), Ok(Compare::Mtime));
+/// assert!("hash".parse::<Compare>().is_err());
+/// assert_eq!(Compare::default().to_string(), "mtime");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compare {
+    /// Nothing else, quick but misses edits that keep the size the same
+    Size,
+    /// The copy being synced to is at least as new as the one being synced from
+    ///
+    /// An object's LastModified is when it was uploaded, so if that doesn't settle it the
+    /// modification time an upload recorded in its `mtime` metadata is looked up and used instead,
+    /// to the second. That's what a restore sets files back to.
+    ///
+    /// Listings don't include metadata, so every object the same size as its file but older than
+    /// it costs a HEAD, [MTIME_LOOKUPS] at a time. Restoring into a directory of files that are
+    /// all newer than their uploads, eg a fresh copy, does one for each object.
+    #[default]
+    Mtime,
+    /// The etag is the file's MD5, so every file is read to hash it
+    ///
+    /// Multipart and SSE-C etags aren't an MD5, those fall back to [Compare::Mtime]. KMS etags
+    /// aren't either but listings don't say which objects use KMS, so those are always sent again.
+    Checksum,
+}
+
+impl FromStr for Compare {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "size" => Ok(Self::Size),
+            "mtime" => Ok(Self::Mtime),
+            "checksum" => Ok(Self::Checksum),
+            _ => Err(format!(
+                "{:?} isn't a comparison, use size, mtime or checksum",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Compare {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Size => "size",
+            Self::Mtime => "mtime",
+            Self::Checksum => "checksum",
+        })
+    }
+}
+
+/// How many objects' recorded modification times are looked up at a time, see [Compare::Mtime]
+pub const MTIME_LOOKUPS: usize = 16;
+
+/// What a sync needs to do, from comparing the local files with the objects under the prefix
+#[derive(Debug)]
+pub struct SyncPlan {
+    /// Files that are new or changed, with the keys they go to
+    pub uploads: Vec<FoundFile>,
+    /// Files their objects already match
+    pub unchanged: Vec<FoundFile>,
+    /// Keys under the prefix that `filter` accepts with no local file, folder markers aren't included
+    pub extra: Vec<String>,
+    /// Files and directories that couldn't be read, while walking or comparing
+    pub unreadable: Vec<(PathBuf, std::io::Error)>,
+    /// Every object under the prefix, by key
+    pub remote: HashMap<String, Object>,
+}
+
+impl SyncPlan {
+    /// How many local files were compared
+    pub fn examined(&self) -> usize {
+        self.uploads.len() + self.unchanged.len()
+    }
+}
+
+/// Walks `root` for the files `filter` accepts and lists everything under `prefix`, sorting the
+/// files into those that need uploading and those that are unchanged, and finding the objects
+/// that aren't in the directory
+///
+/// Symlinks are handled as `links` says, see [find_files].
+///
+/// Files that [ManifestEntry::matches_file] for an entry in `previous`, with the object still
+/// having the etag it recorded, are unchanged without comparing them.
+///
+/// ```
+/// use rust_test_s3_upload::filter::PathFilter;
+/// use rust_test_s3_upload::mock::MockStore;
+/// use rust_test_s3_upload::sync::{plan, Compare};
+/// use rust_test_s3_upload::walk::{FoundFile, Links};
+/// use std::time::{Duration, SystemTime};
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let root = std::env::temp_dir().join("sync-plan-doctest");
+/// std::fs::create_dir_all(&root).unwrap();
+/// std::fs::write(root.join("same.txt"), "unchanged").unwrap();
+/// std::fs::write(root.join("edited.txt"), "a longer edit").unwrap();
+/// std::fs::write(root.join("new.txt"), "new").unwrap();
+/// // uploaded after the files were written, so only a different size means a change
+/// let later = SystemTime::now() + Duration::from_secs(60);
+/// let store = MockStore::default()
+///     .with_page_size(2)
+///     .with_object("web1/same.txt", b"unchanged", later)
+///     .with_object("web1/edited.txt", b"an edit", later)
+///     .with_object("web1/gone.txt", b"deleted locally", later);
+///
+/// let plan = plan(&store, &root, "web1", &PathFilter::default(), Compare::Mtime, Links::Follow, &[]).await.unwrap();
+/// let keys = |files: &[FoundFile]| -> Vec<String> { files.iter().map(|file| file.key.clone()).collect() };
+/// assert_eq!(keys(&plan.uploads), ["web1/edited.txt", "web1/new.txt"]);
+/// assert_eq!(keys(&plan.unchanged), ["web1/same.txt"]);
+/// assert_eq!(plan.extra, ["web1/gone.txt"]);
+/// // planning only looks
+/// assert!(store.put_keys().is_empty() && store.deleted().is_empty());
+/// std::fs::remove_dir_all(&root).unwrap();
+/// # }
+/// ```
+///
+/// ```no_run
+/// # async fn example(client: rust_test_s3_upload::S3Client) -> Result<(), rust_test_s3_upload::S3Error> {
+/// use rust_test_s3_upload::client::UploadOptions;
+/// use rust_test_s3_upload::filter::PathFilter;
+/// use rust_test_s3_upload::sync::{plan, Compare};
+/// use rust_test_s3_upload::walk::Links;
+///
+/// let plan = plan(&client, "data".as_ref(), "hosts/web1", &PathFilter::default(), Compare::Mtime, Links::Follow, &[]).await?;
+/// let results = client.upload_files(&plan.uploads, &UploadOptions::default(), 4).await;
+/// println!("{} unchanged, {} uploaded", plan.unchanged.len(), results.len());
+/// # Ok(())
+/// # }
+/// ```
+pub async fn plan<S: ObjectStore>(
+    client: &S,
+    root: &Path,
+    prefix: &str,
+    filter: &PathFilter,
+    compare: Compare,
+    links: Links,
+    previous: &[ManifestEntry],
+) -> Result<SyncPlan, S3Error> {
+    let (files, mut unreadable) = find_files(root, prefix, filter, links)?;
+    let previous: HashMap<&Path, &ManifestEntry> = previous
+        .iter()
+        .map(|entry| (entry.path.as_path(), entry))
+        .collect();
+    let listed = join_key(prefix, "");
+    let remote: HashMap<String, Object> = client
+        .list_all(&listed)
+        .await?
+        .into_iter()
+        .filter_map(|object| {
+            let key = object.key()?.to_string();
+            Some((key, object))
+        })
+        .collect();
+    let local: HashSet<&str> = files.iter().map(|file| file.key.as_str()).collect();
+    let mut extra: Vec<String> = remote
+        .keys()
+        .filter(|key| !key.ends_with('/') && !local.contains(key.as_str()))
+        .filter(|key| filter.matches(key.strip_prefix(&listed).unwrap_or(key)))
+        .cloned()
+        .collect();
+    extra.sort();
+
+    let mut checked = Vec::with_capacity(files.len());
+    for file in files {
+        let Some(object) = remote.get(&file.key) else {
+            checked.push((file, Ok(Compared::Differs)));
+            continue;
+        };
+        if let Some(entry) = previous.get(file.path.as_path()) {
+            let etag = object.e_tag().map(|etag| etag.trim_matches('"'));
+            let stored = entry.etag.as_deref() == etag
+                && std::fs::metadata(&file.path)
+                    .is_ok_and(|metadata| entry.matches_file(&file, &metadata));
+            if stored {
+                checked.push((file, Ok(Compared::Matches)));
+                continue;
+            }
+        }
+        let compared = compare_object(client, &file.path, object, compare, Direction::Up).await;
+        checked.push((file, compared));
+    }
+
+    let mut uploads = Vec::new();
+    let mut unchanged = Vec::new();
+    for (file, result) in settle(client, checked, Direction::Up).await {
+        match result {
+            Ok(true) => unchanged.push(file),
+            Ok(false) => uploads.push(file),
+            Err(error) => unreadable.push((file.path, error)),
+        }
+    }
+    Ok(SyncPlan {
+        uploads,
+        unchanged,
+        extra,
+        unreadable,
+        remote,
+    })
+}
+
+/// What a restore needs to do, from comparing the objects under the prefix with the local files
+#[derive(Debug)]
+pub struct RestorePlan {
+    /// Objects that aren't in the directory or have changed, with the paths they go to
+    pub downloads: Vec<FoundFile>,
+    /// Objects their files already match
+    pub unchanged: Vec<FoundFile>,
+    /// Local files that `filter` accepts with no object under the prefix
+    pub extra: Vec<PathBuf>,
+    /// Files and directories that couldn't be read, while walking or comparing
+    pub unreadable: Vec<(PathBuf, std::io::Error)>,
+    /// Every object under the prefix, by key
+    pub remote: HashMap<String, Object>,
+}
+
+impl RestorePlan {
+    /// How many objects were compared
+    pub fn examined(&self) -> usize {
+        self.downloads.len() + self.unchanged.len()
+    }
+}
+
+/// Lists everything under `prefix` that `filter` accepts and walks `root`, sorting the objects into
+/// those that need downloading and those that are unchanged, and finding the local files that
+/// aren't in S3
+///
+/// Keys are mapped to paths under `root` by [local_path], folder markers are left out. Symlinks
+/// under `root` are handled as `links` says, see [find_files]. A local file is only extra if it
+/// isn't where an object is restored to and wouldn't be uploaded to one of the keys either.
+///
+/// ```
+/// use rust_test_s3_upload::filter::PathFilter;
+/// use rust_test_s3_upload::mock::MockStore;
+/// use rust_test_s3_upload::sync::{plan_restore, Compare};
+/// use rust_test_s3_upload::walk::Links;
+/// use std::time::SystemTime;
+///
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// # #[cfg(unix)]
+/// # {
+/// let root = std::env::temp_dir().join(format!("plan-restore-doctest-{}", std::process::id()));
+/// std::fs::create_dir_all(root.join("logs")).unwrap();
+/// std::fs::write(root.join("logs/12:00.log"), "noon").unwrap();
+/// std::fs::write(root.join("logs/old.log"), "gone").unwrap();
+/// let store = MockStore::default().with_object("web1/logs/12:00.log", b"noon", SystemTime::now());
+///
+/// let plan = plan_restore(&store, "web1", &root, &PathFilter::default(), Compare::Size, Links::Follow).await.unwrap();
+/// assert_eq!(plan.unchanged[0].path, root.join("logs/12:00.log"));
+/// assert_eq!(plan.extra, [root.join("logs/old.log")]);
+/// std::fs::remove_dir_all(&root).unwrap();
+/// # }
+/// # }
+/// ```
+///
+/// ```no_run
+/// # async fn example(client: rust_test_s3_upload::S3Client) -> Result<(), rust_test_s3_upload::S3Error> {
+/// use rust_test_s3_upload::client::DownloadOptions;
+/// use rust_test_s3_upload::filter::PathFilter;
+/// use rust_test_s3_upload::sync::{plan_restore, Compare};
+/// use rust_test_s3_upload::walk::Links;
+///
+/// let plan = plan_restore(&client, "hosts/web1", "restore".as_ref(), &PathFilter::default(), Compare::Mtime, Links::Follow).await?;
+/// let options = DownloadOptions {
+///     force: true,
+///     ..Default::default()
+/// };
+/// let results = client.download_files(&plan.downloads, &options, 4).await;
+/// println!("{} unchanged, {} downloaded", plan.unchanged.len(), results.len());
+/// # Ok(())
+/// # }
+/// ```
+pub async fn plan_restore<S: ObjectStore>(
+    client: &S,
+    prefix: &str,
+    root: &Path,
+    filter: &PathFilter,
+    compare: Compare,
+    links: Links,
+) -> Result<RestorePlan, S3Error> {
+    let listed = join_key(prefix, "");
+    let remote: HashMap<String, Object> = client
+        .list_all(&listed)
+        .await?
+        .into_iter()
+        .filter_map(|object| {
+            let key = object.key()?.to_string();
+            Some((key, object))
+        })
+        .collect();
+    // a restore into a new directory has nothing to compare with yet
+    let (local, mut unreadable) = match root.exists() {
+        true => find_files(root, "", filter, links)?,
+        false => (Vec::new(), Vec::new()),
+    };
+
+    let mut checked = Vec::new();
+    let mut expected = HashSet::new();
+    let mut keys: Vec<&String> = remote.keys().collect();
+    keys.sort();
+    for key in keys {
+        let relative = key.strip_prefix(&listed).unwrap_or(key);
+        if !filter.matches(relative) {
+            continue;
+        }
+        let path = match local_path(relative) {
+            Ok(Some(value)) => value,
+            Ok(None) => continue,
+            Err(message) => {
+                let error = std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("refusing to restore {}, {}", key, message),
+                );
+                unreadable.push((root.to_path_buf(), error));
+                continue;
+            }
+        };
+        let file = FoundFile {
+            path: root.join(path),
+            key: key.clone(),
+            symlink: false,
+        };
+        expected.insert(file.path.clone());
+        let compared =
+            compare_object(client, &file.path, &remote[key], compare, Direction::Down).await;
+        checked.push((file, compared));
+    }
+
+    let mut downloads = Vec::new();
+    let mut unchanged = Vec::new();
+    for (file, result) in settle(client, checked, Direction::Down).await {
+        match result {
+            Ok(true) => unchanged.push(file),
+            Ok(false) => downloads.push(file),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => downloads.push(file),
+            Err(error) => unreadable.push((file.path, error)),
+        }
+    }
+    // a file that uploads to one of the keys is never extra, even if the object would be
+    // restored under a different name
+    let relative: HashSet<&str> = remote
+        .keys()
+        .map(|key| key.strip_prefix(&listed).unwrap_or(key))
+        .collect();
+    let extra = local
+        .into_iter()
+        .filter(|file| !expected.contains(&file.path) && !relative.contains(file.key.as_str()))
+        .map(|file| file.path)
+        .collect();
+    Ok(RestorePlan {
+        downloads,
+        unchanged,
+        extra,
+        unreadable,
+        remote,
+    })
+}
+
+/// Which way a sync is going, so the right copy has to be the newer one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    Up,
+    Down,
+}
+
+impl Direction {
+    /// Checks the copy being synced to is at least as new as the one being synced from
+    fn newer(self, object: SystemTime, file: SystemTime) -> bool {
+        match self {
+            Self::Up => object >= file,
+            Self::Down => file >= object,
+        }
+    }
+}
+
+/// What comparing a file with the object listed for it found, before any HEADs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Compared {
+    Matches,
+    Differs,
+    /// It's down to the modification time the object recorded, compared with the file's
+    Recorded(SystemTime),
+}
+
+/// Checks if the object listed for a file and the file have the same content, as far as the
+/// listing can tell
+async fn compare_object<S: ObjectStore>(
+    client: &S,
+    path: &Path,
+    object: &Object,
+    compare: Compare,
+    direction: Direction,
+) -> std::io::Result<Compared> {
+    let metadata = tokio::fs::metadata(path).await?;
+    let size = object.size().and_then(|value| u64::try_from(value).ok());
+    if size != Some(metadata.len()) {
+        return Ok(Compared::Differs);
+    }
+    let modified = metadata.modified().ok();
+    let newer = match (
+        object
+            .last_modified()
+            .and_then(|value| SystemTime::try_from(*value).ok()),
+        modified,
+    ) {
+        (Some(uploaded), Some(modified)) => direction.newer(uploaded, modified),
+        _ => false,
+    };
+    let etag = object.e_tag().unwrap_or_default().trim_matches('"');
+    let matches = |same: bool| match same {
+        true => Compared::Matches,
+        false => Compared::Differs,
+    };
+    match compare {
+        Compare::Size => Ok(Compared::Matches),
+        Compare::Checksum if etag_is_md5(etag, None, client.has_customer_key()) => {
+            Ok(matches(ContentMd5::of_file(path).await?.matches_etag(etag)))
+        }
+        Compare::Mtime | Compare::Checksum if newer => Ok(Compared::Matches),
+        Compare::Mtime | Compare::Checksum => {
+            Ok(modified.map_or(Compared::Differs, Compared::Recorded))
+        }
+    }
+}
+
+/// Settles the comparisons that are down to a recorded modification time by HEADing those
+/// objects, [MTIME_LOOKUPS] at a time, keeping the files in the order given
+async fn settle<S: ObjectStore>(
+    client: &S,
+    checked: Vec<(FoundFile, std::io::Result<Compared>)>,
+    direction: Direction,
+) -> Vec<(FoundFile, std::io::Result<bool>)> {
+    let lookups: Vec<FoundFile> = checked
+        .iter()
+        .filter(|(_, compared)| matches!(compared, Ok(Compared::Recorded(_))))
+        .map(|(file, _)| file.clone())
+        .collect();
+    let mut heads = client.head_many(&lookups, MTIME_LOOKUPS).await.into_iter();
+    checked
+        .into_iter()
+        .map(|(file, compared)| {
+            let same = compared.map(|compared| match compared {
+                Compared::Matches => true,
+                Compared::Differs => false,
+                Compared::Recorded(modified) => heads
+                    .next()
+                    .is_some_and(|(_, info)| recorded_newer(&file.key, info, modified, direction)),
+            });
+            (file, same)
+        })
+        .collect()
+}
+
+/// Compares a file's modification time with the one the object's `mtime` metadata recorded for
+/// the file it was uploaded from, to the second, false if it didn't record one
+fn recorded_newer(
+    key: &str,
+    info: Result<S3FileInfo, S3Error>,
+    modified: SystemTime,
+    direction: Direction,
+) -> bool {
+    let recorded = match info {
+        Ok(info) => info
+            .metadata
+            .get(MTIME_METADATA)
+            .and_then(|value| parse_mtime(value)),
+        Err(error) => {
+            debug!("Couldn't look up the mtime of {}: {}", key, error);
+            None
+        }
+    };
+    // filesystems keep times to different precisions, so only whole seconds count
+    let whole_seconds = |time: SystemTime| match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => UNIX_EPOCH + Duration::from_secs(since.as_secs()),
+        Err(_) => time,
+    };
+    recorded
+        .is_some_and(|recorded| direction.newer(whole_seconds(recorded), whole_seconds(modified)))
+}
@@ -0,0 +1,100 @@
+//! Object tags, which lifecycle rules and IAM policies can match on
+//!
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::collections::BTreeMap;
+
+/// The most tags S3 allows on one object
+pub const MAX_TAGS: usize = 10;
+/// The most tags S3 allows on a bucket
+pub const MAX_BUCKET_TAGS: usize = 50;
+const MAX_KEY_LENGTH: usize = 128;
+const MAX_VALUE_LENGTH: usize = 256;
+
+/// Everything but the unreserved characters gets escaped, so spaces become %20 rather than +
+const TAGGING_ESCAPES: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Checks the tags against S3's limits on how many there can be and how long they are
+///
+/// ```
+/// use rust_test_s3_upload::tags::validate_tags;
+/// use std::collections::BTreeMap;
+///
+/// let mut tags: BTreeMap<String, String> =
+///     (0..10).map(|n| (format!("tag{}", n), String::from("x"))).collect();
+/// assert!(validate_tags(&tags).is_ok());
+/// tags.insert(String::from("retention"), String::from("90d"));
+/// assert!(validate_tags(&tags).is_err());
+/// ```
+pub fn validate_tags(tags: &BTreeMap<String, String>) -> Result<(), String> {
+    check_tags(tags, MAX_TAGS, "object")
+}
+
+/// Checks tags for a bucket, which can have more of them than an object
+///
+/// ```
+/// use rust_test_s3_upload::tags::validate_bucket_tags;
+/// use std::collections::BTreeMap;
+///
+/// let mut tags: BTreeMap<String, String> =
+///     (0..50).map(|n| (format!("tag{}", n), String::from("x"))).collect();
+/// assert!(validate_bucket_tags(&tags).is_ok());
+/// tags.insert(String::from("cost-centre"), String::from("backups"));
+/// assert!(validate_bucket_tags(&tags).is_err());
+/// ```
+pub fn validate_bucket_tags(tags: &BTreeMap<String, String>) -> Result<(), String> {
+    check_tags(tags, MAX_BUCKET_TAGS, "bucket")
+}
+
+fn check_tags(tags: &BTreeMap<String, String>, max: usize, on: &str) -> Result<(), String> {
+    if tags.len() > max {
+        return Err(format!(
+            "{} tags given but S3 allows {} per {}",
+            tags.len(),
+            max,
+            on
+        ));
+    }
+    for (key, value) in tags.iter() {
+        if key.is_empty() || key.chars().count() > MAX_KEY_LENGTH {
+            return Err(format!(
+                "tag key {:?} should be 1 to {} characters long",
+                key, MAX_KEY_LENGTH
+            ));
+        }
+        if value.chars().count() > MAX_VALUE_LENGTH {
+            return Err(format!(
+                "the value for tag {} is over {} characters long",
+                key, MAX_VALUE_LENGTH
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Encodes the tags as URL query parameters, for the `x-amz-tagging` header
+///
+/// ```
+/// use rust_test_s3_upload::tags::tagging_header;
+/// use std::collections::BTreeMap;
+///
+/// let mut tags = BTreeMap::new();
+/// tags.insert(String::from("retention"), String::from("90d"));
+/// tags.insert(String::from("owner"), String::from("db team & ops"));
+/// assert_eq!(tagging_header(&tags), "owner=db%20team%20%26%20ops&retention=90d");
+/// ```
+pub fn tagging_header(tags: &BTreeMap<String, String>) -> String {
+    tags.iter()
+        .map(|(key, value)| {
+            format!(
+                "{}={}",
+                utf8_percent_encode(key, TAGGING_ESCAPES),
+                utf8_percent_encode(value, TAGGING_ESCAPES)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
@@ -0,0 +1,198 @@
+//! Key templates like `mysql/{hostname}/{yyyy}-{mm}-{dd}/{filename}`
+//!
+use crate::config::ConfigError;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The tokens a template can use, for the error message
+const TOKENS: &str = "{filename}, {basename}, {ext}, {hostname}, {yyyy}, {mm}, {dd}, {epoch}";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token {
+    Filename,
+    Basename,
+    Ext,
+    Hostname,
+    Year,
+    Month,
+    Day,
+    Epoch,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Token(Token),
+}
+
+/// A parsed key template, checked up front so a typo fails before anything is uploaded
+///
+/// `{filename}` is the key the file would get without a template, its name or its path under the
+/// directory for recursive uploads. `{basename}` and `{ext}` split the last part of that at the
+/// final dot, `{ext}` is empty if there isn't one. Dates are in UTC and `{epoch}` is in seconds.
+/// `{{` and `}}` are literal braces. Any prefix is joined in front of the rendered key.
+///
+/// ```
+/// use rust_test_s3_upload::template::KeyTemplate;
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// let when = UNIX_EPOCH + Duration::from_secs(1714564800);
+/// let template = KeyTemplate::parse("mysql/{yyyy}-{mm}-{dd}/{basename}.{epoch}.{ext}").unwrap();
+/// assert_eq!(
+///     template.render("db.sql", when),
+///     "mysql/2024-05-01/db.1714564800.sql"
+/// );
+/// // recursive uploads keep their directories in {filename}
+/// let template = KeyTemplate::parse("{yyyy}/{filename}").unwrap();
+/// assert_eq!(template.render("db/2024/dump.sql.gz", when), "2024/db/2024/dump.sql.gz");
+/// let template = KeyTemplate::parse("{{{basename}}}-{ext}").unwrap();
+/// assert_eq!(template.render("archive/Makefile", when), "{Makefile}-");
+///
+/// assert!(KeyTemplate::parse("{date}/{filename}").is_err());
+/// assert!(KeyTemplate::parse("{filename").is_err());
+/// assert!(KeyTemplate::parse("/{filename}").is_err());
+/// ```
+#[derive(Clone, Debug)]
+pub struct KeyTemplate {
+    parts: Vec<Part>,
+    /// Looked up when parsing, only if the template uses it
+    hostname: Option<String>,
+}
+
+impl KeyTemplate {
+    pub fn parse(template: &str) -> Result<Self, ConfigError> {
+        let invalid = |message: String| ConfigError::InvalidTemplate(template.to_string(), message);
+
+        if template.starts_with('/') {
+            return Err(invalid(String::from("keys can't start with /")));
+        }
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(invalid(format!("{{{} is missing its }}", name))),
+                        }
+                    }
+                    let token = match name.as_str() {
+                        "filename" => Token::Filename,
+                        "basename" => Token::Basename,
+                        "ext" => Token::Ext,
+                        "hostname" => Token::Hostname,
+                        "yyyy" => Token::Year,
+                        "mm" => Token::Month,
+                        "dd" => Token::Day,
+                        "epoch" => Token::Epoch,
+                        _ => {
+                            return Err(invalid(format!(
+                                "unknown token {{{}}}, use {}",
+                                name, TOKENS
+                            )))
+                        }
+                    };
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Token(token));
+                }
+                '}' => {
+                    return Err(invalid(String::from(
+                        "} without a {, use }} for a literal one",
+                    )))
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        let hostname = match parts.contains(&Part::Token(Token::Hostname)) {
+            true => Some(
+                hostname().ok_or_else(|| invalid(String::from("couldn't find the hostname")))?,
+            ),
+            false => None,
+        };
+        Ok(Self { parts, hostname })
+    }
+
+    /// Builds the key for the file that would otherwise be uploaded to `filename`
+    ///
+    /// Pass the same `now` for every file in a run, so they all land under the same date.
+    pub fn render(&self, filename: &str, now: SystemTime) -> String {
+        let name = filename.rsplit('/').next().unwrap_or(filename);
+        let (basename, ext) = match name.rsplit_once('.') {
+            // a leading dot is a hidden file, not an extension
+            Some((basename, ext)) if !basename.is_empty() => (basename, ext),
+            _ => (name, ""),
+        };
+        let epoch = now
+            .duration_since(UNIX_EPOCH)
+            .map(|value| value.as_secs())
+            .unwrap_or(0);
+        let (year, month, day) = civil_date(epoch / 86400);
+
+        let mut key = String::new();
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(value) => key.push_str(value),
+                Part::Token(Token::Filename) => key.push_str(filename),
+                Part::Token(Token::Basename) => key.push_str(basename),
+                Part::Token(Token::Ext) => key.push_str(ext),
+                Part::Token(Token::Hostname) => {
+                    key.push_str(self.hostname.as_deref().unwrap_or_default())
+                }
+                Part::Token(Token::Year) => key.push_str(&format!("{:04}", year)),
+                Part::Token(Token::Month) => key.push_str(&format!("{:02}", month)),
+                Part::Token(Token::Day) => key.push_str(&format!("{:02}", day)),
+                Part::Token(Token::Epoch) => key.push_str(&epoch.to_string()),
+            }
+        }
+        key
+    }
+}
+
+/// Finds the machine's hostname without needing libc, from the kernel or the environment
+fn hostname() -> Option<String> {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .chain(
+            ["HOSTNAME", "COMPUTERNAME"]
+                .iter()
+                .filter_map(|var| std::env::var(var).ok()),
+        )
+        .map(|value| value.trim().to_string())
+        .find(|value| !value.is_empty())
+}
+
+/// Turns days since 1970-01-01 into a year, month and day, from Howard Hinnant's date algorithms
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = match month_index < 10 {
+        true => month_index + 3,
+        false => month_index - 9,
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
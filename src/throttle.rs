@@ -0,0 +1,219 @@
+//! Capping how fast uploads and downloads go, across everything in flight at once
+//!
+use crate::progress::Counter;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_smithy_types::body::SdkBody;
+use bytes::Bytes;
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::time::Sleep;
+
+/// How much of an upload body is let through at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Parses a rate in bytes per second, with an optional K, M or G suffix in powers of 1024
+///
+/// ```
+/// use rust_test_s3_upload::throttle::parse_rate;
+///
+/// assert_eq!(parse_rate("512"), Ok(512));
+/// assert_eq!(parse_rate("10M"), Ok(10 * 1024 * 1024));
+/// assert_eq!(parse_rate("1g"), Ok(1024 * 1024 * 1024));
+/// assert!(parse_rate("fast").is_err());
+/// ```
+pub fn parse_rate(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, multiplier) = match value.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&value[..value.len() - 1], 1024),
+        Some('M') => (&value[..value.len() - 1], 1024 * 1024),
+        Some('G') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| {
+            format!(
+                "{:?} should be bytes per second, with an optional K, M or G suffix like 10M",
+                value
+            )
+        })
+}
+
+/// A token bucket shared by every transfer, so the total rate stays under the cap
+///
+/// It holds up to a second's worth of bytes. Taking more than is there puts it in debt, and the
+/// caller waits until the debt's paid off, so concurrent transfers share the rate between them.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second,
+            bucket: Mutex::new(Bucket {
+                tokens: bytes_per_second as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    /// Takes `bytes` from the bucket, returning how long to wait before sending them
+    ///
+    /// ```
+    /// use rust_test_s3_upload::throttle::RateLimiter;
+    /// use std::time::Duration;
+    ///
+    /// let limiter = RateLimiter::new(1000);
+    /// assert_eq!(limiter.reserve(1000), Duration::ZERO);
+    /// assert!(limiter.reserve(500) > Duration::from_millis(400));
+    /// ```
+    pub fn reserve(&self, bytes: u64) -> Duration {
+        let rate = self.bytes_per_second as f64;
+        let mut bucket = match self.bucket.lock() {
+            Ok(value) => value,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let now = Instant::now();
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refilled).min(rate) - bytes as f64;
+        bucket.updated = now;
+        match bucket.tokens < 0.0 {
+            true => Duration::from_secs_f64(-bucket.tokens / rate),
+            false => Duration::ZERO,
+        }
+    }
+
+    /// Waits until `bytes` can be sent or written without going over the rate
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// An upload body that's let out a chunk at a time, as fast as the limiter allows, counting each
+/// chunk towards the progress bars as it goes
+struct MeteredBody {
+    data: Bytes,
+    limiter: Option<Arc<RateLimiter>>,
+    counter: Option<Counter>,
+    /// How much of this attempt has gone, shared with the retry closure
+    sent: Arc<AtomicU64>,
+    /// A chunk that's been paid for, waiting on `sleep` before it goes
+    pending: Option<Bytes>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl http_body::Body for MeteredBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let this = &mut *self;
+        loop {
+            if let Some(sleep) = this.sleep.as_mut() {
+                if sleep.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.sleep = None;
+                let chunk = this.pending.take();
+                if let Some(chunk) = &chunk {
+                    this.count(chunk.len() as u64);
+                }
+                return Poll::Ready(chunk.map(Ok));
+            }
+            if this.data.is_empty() {
+                return Poll::Ready(None);
+            }
+            let chunk = this.data.split_to(CHUNK_SIZE.min(this.data.len()));
+            let wait = this
+                .limiter
+                .as_ref()
+                .map(|limiter| limiter.reserve(chunk.len() as u64))
+                .unwrap_or_default();
+            if wait.is_zero() {
+                this.count(chunk.len() as u64);
+                return Poll::Ready(Some(Ok(chunk)));
+            }
+            // polled straight away on the next time round, so the sleep registers the waker
+            this.pending = Some(chunk);
+            this.sleep = Some(Box::pin(tokio::time::sleep(wait)));
+        }
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_empty() && self.pending.is_none()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        let pending = self.pending.as_ref().map(Bytes::len).unwrap_or_default();
+        http_body::SizeHint::with_exact((self.data.len() + pending) as u64)
+    }
+}
+
+impl MeteredBody {
+    fn count(&self, bytes: u64) {
+        self.sent.fetch_add(bytes, Ordering::Relaxed);
+        if let Some(counter) = &self.counter {
+            counter.inc(bytes);
+        }
+    }
+}
+
+/// Wraps an in-memory upload body so it's sent no faster than `limiter` allows, and counted
+/// towards the progress bars by `counter`
+///
+/// The body can be rebuilt from the start, so the SDK can still retry the request. Whatever the
+/// failed attempt had counted is taken back off the bars when it does.
+pub(crate) fn metered_body(
+    body: Vec<u8>,
+    limiter: Option<Arc<RateLimiter>>,
+    counter: Option<Counter>,
+) -> ByteStream {
+    let data = Bytes::from(body);
+    let sent = Arc::new(AtomicU64::new(0));
+    ByteStream::new(SdkBody::retryable(move || {
+        if let Some(counter) = &counter {
+            counter.dec(sent.swap(0, Ordering::Relaxed));
+        }
+        SdkBody::from_body_0_4(MeteredBody {
+            data: data.clone(),
+            limiter: limiter.clone(),
+            counter: counter.clone(),
+            sent: sent.clone(),
+            pending: None,
+            sleep: None,
+        })
+    }))
+}
@@ -0,0 +1,168 @@
+//! Checking local files against their objects, to catch backups that have gone missing or changed
+//!
+use crate::checksum::{etag_is_md5, parse_checksum_algorithm, Checksum, ContentMd5};
+use crate::client::S3FileInfo;
+use crate::error::S3Error;
+use crate::store::ObjectStore;
+use crate::walk::FoundFile;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// How a file and its object compared
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// The same size, and the same content if that was checked
+    Matches,
+    /// There's no object for the file
+    MissingRemote,
+    Differs,
+    /// An object under the prefix with no local file
+    OnlyRemote,
+    /// The file couldn't be read or the HEAD failed
+    Error,
+}
+
+/// A file or object that was checked
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Check {
+    /// `None` for objects that are only in S3
+    pub path: Option<PathBuf>,
+    pub key: String,
+    pub status: Status,
+    pub local_size: Option<u64>,
+    pub remote_size: Option<u64>,
+    /// What differs, or what went wrong
+    pub detail: Option<String>,
+}
+
+/// HEADs the object for each file, up to `jobs` at a time, and compares it with the file, then
+/// with a `prefix` lists it for the objects that don't have a file
+///
+/// Files are compared by size, and with `checksum` they're read to compare their MD5 with the
+/// etag where that's the MD5, or else their additional checksum with the one S3 stored for the
+/// whole object. Checks are returned in the order of `files`, followed by the objects only in S3.
+///
+/// ```no_run
+/// # async fn example(client: rust_test_s3_upload::S3Client) -> Result<(), rust_test_s3_upload::S3Error> {
+/// use rust_test_s3_upload::filter::PathFilter;
+/// use rust_test_s3_upload::verify::{verify, Status};
+/// use rust_test_s3_upload::walk::{find_files, Links};
+///
+/// let (files, _) = find_files("backups".as_ref(), "hosts/web1/", &PathFilter::default(), Links::Follow)?;
+/// for check in verify(&client, &files, Some("hosts/web1/"), true, 8).await? {
+///     if check.status != Status::Matches {
+///         println!("{:?} {}", check.status, check.key);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn verify<S: ObjectStore>(
+    client: &S,
+    files: &[FoundFile],
+    prefix: Option<&str>,
+    checksum: bool,
+    jobs: usize,
+) -> Result<Vec<Check>, S3Error> {
+    let customer_key = client.has_customer_key();
+    let mut checks = Vec::with_capacity(files.len());
+    for (file, result) in client.head_many(files, jobs).await {
+        let local_size = std::fs::metadata(&file.path)
+            .ok()
+            .map(|metadata| metadata.len());
+        let mut check = Check {
+            path: Some(file.path.clone()),
+            key: file.key.clone(),
+            status: Status::Matches,
+            local_size,
+            remote_size: None,
+            detail: None,
+        };
+        match result {
+            Ok(info) => {
+                check.remote_size = Some(info.size);
+                match compare(&file.path, &info, checksum, customer_key).await {
+                    Ok(None) => {}
+                    Ok(Some(difference)) => {
+                        check.status = Status::Differs;
+                        check.detail = Some(difference);
+                    }
+                    Err(error) => {
+                        check.status = Status::Error;
+                        check.detail = Some(error.to_string());
+                    }
+                }
+            }
+            Err(S3Error::NotFound { .. }) => check.status = Status::MissingRemote,
+            Err(error) => {
+                check.status = Status::Error;
+                check.detail = Some(error.to_string());
+            }
+        }
+        checks.push(check);
+    }
+
+    if let Some(prefix) = prefix {
+        let local: HashSet<&str> = files.iter().map(|file| file.key.as_str()).collect();
+        for object in client.list_all(prefix).await? {
+            let Some(key) = object.key() else {
+                continue;
+            };
+            if key.ends_with('/') || local.contains(key) {
+                continue;
+            }
+            checks.push(Check {
+                path: None,
+                key: key.to_string(),
+                status: Status::OnlyRemote,
+                local_size: None,
+                remote_size: object.size().and_then(|value| u64::try_from(value).ok()),
+                detail: None,
+            });
+        }
+    }
+    Ok(checks)
+}
+
+/// What's different between a file and its object, `None` if nothing that was compared is
+async fn compare(
+    path: &Path,
+    info: &S3FileInfo,
+    checksum: bool,
+    customer_key: bool,
+) -> std::io::Result<Option<String>> {
+    let size = tokio::fs::metadata(path).await?.len();
+    if size != info.size {
+        return Ok(Some(format!(
+            "{} bytes locally and {} in S3",
+            size, info.size
+        )));
+    }
+    if !checksum {
+        return Ok(None);
+    }
+    if etag_is_md5(
+        &info.etag,
+        info.server_side_encryption.as_deref(),
+        customer_key,
+    ) {
+        return Ok((!ContentMd5::of_file(path).await?.matches_etag(&info.etag))
+            .then(|| String::from("the MD5 doesn't match the etag")));
+    }
+    let algorithm = info
+        .checksum_algorithm
+        .as_deref()
+        .and_then(|value| parse_checksum_algorithm(value).ok());
+    match (algorithm, info.checksum.as_deref()) {
+        // a composite checksum of the parts can't be worked out without knowing where they split
+        (Some(algorithm), Some(stored)) if !stored.contains('-') => {
+            let matches = Checksum::of_file(&algorithm, path)
+                .await?
+                .is_some_and(|local| local.matches(Some(stored)));
+            Ok((!matches).then(|| format!("the {} checksum doesn't match", algorithm.as_str())))
+        }
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,641 @@
+//! Finding the files under a directory and working out their object keys
+//!
+use crate::client::PARTIAL_SUFFIX;
+use crate::error::S3Error;
+use crate::filter::PathFilter;
+use crate::resume::STATE_SUFFIX;
+use percent_encoding::percent_decode_str;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+/// Files and directories that couldn't be read, and why
+pub type Unreadable = Vec<(PathBuf, std::io::Error)>;
+
+/// A file found under the root, and the key it should be uploaded to
+#[derive(Clone, Debug)]
+pub struct FoundFile {
+    pub path: PathBuf,
+    pub key: String,
+    /// Whether the path is a symlink or inside a linked directory
+    pub symlink: bool,
+}
+
+/// Builds the key for `path` from its location relative to `root` with [relative_key], so it's
+/// the same whichever platform the upload runs on
+///
+/// ```
+/// use rust_test_s3_upload::walk::key_for;
+/// use std::path::Path;
+///
+/// let key = key_for(Path::new("backups"), Path::new("backups/db/dump.sql"), "nightly");
+/// assert_eq!(key.as_deref(), Ok("nightly/db/dump.sql"));
+/// ```
+pub fn key_for(root: &Path, path: &Path, prefix: &str) -> Result<String, String> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    Ok(join_key(
+        prefix,
+        &relative_key(&relative.to_string_lossy())?,
+    ))
+}
+
+/// Maps a relative path from either Windows or Unix to the `/` separated names of a key
+///
+/// `\` is a separator wherever the path came from, a drive letter or UNC prefix like
+/// `\\server\share\` is stripped, and empty and `.` names are dropped. A `..` name is refused,
+/// as the key would mean something different depending on where it's restored.
+///
+/// ```
+/// use rust_test_s3_upload::walk::relative_key;
+///
+/// assert_eq!(relative_key("db/monday.sql").as_deref(), Ok("db/monday.sql"));
+/// assert_eq!(relative_key("backups\\db\\monday.sql").as_deref(), Ok("backups/db/monday.sql"));
+/// assert_eq!(relative_key("./db//monday.sql").as_deref(), Ok("db/monday.sql"));
+/// assert_eq!(relative_key("/var/backups/db.sql").as_deref(), Ok("var/backups/db.sql"));
+/// // drive letters and UNC prefixes
+/// assert_eq!(relative_key("C:\\backups\\db.sql").as_deref(), Ok("backups/db.sql"));
+/// assert_eq!(relative_key("d:/backups/db.sql").as_deref(), Ok("backups/db.sql"));
+/// assert_eq!(relative_key("\\\\nas\\share\\db\\monday.sql").as_deref(), Ok("db/monday.sql"));
+/// assert_eq!(relative_key("\\\\?\\C:\\backups\\db.sql").as_deref(), Ok("backups/db.sql"));
+/// assert_eq!(relative_key("\\\\?\\UNC\\nas\\share\\db.sql").as_deref(), Ok("db.sql"));
+/// // a colon further in is part of the name
+/// assert_eq!(relative_key("logs/12:00.log").as_deref(), Ok("logs/12:00.log"));
+///
+/// assert!(relative_key("db/../../etc/passwd").is_err());
+/// assert!(relative_key("db\\..\\secrets.txt").is_err());
+/// ```
+pub fn relative_key(path: &str) -> Result<String, String> {
+    let path = path.replace('\\', "/");
+    let mut rest = path.as_str();
+    if let Some(unc) = rest.strip_prefix("//") {
+        rest = match unc.strip_prefix("?/").or_else(|| unc.strip_prefix("./")) {
+            // \\?\UNC\server\share\ or \\?\C:\, the drive's handled below
+            Some(verbatim) => match verbatim.strip_prefix("UNC/") {
+                Some(share) => after_names(share, 2),
+                None => verbatim,
+            },
+            None => after_names(unc, 2),
+        };
+    }
+    if let [drive, b':', after @ ..] = rest.as_bytes() {
+        if drive.is_ascii_alphabetic() && matches!(after.first(), None | Some(b'/')) {
+            rest = &rest[2..];
+        }
+    }
+    let mut names = Vec::new();
+    for name in rest.split('/') {
+        match name {
+            "" | "." => {}
+            ".." => {
+                return Err(String::from(
+                    "it has a `..` in it, which would climb out of the directory",
+                ))
+            }
+            name => names.push(name),
+        }
+    }
+    Ok(names.join("/"))
+}
+
+/// What's left of `path` after its first `count` names
+fn after_names(path: &str, count: usize) -> &str {
+    path.splitn(count + 1, '/').nth(count).unwrap_or_default()
+}
+
+/// Joins `prefix` onto `key` with exactly one `/`, an empty prefix leaves the key as it is
+///
+/// Runs of slashes in the prefix are collapsed and its leading slash dropped, so careless settings
+/// like `"/hosts//web1/"` still give tidy keys. Joining onto an empty key gives the prefix with a
+/// trailing `/`, for listing what's under it.
+///
+/// ```
+/// use rust_test_s3_upload::walk::join_key;
+///
+/// assert_eq!(join_key("hosts/web1", "db.sql"), "hosts/web1/db.sql");
+/// assert_eq!(join_key("hosts/web1/", "/db.sql"), "hosts/web1/db.sql");
+/// assert_eq!(join_key("/hosts//web1//", "//db.sql"), "hosts/web1/db.sql");
+/// assert_eq!(join_key("hosts", "db/2024/dump.sql"), "hosts/db/2024/dump.sql");
+/// assert_eq!(join_key("hosts/web1", ""), "hosts/web1/");
+/// // no prefix changes nothing
+/// assert_eq!(join_key("", "db.sql"), "db.sql");
+/// assert_eq!(join_key("", "a//b.sql"), "a//b.sql");
+/// assert_eq!(join_key("/", "db.sql"), "db.sql");
+/// assert_eq!(join_key("", ""), "");
+/// ```
+pub fn join_key(prefix: &str, key: &str) -> String {
+    let prefix: Vec<&str> = prefix.split('/').filter(|part| !part.is_empty()).collect();
+    match prefix.is_empty() {
+        true => key.to_string(),
+        false => format!("{}/{}", prefix.join("/"), key.trim_start_matches('/')),
+    }
+}
+
+/// A key under `prefix` and `folder` ending in a random suffix, for an object that's made and
+/// deleted again and mustn't collide with anything, even another run at the same time
+///
+/// ```
+/// use rust_test_s3_upload::walk::unique_key;
+///
+/// let key = unique_key("hosts/web1", "selftest/", "txt");
+/// assert!(key.starts_with("hosts/web1/selftest/") && key.ends_with(".txt"));
+/// assert_eq!(key.len(), "hosts/web1/selftest/0123456789abcdef.txt".len());
+/// assert_ne!(key, unique_key("hosts/web1", "selftest/", "txt"));
+/// ```
+pub fn unique_key(prefix: &str, folder: &str, extension: &str) -> String {
+    let mut bytes = [0u8; 8];
+    // the time will do if there's no randomness to be had, it only has to differ between runs
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_nanos() as u64)
+            .unwrap_or_default();
+        bytes = nanos.to_be_bytes();
+    }
+    let suffix: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    join_key(
+        prefix,
+        &join_key(folder, &format!("{}.{}", suffix, extension)),
+    )
+}
+
+/// The key a single file is uploaded to when none is given, its file name without the directories
+///
+/// ```
+/// use rust_test_s3_upload::walk::default_key;
+/// use std::path::Path;
+///
+/// assert_eq!(default_key(Path::new("/var/backups/db.dump")), "db.dump");
+/// assert_eq!(default_key(Path::new("C:\\backups\\db.dump")), "db.dump");
+/// ```
+pub fn default_key(path: &Path) -> String {
+    let name = match path.file_name() {
+        Some(value) => value.to_string_lossy(),
+        None => path.to_string_lossy(),
+    };
+    // a Windows path is one name to Unix
+    match name.rsplit(['/', '\\']).next() {
+        Some(last) if !last.is_empty() => last.to_string(),
+        _ => name.into_owned(),
+    }
+}
+
+/// Strips leading slashes, which most S3 browsers show as an empty folder, `None` if nothing is left
+///
+/// ```
+/// use rust_test_s3_upload::walk::normalize_key;
+///
+/// assert_eq!(normalize_key("/backups/db.dump"), Some("backups/db.dump"));
+/// assert_eq!(normalize_key("db.dump"), Some("db.dump"));
+/// assert_eq!(normalize_key("//"), None);
+/// ```
+pub fn normalize_key(key: &str) -> Option<&str> {
+    let key = key.trim_start_matches('/');
+    match key.is_empty() {
+        true => None,
+        false => Some(key),
+    }
+}
+
+/// The longest key S3 takes, in bytes of UTF-8
+pub const MAX_KEY_LENGTH: usize = 1024;
+
+/// Checks `key` can be stored and listed back as it is
+///
+/// Control characters like a newline are refused, as S3 accepts them but they can't be carried in
+/// an XML listing without URL encoding, and plenty of tools get that wrong.
+///
+/// ```
+/// use rust_test_s3_upload::walk::validate_key;
+///
+/// assert!(validate_key("reports/weekly report (final).pdf").is_ok());
+/// assert!(validate_key("photos/📦 100% + more.jpg").is_ok());
+/// assert!(validate_key("line\nbreak.txt").is_err());
+/// assert!(validate_key(&"a".repeat(1025)).is_err());
+/// ```
+pub fn validate_key(key: &str) -> Result<(), String> {
+    if let Some(character) = key.chars().find(|character| character.is_control()) {
+        return Err(format!(
+            "it contains the control character {:?}, rename the file or give another key",
+            character
+        ));
+    }
+    if key.len() > MAX_KEY_LENGTH {
+        return Err(format!(
+            "it's {} bytes long, S3 allows {}",
+            key.len(),
+            MAX_KEY_LENGTH
+        ));
+    }
+    Ok(())
+}
+
+/// The names in `key` that end in a space or `.`, which S3 keeps but are easily lost, eg by
+/// Windows when the object's downloaded or by a person copying the key
+///
+/// ```
+/// use rust_test_s3_upload::walk::trailing_space_or_dot;
+///
+/// assert_eq!(trailing_space_or_dot("backups /db.sql."), ["backups ", "db.sql."]);
+/// assert!(trailing_space_or_dot("backups/db.sql").is_empty());
+/// // a folder marker's empty name doesn't count
+/// assert!(trailing_space_or_dot("backups/").is_empty());
+/// ```
+pub fn trailing_space_or_dot(key: &str) -> Vec<&str> {
+    key.split('/')
+        .filter(|name| name.ends_with(' ') || name.ends_with('.'))
+        .filter(|name| !matches!(*name, "." | ".."))
+        .collect()
+}
+
+/// A key from a listing made with `encoding-type=url`, where S3 escapes keys like a form, with `+`
+/// for a space and `%` with hex bytes for anything else that isn't safe in XML or a URL
+///
+/// ```
+/// use rust_test_s3_upload::walk::decode_listed_key;
+///
+/// assert_eq!(decode_listed_key("weekly+report+%28final%29.pdf"), "weekly report (final).pdf");
+/// assert_eq!(decode_listed_key("1%2B1%3D2+100%25"), "1+1=2 100%");
+/// assert_eq!(decode_listed_key("%F0%9F%93%A6%0Anew+line"), "📦\nnew line");
+/// assert_eq!(decode_listed_key("plain/key.txt"), "plain/key.txt");
+/// ```
+pub fn decode_listed_key(key: &str) -> String {
+    percent_decode_str(&key.replace('+', " "))
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// Characters Windows doesn't allow in file names, along with `%` itself so escaped names can't be
+/// confused with real ones
+#[cfg(windows)]
+const UNSAFE_CHARACTERS: &str = "%\\:*?\"<>|";
+
+/// The relative path an object is restored to, the reverse of [key_for]
+///
+/// On Windows the characters it doesn't allow in file names, control characters, and a `.` or
+/// space at the end of a name are escaped as `%` and their hex UTF-8 bytes. Elsewhere names are
+/// kept as they are, so a restore gives back the names that were uploaded. Empty names from runs
+/// of `/` are dropped. Keys ending in `/` are folder markers rather than files, so they give
+/// `None`, as does an empty key.
+///
+/// A key with a `..` name, split at `/` or `\`, is refused rather than restored anywhere, as it
+/// was made to climb out of the directory. None of this crate's uploads make one.
+///
+/// ```
+/// use rust_test_s3_upload::walk::local_path;
+/// use std::path::PathBuf;
+///
+/// assert_eq!(local_path("db/2024/dump.sql"), Ok(Some(PathBuf::from("db/2024/dump.sql"))));
+/// assert_eq!(local_path("/db//dump.sql"), Ok(Some(PathBuf::from("db/dump.sql"))));
+/// #[cfg(windows)]
+/// {
+///     assert_eq!(local_path("logs/12:00 \"am\".log"), Ok(Some(PathBuf::from("logs/12%3A00 %22am%22.log"))));
+///     assert_eq!(local_path("100%.txt"), Ok(Some(PathBuf::from("100%25.txt"))));
+///     assert_eq!(local_path("old\\backup.sql"), Ok(Some(PathBuf::from("old%5Cbackup.sql"))));
+/// }
+/// #[cfg(not(windows))]
+/// {
+///     assert_eq!(local_path("logs/12:00 \"am\".log"), Ok(Some(PathBuf::from("logs/12:00 \"am\".log"))));
+///     assert_eq!(local_path("100%.txt"), Ok(Some(PathBuf::from("100%.txt"))));
+/// }
+/// assert_eq!(local_path("db/"), Ok(None));
+///
+/// assert!(local_path("a/../../etc/passwd").is_err());
+/// assert!(local_path("..\\..\\etc\\passwd").is_err());
+/// ```
+pub fn local_path(key: &str) -> Result<Option<PathBuf>, String> {
+    if key.split(['/', '\\']).any(|name| name == "..") {
+        return Err(String::from(
+            "it has a `..` in it, which would climb out of the directory",
+        ));
+    }
+    if key.ends_with('/') {
+        return Ok(None);
+    }
+    let path: PathBuf = key
+        .split('/')
+        .filter(|name| !name.is_empty())
+        .map(escape_name)
+        .collect();
+    Ok((!path.as_os_str().is_empty()).then_some(path))
+}
+
+/// Whether `c` can't be used at `index` of a name of `length` characters on this platform
+#[cfg(windows)]
+fn unsafe_character(c: char, index: usize, length: usize) -> bool {
+    let trailing = index + 1 == length && (c == '.' || c == ' ');
+    c.is_control() || UNSAFE_CHARACTERS.contains(c) || trailing
+}
+
+/// Whether `c` can't be used at `index` of a name of `length` characters on this platform
+#[cfg(not(windows))]
+fn unsafe_character(c: char, _index: usize, _length: usize) -> bool {
+    c == '\0'
+}
+
+fn escape_name(name: &str) -> String {
+    let length = name.chars().count();
+    let mut escaped = String::with_capacity(name.len());
+    for (index, c) in name.chars().enumerate() {
+        if unsafe_character(c, index, length) {
+            let mut bytes = [0u8; 4];
+            for byte in c.encode_utf8(&mut bytes).bytes() {
+                escaped.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// What a walk does with a symlink it finds
+///
+/// ```
+/// use rust_test_s3_upload::walk::Links;
+///
+/// assert_eq!("skip".parse::<Links>(), Ok(Links::Skip));
+/// assert!("ignore".parse::<Links>().is_err());
+/// assert_eq!(Links::default().to_string(), "follow");
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Links {
+    /// Upload what it points to, and walk a linked directory unless it's one the link is already
+    /// inside. A link to nothing is skipped with a warning.
+    #[default]
+    Follow,
+    /// Leave it out, saying so in the log
+    Skip,
+    /// Stop the walk at the first one
+    Error,
+}
+
+impl FromStr for Links {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "follow" => Ok(Self::Follow),
+            "skip" => Ok(Self::Skip),
+            "error" => Ok(Self::Error),
+            _ => Err(format!(
+                "{:?} isn't a way to handle symlinks, use follow, skip or error",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Links {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Follow => "follow",
+            Self::Skip => "skip",
+            Self::Error => "error",
+        })
+    }
+}
+
+/// What identifies a directory however it's reached, so a walk can tell it's gone round a loop
+#[cfg(unix)]
+type DirectoryId = (u64, u64);
+#[cfg(not(unix))]
+type DirectoryId = PathBuf;
+
+/// The device and inode of the directory `path` leads to
+#[cfg(unix)]
+fn directory_id(path: &Path) -> std::io::Result<DirectoryId> {
+    use std::os::unix::fs::MetadataExt;
+    let metadata = std::fs::metadata(path)?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
+/// Without inodes the canonical path, with every link resolved, will do
+#[cfg(not(unix))]
+fn directory_id(path: &Path) -> std::io::Result<DirectoryId> {
+    std::fs::canonicalize(path)
+}
+
+/// A directory waiting to be walked
+struct Pending {
+    path: PathBuf,
+    /// The directories it's inside, back to the root
+    ancestors: Vec<DirectoryId>,
+    /// Whether it was reached through a symlink
+    linked: bool,
+}
+
+/// Says what `path` is if it's one of the files interrupted transfers leave behind, going by
+/// what's next to it so a file of the user's that just has a similar name isn't mistaken for one
+///
+/// These are a download's `<name>.<16 hex digits>.partial` temporary file, a `<name>.partial`
+/// with a download's resume state next to it, and resume state or its temporary file next to
+/// the file it's for.
+fn own_file(path: &Path) -> Option<&'static str> {
+    let name = path.file_name()?.to_str()?;
+    let sibling = |name: &str| path.with_file_name(name).exists();
+    if let Some(stem) = name
+        .strip_suffix(&format!("{}.tmp", STATE_SUFFIX))
+        .or_else(|| name.strip_suffix(STATE_SUFFIX))
+    {
+        return sibling(stem).then_some("resume state for an interrupted transfer");
+    }
+    let stem = name.strip_suffix(PARTIAL_SUFFIX)?;
+    let temporary = stem.rsplit_once('.').is_some_and(|(file, random)| {
+        !file.is_empty()
+            && random.len() == 16
+            && random
+                .chars()
+                .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    });
+    let resumable = sibling(&format!("{}{}", name, STATE_SUFFIX));
+    (temporary || resumable).then_some("part of an interrupted download")
+}
+
+/// Walks `root` for files `filter` accepts, returning them sorted by key along with anything that
+/// couldn't be read
+///
+/// Symlinks are handled as `links` says. When they're followed, a linked directory that's one of
+/// those the link is in is skipped rather than walked round forever, and each file found through a
+/// link is marked as [FoundFile::symlink]. With [Links::Error] the first symlink that `filter`
+/// doesn't exclude is returned as [S3Error::Symlink].
+///
+/// Resume state files from interrupted uploads and partial files from interrupted downloads are
+/// skipped, a file that only has a name like theirs isn't.
+/// So is anything that isn't a regular file or directory, eg a FIFO, socket or device.
+///
+/// ```
+/// # #[cfg(unix)]
+/// # {
+/// use rust_test_s3_upload::filter::PathFilter;
+/// use rust_test_s3_upload::walk::{find_files, Links};
+/// use std::os::unix::fs::symlink;
+/// use std::os::unix::net::UnixListener;
+///
+/// let root = std::env::temp_dir().join(format!("walk-links-doctest-{}", std::process::id()));
+/// std::fs::create_dir_all(root.join("db")).unwrap();
+/// std::fs::write(root.join("db/dump.sql"), "select 1;").unwrap();
+/// let _socket = UnixListener::bind(root.join("db/mysql.sock")).unwrap();
+/// symlink("dump.sql", root.join("db/latest.sql")).unwrap();
+/// symlink("..", root.join("db/loop")).unwrap();
+/// symlink("gone.sql", root.join("broken.sql")).unwrap();
+///
+/// let (files, errors) = find_files(&root, "", &PathFilter::default(), Links::Follow).unwrap();
+/// let found: Vec<_> = files.iter().map(|file| (file.key.as_str(), file.symlink)).collect();
+/// assert_eq!(found, [("db/dump.sql", false), ("db/latest.sql", true)]);
+/// assert!(errors.is_empty());
+///
+/// let (files, _) = find_files(&root, "", &PathFilter::default(), Links::Skip).unwrap();
+/// assert_eq!(files.len(), 1);
+/// assert!(find_files(&root, "", &PathFilter::default(), Links::Error).is_err());
+/// std::fs::remove_dir_all(&root).unwrap();
+/// # }
+/// ```
+///
+/// ```
+/// use rust_test_s3_upload::filter::PathFilter;
+/// use rust_test_s3_upload::walk::{find_files, Links};
+///
+/// let root = std::env::temp_dir().join(format!("walk-partial-doctest-{}", std::process::id()));
+/// std::fs::create_dir_all(&root).unwrap();
+/// for name in [
+///     "db.sql",
+///     "db.sql.s3upload.json",
+///     "big.iso.partial",
+///     "big.iso.partial.s3upload.json",
+///     "small.iso.0123456789abcdef.partial",
+///     "report.partial",
+///     "notes.s3upload.json",
+/// ] {
+///     std::fs::write(root.join(name), name).unwrap();
+/// }
+///
+/// let (files, _) = find_files(&root, "", &PathFilter::default(), Links::Follow).unwrap();
+/// let keys: Vec<_> = files.iter().map(|file| file.key.as_str()).collect();
+/// assert_eq!(keys, ["db.sql", "notes.s3upload.json", "report.partial"]);
+/// std::fs::remove_dir_all(&root).unwrap();
+/// ```
+pub fn find_files(
+    root: &Path,
+    prefix: &str,
+    filter: &PathFilter,
+    links: Links,
+) -> Result<(Vec<FoundFile>, Unreadable), S3Error> {
+    let mut files = Vec::new();
+    let mut errors = Vec::new();
+    let mut directories = vec![Pending {
+        path: root.to_path_buf(),
+        ancestors: Vec::new(),
+        linked: false,
+    }];
+    while let Some(directory) = directories.pop() {
+        let mut ancestors = directory.ancestors;
+        match directory_id(&directory.path) {
+            Ok(id) if ancestors.contains(&id) => {
+                warn!(
+                    "Skipping {}, it links back to a directory it's in",
+                    directory.path.display()
+                );
+                continue;
+            }
+            Ok(id) => ancestors.push(id),
+            Err(error) => {
+                errors.push((directory.path, error));
+                continue;
+            }
+        }
+        let entries = match std::fs::read_dir(&directory.path) {
+            Ok(value) => value,
+            Err(error) => {
+                errors.push((directory.path, error));
+                continue;
+            }
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(value) => value,
+                Err(error) => {
+                    errors.push((directory.path.clone(), error));
+                    continue;
+                }
+            };
+            let path = entry.path();
+            let file_type = match entry.file_type() {
+                Ok(value) => value,
+                Err(error) => {
+                    errors.push((path, error));
+                    continue;
+                }
+            };
+            let relative = match key_for(root, &path, "") {
+                Ok(value) => value,
+                Err(message) => {
+                    errors.push((path, std::io::Error::new(ErrorKind::InvalidInput, message)));
+                    continue;
+                }
+            };
+            let symlink = file_type.is_symlink();
+            let is_dir = match (symlink, links) {
+                (false, _) if file_type.is_dir() => true,
+                (false, _) if file_type.is_file() => false,
+                // reading a FIFO would block forever, and a socket or device isn't something to
+                // back up
+                (false, _) => {
+                    info!("Skipping {}, it's not a regular file", path.display());
+                    continue;
+                }
+                (true, _) if !filter.matches(&relative) && links != Links::Follow => continue,
+                (true, Links::Skip) => {
+                    info!("Skipping {}, it's a symlink", path.display());
+                    continue;
+                }
+                (true, Links::Error) => return Err(S3Error::Symlink { path }),
+                (true, Links::Follow) => match std::fs::metadata(&path) {
+                    Ok(metadata) if metadata.is_dir() => true,
+                    Ok(metadata) if metadata.is_file() => false,
+                    Ok(_) => {
+                        info!(
+                            "Skipping {}, it links to something that's not a regular file",
+                            path.display()
+                        );
+                        continue;
+                    }
+                    Err(error) if error.kind() == ErrorKind::NotFound => {
+                        warn!("Skipping {}, it's a broken symlink", path.display());
+                        continue;
+                    }
+                    Err(error) => {
+                        errors.push((path, error));
+                        continue;
+                    }
+                },
+            };
+            if is_dir {
+                directories.push(Pending {
+                    path,
+                    ancestors: ancestors.clone(),
+                    linked: directory.linked || symlink,
+                });
+                continue;
+            }
+            if !filter.matches(&relative) {
+                continue;
+            }
+            // our own resume state and partial downloads shouldn't end up in the bucket
+            if let Some(kind) = own_file(&path) {
+                info!("Skipping {}, it's {}", path.display(), kind);
+                continue;
+            }
+            files.push(FoundFile {
+                key: join_key(prefix, &relative),
+                path,
+                symlink: directory.linked || symlink,
+            });
+        }
+    }
+    files.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok((files, errors))
+}
@@ -0,0 +1,235 @@
+//! Loading config files, from the fixtures in `tests/fixtures/config`
+use aws_sdk_s3::types::ServerSideEncryption;
+use rust_test_s3_upload::client::{DEFAULT_MULTIPART_THRESHOLD, DEFAULT_PART_SIZE};
+use rust_test_s3_upload::config::{
+    ConfigError, CredentialSource, S3Configuration, DEFAULT_PROFILE,
+};
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/config")
+        .join(name)
+}
+
+fn load(name: &str) -> Result<S3Configuration, ConfigError> {
+    S3Configuration::load(&fixture(name), DEFAULT_PROFILE)
+}
+
+#[test]
+fn full_config() {
+    let configuration = load("full.toml").expect("full.toml should load");
+
+    assert_eq!(configuration.backup_s3_bucket, "backups.example.com");
+    assert_eq!(configuration.backup_s3_region, "ap-southeast-2");
+    assert_eq!(
+        configuration.backup_s3_prefix.as_deref(),
+        Some("hosts/web1")
+    );
+    assert_eq!(
+        configuration.backup_s3_endpoint.as_deref(),
+        Some("https://minio.example.com:9000")
+    );
+    // set explicitly, so the endpoint doesn't turn it on
+    assert!(!configuration.force_path_style());
+    assert_eq!(
+        configuration.server_side_encryption().unwrap(),
+        Some(ServerSideEncryption::AwsKms)
+    );
+    let metadata = configuration.backup_s3_metadata.as_ref().unwrap();
+    assert_eq!(metadata.get("team").map(String::as_str), Some("dba"));
+    assert_eq!(configuration.backup_s3_limit_rate, Some(10 << 20));
+    // sizes are in MiB, like on the command line
+    assert_eq!(configuration.multipart_threshold(), 128 << 20);
+    assert_eq!(configuration.part_size(), 32 << 20);
+
+    let timeouts = configuration.timeout_config();
+    assert_eq!(timeouts.connect_timeout(), Some(Duration::from_secs(5)));
+    assert_eq!(timeouts.read_timeout(), Some(Duration::from_secs(30)));
+    assert_eq!(timeouts.operation_timeout(), Some(Duration::from_secs(120)));
+    assert_eq!(
+        configuration.transfer_timeout_config().read_timeout(),
+        Some(Duration::from_secs(600))
+    );
+    assert_eq!(configuration.retry_config().max_attempts(), 5);
+    let retry = configuration.retry_policy();
+    assert_eq!(retry.max_attempts, 4);
+    assert_eq!(retry.max_elapsed, Some(Duration::from_secs(900)));
+    assert_eq!(
+        configuration.backup_s3_role_arn.as_deref(),
+        Some("arn:aws:iam::123456789012:role/backups")
+    );
+    assert_eq!(
+        configuration.backup_s3_external_id.as_deref(),
+        Some("example")
+    );
+    assert_eq!(
+        configuration.backup_s3_session_name.as_deref(),
+        Some("web1")
+    );
+}
+
+#[test]
+fn minimal_config() {
+    let configuration = load("minimal.toml").expect("minimal.toml should load");
+
+    assert_eq!(configuration.backup_s3_bucket, "backups");
+    assert_eq!(configuration.backup_s3_endpoint, None);
+    assert!(!configuration.force_path_style());
+    assert!(!configuration.use_accelerate());
+    assert_eq!(configuration.server_side_encryption().unwrap(), None);
+    assert!(configuration.customer_key().unwrap().is_none());
+    assert_eq!(configuration.retry_config().max_attempts(), 3);
+    assert_eq!(configuration.timeout_config().operation_timeout(), None);
+    assert_eq!(
+        configuration.multipart_threshold(),
+        DEFAULT_MULTIPART_THRESHOLD
+    );
+    assert_eq!(configuration.part_size(), DEFAULT_PART_SIZE);
+}
+
+#[test]
+fn unknown_field_is_an_error() {
+    match load("unknown_field.toml") {
+        Err(ConfigError::Parse(message)) => {
+            assert!(message.contains("backup_s3_endpont"), "{}", message)
+        }
+        Err(error) => panic!("expected a parse error, got {}", error),
+        Ok(_) => panic!("a misspelled field should fail to load"),
+    }
+}
+
+#[test]
+fn endpoint_needs_a_scheme() {
+    match load("invalid_endpoint.toml") {
+        Err(ConfigError::InvalidEndpoint(endpoint, _)) => assert_eq!(endpoint, "localhost:9000"),
+        Err(error) => panic!("expected an invalid endpoint, got {}", error),
+        Ok(_) => panic!("an endpoint without a scheme should fail to load"),
+    }
+}
+
+#[test]
+fn proxy_and_skipping_verification() {
+    match load("insecure_proxy.toml") {
+        Err(ConfigError::InvalidProxy(message)) => {
+            assert!(
+                message.contains("backup_s3_insecure_skip_verify"),
+                "{}",
+                message
+            )
+        }
+        Err(error) => panic!("expected an invalid proxy, got {}", error),
+        Ok(_) => panic!("a proxy can't be used without verifying certificates"),
+    }
+}
+
+#[test]
+fn region_is_required() {
+    assert!(matches!(
+        load("missing_region.toml"),
+        Err(ConfigError::MissingField("backup_s3_region"))
+    ));
+}
+
+#[test]
+fn profiles() {
+    let path = fixture("profiles.toml");
+
+    let default = S3Configuration::load(&path, DEFAULT_PROFILE).unwrap();
+    assert_eq!(default.backup_s3_endpoint, None);
+    let minio = S3Configuration::load(&path, "minio").unwrap();
+    assert!(minio.force_path_style());
+
+    assert!(matches!(
+        S3Configuration::load(&path, "broken"),
+        Err(ConfigError::MissingField("backup_s3_bucket"))
+    ));
+    match S3Configuration::load(&path, "staging") {
+        Err(ConfigError::ProfileNotFound(profile, mut available)) => {
+            available.sort();
+            assert_eq!(profile, "staging");
+            assert_eq!(available, ["broken", DEFAULT_PROFILE, "minio"]);
+        }
+        Err(error) => panic!("expected a missing profile, got {}", error),
+        Ok(_) => panic!("there's no staging profile"),
+    }
+}
+
+#[test]
+fn default_profile_twice() {
+    match load("default_profile_twice.toml") {
+        Err(ConfigError::Parse(message)) => {
+            assert!(message.contains("[profiles.default]"), "{}", message)
+        }
+        Err(error) => panic!("expected a parse error, got {}", error),
+        Ok(_) => panic!("two default profiles should fail to load"),
+    }
+}
+
+#[test]
+fn from_reader_matches_load() {
+    let file = std::fs::File::open(fixture("full.toml")).unwrap();
+    let read = S3Configuration::from_reader(file, DEFAULT_PROFILE).unwrap();
+    let loaded = load("full.toml").unwrap();
+    assert_eq!(read.backup_s3_bucket, loaded.backup_s3_bucket);
+    assert_eq!(read.backup_s3_metadata, loaded.backup_s3_metadata);
+}
+
+#[test]
+fn missing_file() {
+    assert!(matches!(
+        load("not_there.toml"),
+        Err(ConfigError::FileOpen(..))
+    ));
+}
+
+/// The only test that touches the environment, so the others can't see it half set
+#[test]
+fn environment_overrides_credentials() {
+    let configuration = load("full.toml").unwrap();
+
+    std::env::remove_var("AWS_ACCESS_KEY_ID");
+    std::env::remove_var("AWS_SECRET_ACCESS_KEY");
+    std::env::remove_var("AWS_SESSION_TOKEN");
+    let (credentials, source) = configuration.credentials().unwrap().unwrap();
+    assert_eq!(credentials.access_key_id(), "AKIAEXAMPLE");
+    assert_eq!(credentials.session_token(), None);
+    assert_eq!(source, CredentialSource::ConfigFile);
+
+    std::env::set_var("AWS_ACCESS_KEY_ID", "AKIAFROMENV");
+    std::env::set_var("AWS_SECRET_ACCESS_KEY", "secretfromenv");
+    std::env::set_var("AWS_SESSION_TOKEN", "token");
+    let (credentials, source) = configuration.credentials().unwrap().unwrap();
+    assert_eq!(credentials.access_key_id(), "AKIAFROMENV");
+    assert_eq!(credentials.secret_access_key(), "secretfromenv");
+    assert_eq!(credentials.session_token(), Some("token"));
+    assert_eq!(source, CredentialSource::Environment);
+
+    // half a pair from the environment isn't mixed with the config file's keys, or its token
+    std::env::remove_var("AWS_SECRET_ACCESS_KEY");
+    let (credentials, source) = configuration.credentials().unwrap().unwrap();
+    assert_eq!(credentials.access_key_id(), "AKIAEXAMPLE");
+    assert_eq!(credentials.secret_access_key(), "secretexample");
+    assert_eq!(credentials.session_token(), None);
+    assert_eq!(source, CredentialSource::ConfigFile);
+    std::env::remove_var("AWS_ACCESS_KEY_ID");
+    std::env::set_var("AWS_SECRET_ACCESS_KEY", "secretfromenv");
+    let (credentials, source) = configuration.credentials().unwrap().unwrap();
+    assert_eq!(credentials.access_key_id(), "AKIAEXAMPLE");
+    assert_eq!(credentials.secret_access_key(), "secretexample");
+    assert_eq!(source, CredentialSource::ConfigFile);
+
+    // and without any there it's still half a pair
+    let minimal = load("minimal.toml").unwrap();
+    std::env::set_var("AWS_ACCESS_KEY_ID", "AKIAFROMENV");
+    std::env::remove_var("AWS_SECRET_ACCESS_KEY");
+    assert!(matches!(
+        minimal.credentials(),
+        Err(ConfigError::Credentials(_))
+    ));
+
+    std::env::remove_var("AWS_ACCESS_KEY_ID");
+    std::env::remove_var("AWS_SESSION_TOKEN");
+    assert!(minimal.credentials().unwrap().is_none());
+}
@@ -0,0 +1,652 @@
+//! Integration tests against a MinIO container, for the endpoint and path-style handling in
+//! `get_client` and the object operations built on it
+//!
+//! They need docker, so they're ignored by default, run them with
+//!
+//! ```shell
+//! cargo test --test minio -- --ignored
+//! ```
+//!
+//! Each test starts its own container and bucket, with a name no other test or run will use, and
+//! deletes the bucket before it finishes.
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::ChecksumAlgorithm;
+use rust_test_s3_upload::checksum::ContentMd5;
+use rust_test_s3_upload::client::{DownloadOptions, UploadOptions, MIN_PART_SIZE};
+use rust_test_s3_upload::filter::PathFilter;
+use rust_test_s3_upload::metadata::{parse_mtime, MTIME_METADATA};
+use rust_test_s3_upload::store::ObjectStore;
+use rust_test_s3_upload::sync::{plan_restore, Compare};
+use rust_test_s3_upload::walk::Links;
+use rust_test_s3_upload::{exit, S3Client, S3Configuration, S3Error, DEFAULT_PROFILE};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use testcontainers::runners::AsyncRunner;
+use testcontainers::ContainerAsync;
+use testcontainers_modules::minio::MinIO;
+use tokio::task::JoinSet;
+
+/// The port MinIO serves S3 on inside the container
+const MINIO_PORT: u16 = 9000;
+
+/// More than one page of ListObjectsV2, which returns at most 1000 keys
+const MANY_KEYS: usize = 1005;
+
+/// A name nothing else has used, lowercase and short enough for a bucket
+fn unique_name(prefix: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_nanos())
+        .unwrap_or_default();
+    format!(
+        "{}-{}-{}-{}",
+        prefix,
+        std::process::id(),
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// A running MinIO with a bucket of its own, and a client for it
+struct TestBucket {
+    client: S3Client,
+    config: PathBuf,
+    // stops the container when the test is done with it
+    _container: ContainerAsync<MinIO>,
+}
+
+impl TestBucket {
+    /// Starts MinIO and creates the bucket, through a config file like a user would write
+    async fn start() -> Self {
+        let container = MinIO::default()
+            .start()
+            .await
+            .expect("failed to start the MinIO container, is docker running?");
+        let host = container.get_host().await.expect("container host");
+        let port = container
+            .get_host_port_ipv4(MINIO_PORT)
+            .await
+            .expect("container port");
+
+        let bucket = unique_name("s3upload-test");
+        let config = std::env::temp_dir().join(format!("{}.toml", bucket));
+        // path style isn't set, an endpoint should turn it on without being asked
+        std::fs::write(
+            &config,
+            format!(
+                r#"
+backup_s3_bucket = "{bucket}"
+backup_s3_region = "us-east-1"
+backup_s3_endpoint = "http://{host}:{port}"
+backup_s3_access_key_id = "minioadmin"
+backup_s3_secret_access_key = "minioadmin"
+"#
+            ),
+        )
+        .expect("failed to write the test config");
+
+        let configuration = S3Configuration::load(&config, DEFAULT_PROFILE).expect("test config");
+        assert!(configuration.force_path_style());
+        let client = S3Client::new(&configuration).await.expect("client");
+        assert!(client.create_bucket().await.expect("create bucket"));
+
+        Self {
+            client,
+            config,
+            _container: container,
+        }
+    }
+
+    /// Empties and deletes the bucket, and removes the config file
+    async fn cleanup(self) {
+        let keys: Vec<String> = self
+            .client
+            .list_all("")
+            .await
+            .expect("list for cleanup")
+            .iter()
+            .filter_map(|object| object.key().map(str::to_string))
+            .collect();
+        for result in self.client.delete_objects(&keys).await.expect("cleanup") {
+            result.expect("delete for cleanup");
+        }
+        self.client.delete_bucket().await.expect("delete bucket");
+        let _ = std::fs::remove_file(&self.config);
+    }
+}
+
+/// Writes `body` to a file of its own, for the uploads that read from disk
+fn local_file(body: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(unique_name("s3upload-test-file"));
+    std::fs::write(&path, body).expect("failed to write the test file");
+    path
+}
+
+#[tokio::test]
+#[ignore = "needs docker, run with --ignored"]
+async fn upload_then_head_has_size_and_etag() {
+    let bucket = TestBucket::start().await;
+    let body = b"select * from backups;\n".repeat(100);
+    let path = local_file(&body);
+
+    let uploaded = bucket
+        .client
+        .upload_as(&path, "hosts/web1/db.sql", &UploadOptions::default())
+        .await
+        .expect("upload");
+    let _ = std::fs::remove_file(&path);
+    // a single PUT's etag is the MD5 of the body
+    let md5 = ContentMd5::of(&body).hex();
+    assert_eq!(uploaded.size, body.len() as u64);
+    assert_eq!(uploaded.etag, md5);
+
+    let info = bucket.client.head("hosts/web1/db.sql").await.expect("head");
+    assert_eq!(info.size, body.len() as u64);
+    assert_eq!(info.etag, md5);
+
+    let mut downloaded = Vec::new();
+    bucket
+        .client
+        .download_to("hosts/web1/db.sql", &mut downloaded)
+        .await
+        .expect("download");
+    assert_eq!(downloaded, body);
+
+    bucket.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs docker, run with --ignored"]
+async fn list_goes_through_every_page() {
+    let bucket = TestBucket::start().await;
+
+    let mut puts = JoinSet::new();
+    for index in 0..MANY_KEYS {
+        let client = bucket.client.clone();
+        puts.spawn(async move {
+            let key = format!("many/{:05}.txt", index);
+            client.put(&key, index.to_string().into_bytes()).await
+        });
+        // keep a few dozen in flight rather than all of them
+        if puts.len() >= 32 {
+            puts.join_next()
+                .await
+                .expect("task")
+                .expect("join")
+                .expect("put");
+        }
+    }
+    while let Some(put) = puts.join_next().await {
+        put.expect("join").expect("put");
+    }
+    // something outside the prefix, which shouldn't be listed
+    bucket
+        .client
+        .put("other.txt", b"other".to_vec())
+        .await
+        .expect("put");
+
+    let mut pages = bucket.client.list_pages("many/", None);
+    let mut sizes = Vec::new();
+    let mut keys = Vec::new();
+    while let Some(page) = pages.next_page().await.expect("list page") {
+        sizes.push(page.objects.len());
+        keys.extend(
+            page.objects
+                .iter()
+                .filter_map(|object| object.key().map(str::to_string)),
+        );
+    }
+    assert_eq!(sizes, [1000, MANY_KEYS - 1000]);
+    let expected: Vec<String> = (0..MANY_KEYS)
+        .map(|index| format!("many/{:05}.txt", index))
+        .collect();
+    assert_eq!(keys, expected);
+
+    let all = bucket.client.list_all("many/").await.expect("list all");
+    assert_eq!(all.len(), MANY_KEYS);
+    // a limit part way into the second page stops there
+    let mut limited = bucket.client.list_pages("many/", Some(1002));
+    let mut count = 0;
+    while let Some(page) = limited.next_page().await.expect("list page") {
+        count += page.objects.len();
+    }
+    assert_eq!(count, 1002);
+
+    bucket.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs docker, run with --ignored"]
+async fn delete_removes_the_object() {
+    let bucket = TestBucket::start().await;
+    bucket
+        .client
+        .put("db.sql", b"select 1;".to_vec())
+        .await
+        .expect("put");
+    bucket
+        .client
+        .put("notes.txt", b"hi".to_vec())
+        .await
+        .expect("put");
+
+    let deleted = bucket.client.delete("db.sql").await.expect("delete");
+    assert_eq!(deleted.key, "db.sql");
+    assert!(matches!(
+        bucket.client.head("db.sql").await,
+        Err(S3Error::NotFound { .. })
+    ));
+    // only what was asked for is gone
+    bucket.client.head("notes.txt").await.expect("head");
+
+    bucket.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs docker, run with --ignored"]
+async fn missing_key_is_not_found() {
+    let bucket = TestBucket::start().await;
+
+    let error = bucket
+        .client
+        .head("not/there.txt")
+        .await
+        .expect_err("head of a missing key");
+    assert!(matches!(error, S3Error::NotFound { .. }), "{:?}", error);
+    assert_eq!(exit::code(&error), exit::NOT_FOUND);
+
+    let mut body = Vec::new();
+    let error = bucket
+        .client
+        .download_to("not/there.txt", &mut body)
+        .await
+        .expect_err("download of a missing key");
+    assert!(matches!(error, S3Error::NotFound { .. }), "{:?}", error);
+    assert!(body.is_empty());
+
+    // like S3, deleting a key that isn't there works
+    bucket
+        .client
+        .delete("not/there.txt")
+        .await
+        .expect("delete of a missing key");
+
+    bucket.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs docker, run with --ignored"]
+async fn missing_bucket_is_not_found() {
+    let bucket = TestBucket::start().await;
+    let missing = bucket
+        .client
+        .clone()
+        .with_bucket(unique_name("s3upload-missing"));
+
+    let error = missing
+        .head("db.sql")
+        .await
+        .expect_err("head in a missing bucket");
+    assert_eq!(exit::code(&error), exit::NOT_FOUND, "{:?}", error);
+
+    bucket.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs docker, run with --ignored"]
+async fn awkward_keys_round_trip() {
+    let bucket = TestBucket::start().await;
+    let keys = [
+        "odd/weekly report (final).pdf",
+        "odd/1+1=2.txt",
+        "odd/100%.txt",
+        "odd/%2B already escaped.txt",
+        "odd/📦 naïve café.txt",
+        "odd/folder with spaces/db.sql",
+    ];
+    for key in keys {
+        bucket
+            .client
+            .put(key, key.as_bytes().to_vec())
+            .await
+            .expect("put");
+    }
+    // S3 takes a newline in a key, so one can turn up even though this client won't upload it
+    let newline = "odd/line\nbreak.txt";
+    assert!(matches!(
+        bucket.client.put(newline, b"newline".to_vec()).await,
+        Err(S3Error::InvalidKey { .. })
+    ));
+    bucket
+        .client
+        .client()
+        .put_object()
+        .bucket(bucket.client.bucket())
+        .key(newline)
+        .body(ByteStream::from_static(b"newline"))
+        .send()
+        .await
+        .expect("put with the SDK");
+
+    let mut expected: Vec<&str> = keys.iter().copied().chain([newline]).collect();
+    expected.sort();
+    let listed: Vec<String> = bucket
+        .client
+        .list_all("odd/")
+        .await
+        .expect("list")
+        .iter()
+        .filter_map(|object| object.key().map(str::to_string))
+        .collect();
+    assert_eq!(listed, expected);
+
+    let mut pages = bucket.client.list_pages("odd/", None).with_delimiter("/");
+    let page = pages.next_page().await.expect("list").expect("a page");
+    assert_eq!(page.prefixes, ["odd/folder with spaces/"]);
+
+    for key in expected.iter().copied() {
+        let info = bucket.client.head(key).await.expect("head");
+        let body = bucket.client.get(key).await.expect("get");
+        assert_eq!(info.size, body.len() as u64);
+        if key != newline {
+            assert_eq!(body, key.as_bytes());
+        }
+    }
+    let keys: Vec<String> = expected.iter().map(|key| key.to_string()).collect();
+    for result in bucket.client.delete_objects(&keys).await.expect("delete") {
+        result.expect("delete");
+    }
+    assert!(bucket
+        .client
+        .list_all("odd/")
+        .await
+        .expect("list")
+        .is_empty());
+
+    bucket.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs docker, run with --ignored"]
+async fn empty_file_is_a_single_put() {
+    let bucket = TestBucket::start().await;
+    let path = local_file(b"");
+
+    let uploaded = bucket
+        .client
+        .upload_as(&path, "markers/done", &UploadOptions::default())
+        .await
+        .expect("upload");
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(uploaded.size, 0);
+    assert_eq!(uploaded.etag, ContentMd5::of(b"").hex());
+
+    let info = bucket.client.head("markers/done").await.expect("head");
+    assert_eq!(info.size, 0);
+    assert!(bucket
+        .client
+        .get("markers/done")
+        .await
+        .expect("get")
+        .is_empty());
+
+    bucket.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs docker, run with --ignored"]
+async fn file_at_the_threshold_is_multipart() {
+    let bucket = TestBucket::start().await;
+    let options = UploadOptions {
+        multipart_threshold: MIN_PART_SIZE,
+        part_size: MIN_PART_SIZE,
+        resume: false,
+        ..UploadOptions::default()
+    };
+    let body = vec![7u8; MIN_PART_SIZE as usize];
+    let path = local_file(&body);
+    let uploaded = bucket
+        .client
+        .upload_as(&path, "exact.bin", &options)
+        .await
+        .expect("upload");
+    // a byte less goes up in one PUT
+    std::fs::write(&path, &body[1..]).expect("failed to write the test file");
+    let smaller = bucket
+        .client
+        .upload_as(&path, "smaller.bin", &options)
+        .await
+        .expect("upload");
+    let _ = std::fs::remove_file(&path);
+
+    // a multipart etag is the MD5 of the parts' MD5s, with the number of parts after it
+    assert!(uploaded.etag.ends_with("-1"), "{}", uploaded.etag);
+    let layout = uploaded.multipart.expect("a multipart upload");
+    assert_eq!((layout.parts, layout.last_part_size), (1, MIN_PART_SIZE));
+    assert!(smaller.multipart.is_none());
+    assert_eq!(uploaded.size, MIN_PART_SIZE);
+    assert_eq!(smaller.etag, ContentMd5::of(&body[1..]).hex());
+
+    bucket.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs docker, run with --ignored"]
+async fn downloads_are_verified() {
+    let bucket = TestBucket::start().await;
+    let body = vec![3u8; MIN_PART_SIZE as usize];
+    let path = local_file(&body);
+    let checksummed = UploadOptions {
+        checksum_algorithm: Some(ChecksumAlgorithm::Sha256),
+        ..UploadOptions::default()
+    };
+    bucket
+        .client
+        .upload_as(&path, "checksummed.bin", &checksummed)
+        .await
+        .expect("upload");
+    let parts = UploadOptions {
+        multipart_threshold: MIN_PART_SIZE,
+        part_size: MIN_PART_SIZE,
+        resume: false,
+        ..UploadOptions::default()
+    };
+    bucket
+        .client
+        .upload_as(&path, "parts.bin", &parts)
+        .await
+        .expect("upload");
+    let _ = std::fs::remove_file(&path);
+
+    let dest = std::env::temp_dir().join(unique_name("s3upload-test-download"));
+    let options = DownloadOptions {
+        force: true,
+        ..DownloadOptions::default()
+    };
+    let download = bucket
+        .client
+        .download("checksummed.bin", &dest, &options)
+        .await
+        .expect("download");
+    assert_eq!(download.checksum_algorithm.as_deref(), Some("SHA256"));
+
+    // a multipart etag without a checksum has nothing to compare the body with
+    let _ = std::fs::remove_file(&dest);
+    match bucket.client.download("parts.bin", &dest, &options).await {
+        Err(S3Error::IntegrityFailure { .. }) => {}
+        other => panic!("expected an integrity failure, got {:?}", other),
+    }
+    assert!(!dest.exists());
+    let unverified = DownloadOptions {
+        verify: false,
+        ..options
+    };
+    bucket
+        .client
+        .download("parts.bin", &dest, &unverified)
+        .await
+        .expect("download without verifying");
+    assert_eq!(std::fs::read(&dest).expect("read the download"), body);
+    let _ = std::fs::remove_file(&dest);
+
+    bucket.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs docker, run with --ignored"]
+async fn interrupted_download_resumes() {
+    let bucket = TestBucket::start().await;
+    let body = b"0123456789abcdef".repeat(4096);
+    let path = local_file(&body);
+    let uploaded = bucket
+        .client
+        .upload_as(&path, "archive.tar", &UploadOptions::default())
+        .await
+        .expect("upload");
+    let _ = std::fs::remove_file(&path);
+
+    // what an interrupted download leaves behind, the first half of the object and a state file
+    // saying which object it was
+    let dest = std::env::temp_dir().join(unique_name("s3upload-test-resume"));
+    let partial = PathBuf::from(format!("{}.partial", dest.display()));
+    let state_path = PathBuf::from(format!("{}.s3upload.json", partial.display()));
+    let state = |etag: &str| {
+        serde_json::json!({
+            "bucket": bucket.client.bucket(),
+            "key": "archive.tar",
+            "etag": etag,
+            "size": body.len(),
+        })
+        .to_string()
+    };
+    std::fs::write(&partial, &body[..body.len() / 2]).expect("write the partial file");
+    std::fs::write(&state_path, state(&uploaded.etag)).expect("write the state");
+    let download = bucket
+        .client
+        .download("archive.tar", &dest, &DownloadOptions::default())
+        .await
+        .expect("resumed download");
+    assert_eq!(download.size, body.len() as u64);
+    assert_eq!(std::fs::read(&dest).expect("read the download"), body);
+    assert!(!partial.exists() && !state_path.exists());
+    let _ = std::fs::remove_file(&dest);
+
+    // a partial download of a different version of the object is thrown away
+    std::fs::write(&partial, b"not the same object").expect("write the partial file");
+    std::fs::write(&state_path, state("0123456789abcdef0123456789abcdef"))
+        .expect("write the state");
+    bucket
+        .client
+        .download("archive.tar", &dest, &DownloadOptions::default())
+        .await
+        .expect("restarted download");
+    assert_eq!(std::fs::read(&dest).expect("read the download"), body);
+    let _ = std::fs::remove_file(&dest);
+
+    bucket.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs docker, run with --ignored"]
+async fn killed_download_leaves_the_destination_alone() {
+    let bucket = TestBucket::start().await;
+    let body = vec![9u8; 4 << 20];
+    let path = local_file(&body);
+    bucket
+        .client
+        .upload_as(&path, "backups/db.dump", &UploadOptions::default())
+        .await
+        .expect("upload");
+    let _ = std::fs::remove_file(&path);
+
+    let dir = std::env::temp_dir().join(unique_name("s3upload-test-atomic"));
+    std::fs::create_dir_all(&dir).expect("create the destination directory");
+    let dest = dir.join("db.dump");
+    std::fs::write(&dest, b"last night's backup").expect("write the old backup");
+
+    // slow enough that it's still streaming when it's killed
+    let client = bucket.client.clone().with_rate_limit(Some(256 << 10));
+    let options = DownloadOptions {
+        force: true,
+        resume: false,
+        ..DownloadOptions::default()
+    };
+    let task = tokio::spawn({
+        let dest = dest.clone();
+        async move { client.download("backups/db.dump", &dest, &options).await }
+    });
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    task.abort();
+    assert!(task
+        .await
+        .expect_err("the download should be killed")
+        .is_cancelled());
+    assert_eq!(
+        std::fs::read(&dest).expect("read the old backup"),
+        b"last night's backup"
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+
+    bucket.cleanup().await;
+}
+
+#[tokio::test]
+#[ignore = "needs docker, run with --ignored"]
+async fn modification_times_round_trip() {
+    let bucket = TestBucket::start().await;
+    let modified = UNIX_EPOCH + Duration::new(1_600_000_000, 250_000_000);
+    let path = local_file(b"yesterday's dump\n");
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .and_then(|file| file.set_modified(modified))
+        .expect("set the modification time");
+    bucket
+        .client
+        .upload_as(&path, "dumps/db.sql", &UploadOptions::default())
+        .await
+        .expect("upload");
+    let _ = std::fs::remove_file(&path);
+
+    let info = bucket.client.head("dumps/db.sql").await.expect("head");
+    assert_eq!(
+        info.metadata
+            .get(MTIME_METADATA)
+            .and_then(|value| parse_mtime(value)),
+        Some(modified)
+    );
+    assert_eq!(info.modified(), Some(modified));
+
+    let seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let root = std::env::temp_dir().join(unique_name("s3upload-test-mtime"));
+    let dest = root.join("db.sql");
+    bucket
+        .client
+        .download("dumps/db.sql", &dest, &DownloadOptions::default())
+        .await
+        .expect("download");
+    let restored = std::fs::metadata(&dest)
+        .and_then(|metadata| metadata.modified())
+        .expect("modification time of the download");
+    assert_eq!(seconds(restored), seconds(modified));
+
+    // the object was uploaded long after that, so only the recorded time says it's unchanged
+    let plan = plan_restore(
+        &bucket.client,
+        "dumps",
+        &root,
+        &PathFilter::default(),
+        Compare::Mtime,
+        Links::Follow,
+    )
+    .await
+    .expect("plan the restore");
+    assert!(plan.downloads.is_empty(), "{:?}", plan.downloads);
+    assert_eq!(plan.unchanged.len(), 1);
+    let _ = std::fs::remove_dir_all(&root);
+
+    bucket.cleanup().await;
+}
@@ -0,0 +1,33 @@
+//! Loading a CA bundle from the config and building a client that trusts it, from the fixtures in
+//! `tests/fixtures/config` and `tests/fixtures/tls`
+//!
+//! Building the client reads the AWS credential variables, so these are kept out of the config
+//! tests, which set them.
+use rust_test_s3_upload::config::{ConfigError, S3Configuration, DEFAULT_PROFILE};
+use rust_test_s3_upload::S3Client;
+use std::path::{Path, PathBuf};
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/config")
+        .join(name)
+}
+
+#[tokio::test]
+async fn self_signed_ca_bundle() {
+    let configuration = S3Configuration::load(&fixture("ca_bundle.toml"), DEFAULT_PROFILE)
+        .expect("ca_bundle.toml should load");
+
+    assert!(S3Client::new(&configuration).await.is_ok());
+}
+
+#[test]
+fn garbage_ca_bundle() {
+    match S3Configuration::load(&fixture("garbage_ca_bundle.toml"), DEFAULT_PROFILE) {
+        Err(ConfigError::InvalidCaBundle(path, _)) => {
+            assert_eq!(path, Path::new("tests/fixtures/tls/garbage.pem"))
+        }
+        Err(error) => panic!("expected an invalid CA bundle, got {}", error),
+        Ok(_) => panic!("a bundle that isn't PEM should fail to load"),
+    }
+}